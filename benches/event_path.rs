@@ -0,0 +1,110 @@
+// Latency of the event path: how long the dwell state machine takes to chew
+// through a sample, how long it takes from a synthetic motion or button event
+// until the tick code notices it, and how long issuing a click through XTest
+// takes.
+//
+// The X benchmarks talk to a real X server and press real buttons, so run
+// them against a throwaway one:
+//
+//     xvfb-run cargo bench
+
+use criterion::{criterion_group, Criterion};
+use rtmouse_rs::config::DwellConfig;
+use rtmouse_rs::dwell::{DwellStateMachine, Sample};
+use rtmouse_rs::*;
+use std::time::{Duration, Instant};
+use x11::{xlib, xtest};
+
+fn fake_motion(st: &StateMainLoop, x: i32, y: i32) {
+    unsafe {
+        xtest::XTestFakeMotionEvent(st.st_x11.display, -1, x, y, 0);
+        xlib::XFlush(st.st_x11.display);
+    }
+}
+
+fn bench_state_machine(c: &mut Criterion) {
+    let mut sm = DwellStateMachine::new(DwellConfig::default());
+    sm.set_active(true);
+
+    let mut now = Instant::now();
+    let mut i = 0;
+    c.bench_function("state machine update", |b| {
+        b.iter(|| {
+            // move for a while, then rest long enough to click and release
+            i = (i + 1) % 40;
+            now += Duration::from_millis(TIMER_INTERVAL_MS as u64);
+            let x = if i < 10 { i * 20 } else { 200 };
+            sm.update(Sample {
+                x,
+                y: 100,
+                buttons: 0,
+                now,
+                unfocused: false,
+                grabbed: false,
+            })
+        })
+    });
+}
+
+fn bench_motion(c: &mut Criterion) {
+    let mut st = initialize_main_loop_state(DwellConfig::default());
+    initialize_x11_state(&mut st.st_x11, &st.config);
+    st.dwell.set_active(true);
+
+    let mut x = 100;
+    c.bench_function("motion event to state machine", |b| {
+        b.iter(|| {
+            x = if x == 100 { 200 } else { 100 };
+            fake_motion(&st, x, 100);
+            loop {
+                let Pointer { x, y, .. } = query_pointer(&st.st_x11);
+                st.dwell.update(Sample {
+                    x,
+                    y,
+                    buttons: 0,
+                    now: Instant::now(),
+                    unfocused: false,
+                    grabbed: false,
+                });
+                if st.dwell.is_moving() {
+                    break;
+                }
+            }
+        })
+    });
+}
+
+fn bench_button(c: &mut Criterion) {
+    let mut st = initialize_main_loop_state(DwellConfig::default());
+    initialize_x11_state(&mut st.st_x11, &st.config);
+    // There's no "button event to inhibit mask" case. Presses made through
+    // XTest, like these, never reach the inhibit mask.
+
+    c.bench_function("issue click", |b| {
+        b.iter(|| {
+            let btn = get_primary_button_code(&st.st_x11);
+            send_button_event(&st.st_x11, btn, true, 0);
+            send_button_event(&st.st_x11, btn, false, 0);
+            unsafe {
+                xlib::XSync(st.st_x11.display, 0);
+            }
+        })
+    });
+}
+
+criterion_group!(pure, bench_state_machine);
+criterion_group!(x_server, bench_motion, bench_button);
+
+fn main() {
+    pure();
+
+    if std::env::var_os("DISPLAY").is_some() {
+        x_server();
+    } else {
+        eprintln!(
+            "event_path: DISPLAY is not set, skipping X benchmarks. try `xvfb-run cargo bench`"
+        );
+    }
+
+    Criterion::default().configure_from_args().final_summary();
+}
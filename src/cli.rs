@@ -0,0 +1,787 @@
+// Command line parsing. There's little enough of it that doing it by hand is
+// simpler than a dependency. FLAGS and COMMANDS describe everything that's
+// accepted, and both the usage text and the shell completions are generated
+// from them, so a new flag only has to be added there and in parse_args.
+
+use crate::config::Theme;
+use crate::control::Request;
+use crate::dwell::ClickType;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Run,
+    Help,
+    InitConfig { force: bool },
+    Setup { force: bool },
+    PrintConfig,
+    Completions(Shell),
+    Diagnose,
+    Doctor,
+    Status,
+    Send(Request),
+    // Only the events newer than since
+    Export { since: Option<Duration> },
+    Heatmap { since: Option<Duration> },
+    ExportProfile { name: String },
+    ImportProfile { path: PathBuf, force: bool },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub config_path: Option<PathBuf>,
+    pub runtime_dir: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub explain: bool,
+    pub supervise: bool,
+    pub json: bool,
+    pub action: Action,
+}
+
+pub struct Flag {
+    pub long: &'static str,
+    pub short: Option<char>,
+    // Name of the flag's argument, for flags that take one
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+pub struct Subcommand {
+    pub name: &'static str,
+    // Name of the positional argument, and the values it can take
+    pub arg: Option<(&'static str, &'static [&'static str])>,
+    pub help: &'static str,
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        long: "config",
+        short: None,
+        value: Some("PATH"),
+        help: "read the config from PATH instead of $XDG_CONFIG_HOME/rtmouse/config.toml",
+    },
+    Flag {
+        long: "runtime-dir",
+        short: None,
+        value: Some("DIR"),
+        help: "keep the socket, status, pid and trace in DIR instead of $XDG_RUNTIME_DIR/rtmouse",
+    },
+    Flag {
+        long: "profile",
+        short: None,
+        value: Some("NAME"),
+        help: "run with the config's [profile.NAME] options over the others",
+    },
+    Flag {
+        long: "init-config",
+        short: None,
+        value: None,
+        help: "write a commented default config and exit",
+    },
+    Flag {
+        long: "setup",
+        short: None,
+        value: None,
+        help: "ask a few questions and write a config from the answers",
+    },
+    Flag {
+        long: "force",
+        short: None,
+        value: None,
+        help:
+            "let --init-config or --setup overwrite an existing file, or profile import a profile",
+    },
+    Flag {
+        long: "print-config",
+        short: None,
+        value: None,
+        help: "print the configuration rtmouse would run with and exit",
+    },
+    Flag {
+        long: "supervise",
+        short: None,
+        value: None,
+        help: "start rtmouse again if it crashes, waiting longer each time",
+    },
+    Flag {
+        long: "explain",
+        short: None,
+        value: None,
+        help: "log why each tick did or didn't click",
+    },
+    Flag {
+        long: "json",
+        short: None,
+        value: None,
+        help: "make status print JSON",
+    },
+    Flag {
+        long: "format",
+        short: None,
+        value: Some("FORMAT"),
+        help: "what export writes, only csv for now",
+    },
+    Flag {
+        long: "since",
+        short: None,
+        value: Some("AGE"),
+        help: "make export and heatmap skip events older than AGE, like 7d, 12h or 30m",
+    },
+    Flag {
+        long: "help",
+        short: Some('h'),
+        value: None,
+        help: "show this help",
+    },
+];
+
+pub const COMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "completions",
+        arg: Some(("SHELL", &["bash", "zsh", "fish"])),
+        help: "print a shell completion script",
+    },
+    Subcommand {
+        name: "diagnose",
+        arg: None,
+        help: "write rtmouse-diagnose.txt to attach to bug reports",
+    },
+    Subcommand {
+        name: "doctor",
+        arg: None,
+        help: "check the display, XInput, XTest, sound and runtime files",
+    },
+    Subcommand {
+        name: "status",
+        arg: None,
+        help: "show what the running rtmouse is doing",
+    },
+    Subcommand {
+        name: "export",
+        arg: None,
+        help: "print the history_log as CSV",
+    },
+    Subcommand {
+        name: "heatmap",
+        arg: None,
+        help: "draw the history_log as a PNG on stdout",
+    },
+    Subcommand {
+        name: "profile",
+        arg: Some(("ACTION", &["export", "import"])),
+        help: "export NAME prints a profile for another machine, import FILE adds one",
+    },
+    Subcommand {
+        name: "enable",
+        arg: None,
+        help: "turn dwell clicking on in the running rtmouse",
+    },
+    Subcommand {
+        name: "disable",
+        arg: None,
+        help: "turn dwell clicking off in the running rtmouse",
+    },
+    Subcommand {
+        name: "toggle",
+        arg: None,
+        help: "switch dwell clicking on or off in the running rtmouse",
+    },
+    Subcommand {
+        name: "click-type",
+        arg: Some(("TYPE", &["left", "right", "double", "drag", "gesture"])),
+        help: "make the running rtmouse's next dwell this kind of click",
+    },
+    Subcommand {
+        name: "theme",
+        arg: Some(("THEME", &["default", "high-contrast"])),
+        help: "switch the running rtmouse's overlays to another theme",
+    },
+    Subcommand {
+        name: "sound-theme",
+        arg: None,
+        help: "switch the running rtmouse to sound theme NAME, or none",
+    },
+    Subcommand {
+        name: "reset-clicks",
+        arg: None,
+        help: "start the running rtmouse's clicks_today count over",
+    },
+    Subcommand {
+        name: "macro",
+        arg: Some(("ACTION", &["record", "stop", "play"])),
+        help: "record NAME notes clicks until stop, play NAME replays them",
+    },
+];
+
+const BIN: &str = "rtmouse-rs";
+
+pub fn usage() -> String {
+    let mut rows = Vec::new();
+    for flag in FLAGS {
+        let mut name = match flag.short {
+            Some(c) => format!("-{}, --{}", c, flag.long),
+            None => format!("--{}", flag.long),
+        };
+        if let Some(value) = flag.value {
+            name = format!("{} {}", name, value);
+        }
+        rows.push((name, flag.help));
+    }
+    let flag_rows = rows.len();
+    for cmd in COMMANDS {
+        let name = match cmd.arg {
+            Some((_, values)) => format!("{} <{}>", cmd.name, values.join("|")),
+            None => cmd.name.to_string(),
+        };
+        rows.push((name, cmd.help));
+    }
+
+    let width = rows.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    let mut out = format!(
+        "usage: {} [options]\n       {} <command> [args]\n\noptions:\n",
+        BIN, BIN
+    );
+    for (i, (name, help)) in rows.iter().enumerate() {
+        if i == flag_rows {
+            out.push_str("\ncommands:\n");
+        }
+        out.push_str(&format!("  {:width$}  {}\n", name, help, width = width));
+    }
+    out
+}
+
+fn parse_shell(s: &str) -> Result<Shell, String> {
+    match s {
+        "bash" => Ok(Shell::Bash),
+        "zsh" => Ok(Shell::Zsh),
+        "fish" => Ok(Shell::Fish),
+        _ => Err(format!("unknown shell `{}`, expected bash, zsh or fish", s)),
+    }
+}
+
+// 7d, 12h, 30m
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let split = s.len() - s.chars().last().map_or(0, char::len_utf8);
+    let (n, unit) = s.split_at(split);
+    let unit_secs = match unit {
+        "d" => Some(86400),
+        "h" => Some(3600),
+        "m" => Some(60),
+        _ => None,
+    };
+    let secs = unit_secs.and_then(|u| n.parse::<u64>().ok()?.checked_mul(u));
+    secs.map(Duration::from_secs)
+        .ok_or_else(|| format!("--since needs an age like 7d, 12h or 30m, not `{}`", s))
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut config_path = None;
+    let mut runtime_dir = None;
+    let mut profile = None;
+    let mut init_config = false;
+    let mut setup = false;
+    let mut print_config = false;
+    let mut force = false;
+    let mut help = false;
+    let mut explain = false;
+    let mut supervise = false;
+    let mut json = false;
+    let mut format = None;
+    let mut since = None;
+    let mut command = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => return Err("--config needs a path".to_string()),
+            },
+            "--runtime-dir" => match args.next() {
+                Some(dir) => runtime_dir = Some(PathBuf::from(dir)),
+                None => return Err("--runtime-dir needs a directory".to_string()),
+            },
+            "--profile" => match args.next() {
+                Some(name) => profile = Some(name),
+                None => return Err("--profile needs a name".to_string()),
+            },
+            "--init-config" => init_config = true,
+            "--setup" => setup = true,
+            "--force" => force = true,
+            "--print-config" => print_config = true,
+            "--explain" => explain = true,
+            "--supervise" => supervise = true,
+            "--json" => json = true,
+            "--format" => match args.next() {
+                Some(f) if f == "csv" => format = Some(f),
+                Some(f) => return Err(format!("export can only write csv, not `{}`", f)),
+                None => return Err("--format needs a format".to_string()),
+            },
+            "--since" => match args.next() {
+                Some(age) => since = Some(parse_age(&age)?),
+                None => return Err("--since needs an age".to_string()),
+            },
+            "-h" | "--help" => help = true,
+            "completions" if command.is_none() => match args.next() {
+                Some(shell) => command = Some(Action::Completions(parse_shell(&shell)?)),
+                None => return Err("completions needs a shell: bash, zsh or fish".to_string()),
+            },
+            "diagnose" if command.is_none() => command = Some(Action::Diagnose),
+            "doctor" if command.is_none() => command = Some(Action::Doctor),
+            "status" if command.is_none() => command = Some(Action::Status),
+            "export" if command.is_none() => command = Some(Action::Export { since: None }),
+            "heatmap" if command.is_none() => command = Some(Action::Heatmap { since: None }),
+            "profile" if command.is_none() => {
+                let what = args.next().unwrap_or_default();
+                command = Some(match (what.as_str(), args.next()) {
+                    ("export", Some(name)) => Action::ExportProfile { name },
+                    ("import", Some(path)) => Action::ImportProfile {
+                        path: PathBuf::from(path),
+                        force: false,
+                    },
+                    ("export", None) => {
+                        return Err("profile export needs the profile's name".to_string())
+                    }
+                    ("import", None) => return Err("profile import needs a file".to_string()),
+                    _ => return Err(format!("profile needs export or import, not `{}`", what)),
+                });
+            }
+            "enable" if command.is_none() => command = Some(Action::Send(Request::Enable)),
+            "disable" if command.is_none() => command = Some(Action::Send(Request::Disable)),
+            "toggle" if command.is_none() => command = Some(Action::Send(Request::Toggle)),
+            "reset-clicks" if command.is_none() => {
+                command = Some(Action::Send(Request::ResetClicks))
+            }
+            "theme" if command.is_none() => {
+                let theme = args.next().unwrap_or_default();
+                match Theme::parse(&theme) {
+                    Some(theme) => command = Some(Action::Send(Request::Theme(theme))),
+                    None => {
+                        return Err(format!(
+                            "theme needs default or high-contrast, not `{}`",
+                            theme
+                        ))
+                    }
+                }
+            }
+            "sound-theme" if command.is_none() => match args.next() {
+                Some(name) => command = Some(Action::Send(Request::SoundTheme(name))),
+                None => return Err("sound-theme needs a theme's name, or none".to_string()),
+            },
+            "macro" if command.is_none() => {
+                let what = args.next().unwrap_or_default();
+                let name = if what == "stop" { None } else { args.next() };
+                let line = format!("macro {} {}", what, name.unwrap_or_default());
+                match Request::parse(&line) {
+                    Some(request) => command = Some(Action::Send(request)),
+                    None if what == "record" || what == "play" => {
+                        return Err(format!(
+                            "macro {} needs a name of letters, digits, - and _",
+                            what
+                        ))
+                    }
+                    None => {
+                        return Err(format!("macro needs record, stop or play, not `{}`", what))
+                    }
+                }
+            }
+            "click-type" if command.is_none() => {
+                let click = args.next().unwrap_or_default();
+                match ClickType::parse(&click) {
+                    Some(click) => command = Some(Action::Send(Request::ClickType(click))),
+                    None => {
+                        return Err(format!(
+                        "click-type needs one of left, right, double, drag or gesture, not `{}`",
+                        click
+                    ))
+                    }
+                }
+            }
+            _ => return Err(format!("unknown argument `{}`", arg)),
+        }
+    }
+
+    match &mut command {
+        Some(Action::ImportProfile { force: f, .. }) => *f = force,
+        _ if force && !init_config && !setup => {
+            return Err(
+                "--force only makes sense with --init-config, --setup or profile import"
+                    .to_string(),
+            )
+        }
+        _ => {}
+    }
+    if json && command != Some(Action::Status) {
+        return Err("--json only makes sense with status".to_string());
+    }
+    match command {
+        Some(Action::Export { .. }) => command = Some(Action::Export { since }),
+        Some(Action::Heatmap { .. }) if format.is_none() => {
+            command = Some(Action::Heatmap { since })
+        }
+        _ if format.is_some() => return Err("--format only makes sense with export".to_string()),
+        _ if since.is_some() => {
+            return Err("--since only makes sense with export or heatmap".to_string())
+        }
+        _ => {}
+    }
+    if [init_config, print_config, setup]
+        .iter()
+        .filter(|&&f| f)
+        .count()
+        > 1
+    {
+        return Err("--init-config, --print-config and --setup can't be used together".to_string());
+    }
+    if command.is_some() && (init_config || print_config || setup) {
+        return Err(
+            "commands can't be combined with --init-config, --print-config or --setup".to_string(),
+        );
+    }
+
+    let action = if help {
+        Action::Help
+    } else if let Some(command) = command {
+        command
+    } else if init_config {
+        Action::InitConfig { force }
+    } else if setup {
+        Action::Setup { force }
+    } else if print_config {
+        Action::PrintConfig
+    } else {
+        Action::Run
+    };
+
+    if supervise && action != Action::Run {
+        return Err("--supervise only makes sense when running rtmouse".to_string());
+    }
+
+    Ok(Options {
+        config_path,
+        runtime_dir,
+        profile,
+        explain,
+        supervise,
+        json,
+        action,
+    })
+}
+
+fn bash_completions() -> String {
+    let mut words: Vec<String> = Vec::new();
+    for flag in FLAGS {
+        words.push(format!("--{}", flag.long));
+        if let Some(c) = flag.short {
+            words.push(format!("-{}", c));
+        }
+    }
+    words.extend(COMMANDS.iter().map(|c| c.name.to_string()));
+
+    let mut cases = String::new();
+    for flag in FLAGS.iter().filter(|f| f.value.is_some()) {
+        cases.push_str(&format!(
+            "        --{}) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;\n",
+            flag.long
+        ));
+    }
+    for cmd in COMMANDS {
+        if let Some((_, values)) = cmd.arg {
+            cases.push_str(&format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n",
+                cmd.name,
+                values.join(" ")
+            ));
+        }
+    }
+
+    format!(
+        "_rtmouse_rs() {{\n\
+         \x20   local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n\
+         \x20   local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\
+         \x20   case \"$prev\" in\n\
+         {}\
+         \x20   esac\n\
+         \x20   COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n\
+         }}\n\
+         complete -F _rtmouse_rs {}\n",
+        cases,
+        words.join(" "),
+        BIN
+    )
+}
+
+// Help text goes inside '...' and [...] in _arguments specs
+fn zsh_escape(s: &str) -> String {
+    s.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh_completions() -> String {
+    let mut specs = String::new();
+    for flag in FLAGS {
+        // -h and --help are the same option, so each excludes the other
+        let names = match flag.short {
+            Some(c) => format!("'(-{c} --{l})'{{-{c},--{l}}}'", c = c, l = flag.long),
+            None => format!("'--{}", flag.long),
+        };
+        let value = match flag.value {
+            Some(v) => format!(":{}:_files", v.to_lowercase()),
+            None => String::new(),
+        };
+        specs.push_str(&format!(
+            "        {}[{}]{}' \\\n",
+            names,
+            zsh_escape(flag.help),
+            value
+        ));
+    }
+
+    let mut commands = String::new();
+    let mut args = String::new();
+    for cmd in COMMANDS {
+        commands.push_str(&format!(
+            "        '{}:{}'\n",
+            cmd.name,
+            zsh_escape(cmd.help)
+        ));
+        if let Some((name, values)) = cmd.arg {
+            args.push_str(&format!(
+                "                {}) _values '{}' {} ;;\n",
+                cmd.name,
+                name.to_lowercase(),
+                values.join(" ")
+            ));
+        }
+    }
+
+    format!(
+        "#compdef {bin}\n\
+         \n\
+         _rtmouse_rs() {{\n\
+         \x20   local -a commands\n\
+         \x20   commands=(\n\
+         {commands}\
+         \x20   )\n\
+         \x20   _arguments -C \\\n\
+         {specs}\
+         \x20       '1: :->command' \\\n\
+         \x20       '*:: :->args'\n\
+         \x20   case $state in\n\
+         \x20       command) _describe 'command' commands ;;\n\
+         \x20       args)\n\
+         \x20           case $words[1] in\n\
+         {args}\
+         \x20           esac ;;\n\
+         \x20   esac\n\
+         }}\n\
+         \n\
+         _rtmouse_rs \"$@\"\n",
+        bin = BIN,
+        commands = commands,
+        specs = specs,
+        args = args
+    )
+}
+
+fn fish_completions() -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+
+    let mut out = format!("complete -c {} -f\n", BIN);
+    for flag in FLAGS {
+        let mut line = format!("complete -c {}", BIN);
+        if let Some(c) = flag.short {
+            line.push_str(&format!(" -s {}", c));
+        }
+        line.push_str(&format!(" -l {}", flag.long));
+        if flag.value.is_some() {
+            line.push_str(" -r -F");
+        }
+        line.push_str(&format!(" -d {}\n", quote(flag.help)));
+        out.push_str(&line);
+    }
+    for cmd in COMMANDS {
+        out.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}\n",
+            BIN,
+            cmd.name,
+            quote(cmd.help)
+        ));
+        if let Some((_, values)) = cmd.arg {
+            out.push_str(&format!(
+                "complete -c {} -n '__fish_seen_subcommand_from {}' -a {}\n",
+                BIN,
+                cmd.name,
+                quote(&values.join(" "))
+            ));
+        }
+    }
+    out
+}
+
+pub fn completions(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash_completions(),
+        Shell::Zsh => zsh_completions(),
+        Shell::Fish => fish_completions(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_init_config() {
+        let opts = parse(&["--config", "/tmp/c.toml", "--init-config", "--force"]).unwrap();
+        assert_eq!(opts.config_path, Some(PathBuf::from("/tmp/c.toml")));
+        assert_eq!(opts.action, Action::InitConfig { force: true });
+        assert_eq!(
+            parse(&["--setup"]).unwrap().action,
+            Action::Setup { force: false }
+        );
+        let opts = parse(&["--runtime-dir", "/tmp/rt", "status"]).unwrap();
+        assert_eq!(opts.runtime_dir, Some(PathBuf::from("/tmp/rt")));
+        assert!(parse(&["--runtime-dir"]).is_err());
+        assert_eq!(parse(&[]).unwrap().action, Action::Run);
+        assert_eq!(
+            parse(&["--print-config"]).unwrap().action,
+            Action::PrintConfig
+        );
+    }
+
+    #[test]
+    fn parses_completions() {
+        assert_eq!(
+            parse(&["completions", "zsh"]).unwrap().action,
+            Action::Completions(Shell::Zsh)
+        );
+        assert!(parse(&["completions"]).is_err());
+        assert!(parse(&["completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn parses_export() {
+        assert_eq!(
+            parse(&["export", "--format", "csv", "--since", "7d"])
+                .unwrap()
+                .action,
+            Action::Export {
+                since: Some(Duration::from_secs(7 * 86400))
+            }
+        );
+        assert_eq!(
+            parse(&["export"]).unwrap().action,
+            Action::Export { since: None }
+        );
+        assert!(parse(&["export", "--format", "xlsx"]).is_err());
+        assert!(parse(&["export", "--since", "7"]).is_err());
+        assert!(parse(&["export", "--since", "ad"]).is_err());
+        assert!(parse(&["status", "--since", "1h"]).is_err());
+        assert_eq!(
+            parse(&["heatmap", "--since", "1h"]).unwrap().action,
+            Action::Heatmap {
+                since: Some(Duration::from_secs(3600))
+            }
+        );
+        assert!(parse(&["heatmap", "--format", "csv"]).is_err());
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let opts = parse(&["--profile", "trackball", "--print-config"]).unwrap();
+        assert_eq!(opts.profile.as_deref(), Some("trackball"));
+        assert_eq!(
+            parse(&["profile", "export", "trackball"]).unwrap().action,
+            Action::ExportProfile {
+                name: "trackball".to_string()
+            }
+        );
+        assert_eq!(
+            parse(&["profile", "import", "a.toml", "--force"])
+                .unwrap()
+                .action,
+            Action::ImportProfile {
+                path: PathBuf::from("a.toml"),
+                force: true
+            }
+        );
+        assert!(parse(&["profile", "export"]).is_err());
+        assert!(parse(&["profile", "delete", "x"]).is_err());
+        assert!(parse(&["--profile"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--force"]).is_err());
+        assert!(parse(&["--json"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["--init-config", "--print-config"]).is_err());
+        assert!(parse(&["--setup", "--init-config"]).is_err());
+        assert!(parse(&["--setup", "status"]).is_err());
+        assert!(parse(&["--supervise", "--print-config"]).is_err());
+    }
+
+    // Everything in the tables has to be accepted by parse_args, and show up
+    // in the help and every completion script.
+    #[test]
+    fn tables_match_parser() {
+        let help = usage();
+        let scripts: Vec<String> = [Shell::Bash, Shell::Zsh, Shell::Fish]
+            .iter()
+            .map(|s| completions(*s))
+            .collect();
+
+        for flag in FLAGS {
+            let mut args = vec![format!("--{}", flag.long)];
+            if flag.value.is_some() {
+                args.push("x".to_string());
+            }
+            if flag.long == "force" {
+                args.push("--init-config".to_string());
+            }
+            if flag.long == "json" {
+                args.push("status".to_string());
+            }
+            if flag.long == "format" || flag.long == "since" {
+                args[1] = if flag.long == "format" { "csv" } else { "7d" }.to_string();
+                args.push("export".to_string());
+            }
+            assert!(parse_args(args).is_ok(), "--{}", flag.long);
+            assert!(help.contains(&format!("--{}", flag.long)));
+            for script in &scripts {
+                assert!(script.contains(flag.long), "--{}", flag.long);
+            }
+        }
+
+        for cmd in COMMANDS {
+            let mut args = vec![cmd.name.to_string()];
+            if let Some((_, values)) = cmd.arg {
+                args.push(values[0].to_string());
+            }
+            if cmd.name == "profile" || cmd.name == "macro" || cmd.name == "sound-theme" {
+                args.push("x".to_string());
+            }
+            assert!(parse_args(args).is_ok(), "{}", cmd.name);
+            assert!(help.contains(cmd.name));
+            for script in &scripts {
+                assert!(script.contains(cmd.name), "{}", cmd.name);
+            }
+        }
+    }
+}
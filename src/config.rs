@@ -0,0 +1,2390 @@
+// Config file loading. The file is a small subset of TOML: `key = value`
+// lines, `[section]` headers, `#` comments, and bool, integer, string and
+// single-line array values. That covers everything rtmouse needs without
+// pulling in a TOML crate.
+//
+// `[profile.NAME]` sections hold options that override the ones above them
+// when rtmouse runs with --profile NAME, for tunings that differ by person
+// or device.
+
+use crate::geometry::Rect;
+use crate::paths;
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// What the countdown overlay shows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Countdown {
+    Off,
+    // Remaining milliseconds
+    Millis,
+    // 3, 2, 1
+    Count,
+}
+
+impl Countdown {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Countdown::Off => "off",
+            Countdown::Millis => "ms",
+            Countdown::Count => "count",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Theme {
+    // Colors and sizes from the anchor_dot_*, drag_bar_* and countdown_* keys
+    Default,
+    // Big, outlined indicators in strong colors, for low vision
+    HighContrast,
+}
+
+impl Theme {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Theme::Default => "default",
+            Theme::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Theme> {
+        match s {
+            "default" => Some(Theme::Default),
+            "high-contrast" => Some(Theme::HighContrast),
+            _ => None,
+        }
+    }
+}
+
+// What moving off after a drag dwell does, for drag_vertical and
+// drag_horizontal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DragAction {
+    // Hold the button down
+    Drag,
+    // Wheel clicks along the way it moved
+    Scroll,
+}
+
+impl DragAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DragAction::Drag => "drag",
+            DragAction::Scroll => "scroll",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<DragAction> {
+        match s {
+            "drag" => Some(DragAction::Drag),
+            "scroll" => Some(DragAction::Scroll),
+            _ => None,
+        }
+    }
+}
+
+// What dwelling does over an on-screen keyboard, see keyboard_classes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyboardDwell {
+    // The same as anywhere else
+    Normal,
+    // keyboard_dwell_time instead of dwell_time
+    Quick,
+    // Nothing, the keyboard's own dwell does the typing
+    Off,
+}
+
+impl KeyboardDwell {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyboardDwell::Normal => "normal",
+            KeyboardDwell::Quick => "quick",
+            KeyboardDwell::Off => "off",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<KeyboardDwell> {
+        match s {
+            "normal" => Some(KeyboardDwell::Normal),
+            "quick" => Some(KeyboardDwell::Quick),
+            "off" => Some(KeyboardDwell::Off),
+            _ => None,
+        }
+    }
+}
+
+// Which corner or edge of the primary monitor the control panel docks to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanelPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+const PANEL_POSITIONS: [(PanelPosition, &str); 8] = [
+    (PanelPosition::TopLeft, "top-left"),
+    (PanelPosition::Top, "top"),
+    (PanelPosition::TopRight, "top-right"),
+    (PanelPosition::Left, "left"),
+    (PanelPosition::Right, "right"),
+    (PanelPosition::BottomLeft, "bottom-left"),
+    (PanelPosition::Bottom, "bottom"),
+    (PanelPosition::BottomRight, "bottom-right"),
+];
+
+impl PanelPosition {
+    pub fn as_str(self) -> &'static str {
+        PANEL_POSITIONS.iter().find(|(p, _)| *p == self).unwrap().1
+    }
+
+    pub fn parse(s: &str) -> Option<PanelPosition> {
+        PANEL_POSITIONS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(p, _)| *p)
+    }
+}
+
+// A spot on screen where a dwell replays a recorded macro instead of
+// clicking, see macros.rs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroZone {
+    pub name: String,
+    pub rect: Rect,
+}
+
+impl MacroZone {
+    // "NAME X Y WIDTH HEIGHT"
+    pub fn parse(s: &str) -> Option<MacroZone> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        match fields[..] {
+            [name, x, y, width, height] => Some(MacroZone {
+                name: name.to_string(),
+                rect: Rect {
+                    x: x.parse().ok()?,
+                    y: y.parse().ok()?,
+                    width: width.parse().ok().filter(|&w| w > 0)?,
+                    height: height.parse().ok().filter(|&h| h > 0)?,
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            self.name, self.rect.x, self.rect.y, self.rect.width, self.rect.height
+        )
+    }
+}
+
+// A device_profiles entry, "PROFILE DEVICE NAME": the profile to run while
+// that device is moving the pointer, and part of the device's XInput name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub profile: String,
+    pub device: String,
+}
+
+impl DeviceProfile {
+    pub fn parse(s: &str) -> Option<DeviceProfile> {
+        let (profile, device) = s.trim().split_once(char::is_whitespace)?;
+        let device = device.trim();
+        if device.is_empty() {
+            return None;
+        }
+        Some(DeviceProfile {
+            profile: profile.to_string(),
+            device: device.to_string(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        format!("{} {}", self.profile, self.device)
+    }
+
+    // Ignoring case, since it's typed from `xinput list`
+    pub fn matches(&self, name: &str) -> bool {
+        name.to_lowercase().contains(&self.device.to_lowercase())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DwellConfig {
+    pub min_movement_pixels: u32,
+    pub min_movement_mm: Option<u32>,
+    pub jump_pixels: Option<u32>,
+    pub jump_arm_delay: Duration,
+    pub precision_mode: bool,
+    pub precision_speed: i32,
+    pub dwell_time: Duration,
+    pub right_dwell_time: Option<Duration>,
+    pub double_dwell_time: Option<Duration>,
+    pub drag_time: Duration,
+    pub drag_enabled: bool,
+    pub drag_cooldown: Duration,
+    pub drag_vertical: DragAction,
+    pub drag_horizontal: DragAction,
+    pub scroll_step: u32,
+    pub scroll_clicks: u32,
+    pub scroll_repeat: Option<Duration>,
+    pub natural_scroll: bool,
+    pub gesture_l: String,
+    pub gesture_circle: String,
+    pub gesture_zigzag: String,
+    pub sound_enabled: bool,
+    pub click_sound: bool,
+    pub drag_start_sound: bool,
+    pub drag_end_sound: bool,
+    pub click_type_sound: bool,
+    pub sound_file: String,
+    pub sound_theme: String,
+    pub sound_pitch: u32,
+    pub sound_duration: Duration,
+    pub led_click: String,
+    pub led_enabled: String,
+    pub write_status_file: bool,
+    pub status_file: String,
+    pub control_socket: String,
+    pub pid_file: String,
+    pub log_file: String,
+    pub send_event_classes: Vec<String>,
+    pub primary_button: Option<u8>,
+    pub keyboard_classes: Vec<String>,
+    pub keyboard_dwell: KeyboardDwell,
+    pub keyboard_dwell_time: Duration,
+    pub max_drag_duration: Duration,
+    pub stuck_drag_limit: Duration,
+    pub release_stale_buttons: bool,
+    pub idle_inhibit: bool,
+    pub disabled: bool,
+    pub auto_disable_after: Option<Duration>,
+    pub wake_movement_pixels: u32,
+    pub battery_tick_interval: Duration,
+    pub anchor_dot: bool,
+    pub anchor_dot_color: u32,
+    pub anchor_dot_radius: u32,
+    pub drag_bar: bool,
+    pub drag_bar_color: u32,
+    pub drag_bar_width: u32,
+    pub drag_bar_height: u32,
+    pub countdown: Countdown,
+    pub countdown_color: u32,
+    pub overlay_theme: Theme,
+    pub click_key: String,
+    pub focused_window_only: bool,
+    pub grab_delay: Duration,
+    pub screens: Vec<u32>,
+    pub history_log: bool,
+    pub monitor_command: String,
+    pub monitor_no_clicks_after: Option<Duration>,
+    pub nudge_keys: bool,
+    pub nudge_step: u32,
+    pub macro_zones: Vec<MacroZone>,
+    pub device_profiles: Vec<DeviceProfile>,
+    pub pause_button: bool,
+    pub pause_button_size: u32,
+    pub panel: bool,
+    pub panel_position: PanelPosition,
+    pub panel_vertical: bool,
+    pub panel_button_size: u32,
+    pub shake_toggle: bool,
+    pub shake_reversals: u32,
+    pub shake_speed: u32,
+    pub shake_time: Duration,
+}
+
+impl Default for DwellConfig {
+    fn default() -> DwellConfig {
+        DwellConfig {
+            // Minimum movement before a mouse motion activates the dwell timer
+            min_movement_pixels: 10,
+            // the same in millimeters, converted for each monitor
+            min_movement_mm: None,
+            // tablets and touchscreens jump instead of moving. a jump this far
+            // isn't movement for a drag to follow, and the dwell timer only
+            // starts jump_arm_delay after it
+            jump_pixels: Some(150),
+            jump_arm_delay: Duration::from_millis(500),
+            // once the dwell timer arms, slow the pointer down to
+            // precision_speed (libinput's accel speed, -100 to 100) for the
+            // last bit of aiming, until the click
+            precision_mode: false,
+            precision_speed: -60,
+
+            // rtmouse will wait this long after mouse movement ends before clicking.
+            // default 500ms. you may want to make it longer
+            dwell_time: Duration::from_millis(500),
+            // right and double clicks can take longer, so they happen less
+            // by accident. None is dwell_time
+            right_dwell_time: None,
+            double_dwell_time: None,
+
+            // rtmouse will drag-click if you move the mouse within this timeframe
+            // after a click occurs.
+            drag_time: Duration::from_millis(500),
+
+            // dragging only happens when this is on
+            drag_enabled: true,
+
+            // no dwell starts for this long after a drag ends, so moving off
+            // doesn't click on what was just dropped. 0 for none
+            drag_cooldown: Duration::ZERO,
+
+            // after a drag dwell, which way the pointer moves off first picks
+            // a real drag or scrolling, one wheel click every scroll_step pixels
+            drag_vertical: DragAction::Drag,
+            drag_horizontal: DragAction::Drag,
+            scroll_step: 20,
+            // wheel clicks sent per scroll_step, and with scroll_repeat,
+            // resting away from where scrolling started keeps it going
+            scroll_clicks: 1,
+            scroll_repeat: None,
+            // moving down scrolls up, like a touchpad's natural scrolling
+            natural_scroll: false,
+
+            // what strokes drawn after a gesture dwell do: "close-window",
+            // "middle-click", "macro NAME", or a command for sh -c. empty
+            // does nothing
+            gesture_l: String::new(),
+            gesture_circle: String::new(),
+            gesture_zigzag: String::new(),
+
+            // sound plays on click when this is on
+            sound_enabled: true,
+
+            // which events get a sound, when sound_enabled is on
+            click_sound: true,
+            drag_start_sound: true,
+            drag_end_sound: true,
+
+            // picking a click type plays its sound after a low blip, so it
+            // can be heard which click is coming
+            click_type_sound: true,
+
+            // played with pw-play, paplay or aplay, whichever is installed.
+            // empty means the built in tones below
+            sound_file: String::new(),
+            // a directory of sounds, one per event, over sound_file. empty
+            // for none
+            sound_theme: String::new(),
+
+            // the built in tones: right clicks are lower, drags higher, double
+            // clicks beep twice
+            sound_pitch: 880,
+            sound_duration: Duration::from_millis(40),
+
+            // keyboard lights, by XKB name like "Scroll Lock": one blinks on
+            // each click, the other is lit while dwell clicking is on
+            led_click: String::new(),
+            led_enabled: String::new(),
+
+            // status_file will be modified with enabled/disabled/terminated statuses
+            // when this is on
+            write_status_file: true,
+
+            // empty means $XDG_RUNTIME_DIR/rtmouse/status, like the paths below
+            status_file: String::new(),
+
+            // where `rtmouse-rs status` and friends reach the running rtmouse
+            control_socket: String::new(),
+
+            // holds the running rtmouse's pid, for scripts
+            pid_file: String::new(),
+
+            // the --explain trace, which `rtmouse diagnose` picks up
+            log_file: String::new(),
+
+            // Windows whose WM_CLASS matches one of these (case-insensitive) get
+            // their clicks delivered with XSendEvent instead of XTest. Some
+            // emulators and VMs grab the pointer and react oddly to XTest.
+            send_event_classes: Vec::new(),
+
+            // The physical button XTest presses for a left click. None looks
+            // it up in the pointer mapping
+            primary_button: None,
+
+            // On-screen keyboards by WM_CLASS, and what dwelling over them
+            // does. Some have a dwell of their own that ours would click on
+            // top of.
+            keyboard_classes: [
+                "onboard",
+                "florence",
+                "xvkbd",
+                "matchbox-keyboard",
+                "kvkbd",
+                "caribou",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            keyboard_dwell: KeyboardDwell::Normal,
+            keyboard_dwell_time: Duration::from_millis(300),
+
+            // Drags end after this long even if the pointer keeps moving, so
+            // one left parked on a jittery spot can't hold the button forever.
+            max_drag_duration: Duration::from_secs(30),
+
+            // A drag held this long is assumed to be a bug and released by
+            // force, whatever the state machine thinks.
+            stuck_drag_limit: Duration::from_secs(120),
+
+            // Buttons down at startup may be a drag the last rtmouse never
+            // let go of. XTest releases only reach buttons XTest pressed.
+            release_stale_buttons: true,
+
+            // Fake clicks don't always count as activity to the desktop's
+            // idle timer, so the screen could lock halfway through a drag.
+            idle_inhibit: true,
+
+            // Start off, for a session that only wants rtmouse there to
+            // switch on when needed.
+            disabled: false,
+
+            // With no pointer activity for this long, rtmouse goes to sleep
+            // until the pointer moves further than wake_movement_pixels, so
+            // coming back to the machine doesn't click on whatever is there.
+            auto_disable_after: None,
+            wake_movement_pixels: 200,
+
+            // Tick this slowly while on battery. Clicks land up to a tick
+            // late, which is hard to notice at a few hundred ms.
+            battery_tick_interval: Duration::from_millis(250),
+
+            // a dot where the pointer came to rest while the dwell timer runs,
+            // colors are 0xAARRGGBB
+            anchor_dot: false,
+            anchor_dot_color: 0xccff3030,
+            anchor_dot_radius: 3,
+
+            // a bar next to the pointer that runs down until a drag lets go
+            drag_bar: false,
+            drag_bar_color: 0xcc3080ff,
+            drag_bar_width: 40,
+            drag_bar_height: 4,
+
+            // a number next to the pointer saying how long until the click
+            countdown: Countdown::Off,
+            countdown_color: 0xffffffff,
+
+            overlay_theme: Theme::Default,
+
+            // X keysym name of a key that clicks right away, empty for none
+            click_key: String::new(),
+
+            // don't click into windows other than the focused one
+            focused_window_only: false,
+
+            // hold a due click back up to this long while another client has
+            // the pointer grabbed, like a menu that's still opening. 0 is off
+            grab_delay: Duration::ZERO,
+
+            // X screen numbers to dwell on, empty for just the default one
+            screens: Vec::new(),
+
+            // log every click with where it went, for `rtmouse-rs export`
+            history_log: false,
+
+            // run with sh -c to tell a carer elsewhere about rtmouse being
+            // turned on or off or not clicking, see monitor.rs. empty is off
+            monitor_command: String::new(),
+
+            // with monitor_command set, say so after this long without a
+            // click while dwell clicking is on and awake
+            monitor_no_clicks_after: None,
+
+            // arrow keys move the pointer nudge_step pixels while the dwell
+            // timer runs, without restarting it
+            nudge_keys: false,
+            nudge_step: 3,
+
+            // dwelling inside one of these replays its macro instead of clicking
+            macro_zones: Vec::new(),
+            // which profile to switch to for which pointing device
+            device_profiles: Vec::new(),
+
+            // a small always-on-top window to dwell on to pause and resume
+            pause_button: false,
+            pause_button_size: 96,
+
+            // the control strip: pause and click type buttons, and the
+            // dwell's progress
+            panel: false,
+            panel_position: PanelPosition::Top,
+            panel_vertical: false,
+            panel_button_size: 64,
+
+            // shaking the pointer back and forth toggles dwell clicking: this
+            // many turns at shake_speed pixels per second within shake_time
+            shake_toggle: false,
+            shake_reversals: 4,
+            shake_speed: 1500,
+            shake_time: Duration::from_millis(1000),
+        }
+    }
+}
+
+// Every key the config file accepts, with the comment written above it in
+// the --init-config template.
+pub const OPTIONS: &[(&str, &str)] = &[
+    (
+        "min_movement_pixels",
+        "Minimum movement, in pixels, before a mouse motion starts the dwell timer.",
+    ),
+    (
+        "min_movement_mm",
+        "The same in millimeters, worked out for each monitor from the size it\n\
+         reports, so one setting feels the same on a laptop panel and a big\n\
+         monitor. Monitors that don't report a size use min_movement_pixels.\n\
+         0 is off.",
+    ),
+    (
+        "jump_pixels",
+        "Graphics tablets and touchscreens in absolute mode put the pointer\n\
+         down somewhere new instead of moving it there. From one of them, a\n\
+         move this many pixels in one go is a jump: it doesn't steer a drag or\n\
+         scroll, and the dwell timer waits jump_arm_delay before it starts.\n\
+         0 is off.",
+    ),
+    (
+        "jump_arm_delay",
+        "How long after a jump, in milliseconds, before the dwell timer can\n\
+         start, so landing somewhere isn't a dwell already.",
+    ),
+    (
+        "precision_mode",
+        "Slow the pointer down once the dwell timer arms, to make the last bit\n\
+         of aiming easier, and speed it back up after the click or once it's\n\
+         moved well away. Only for devices the libinput driver runs.",
+    ),
+    (
+        "precision_speed",
+        "How slow precision_mode makes the pointer: libinput's accel speed from\n\
+         -100, slowest, to 100. A speed already slower than this is left alone.",
+    ),
+    (
+        "dwell_time",
+        "How long the pointer has to rest before rtmouse clicks, in milliseconds.\n\
+         You may want to make it longer.",
+    ),
+    (
+        "right_dwell_time",
+        "How long a right click dwell takes instead, in milliseconds. Longer\n\
+         than dwell_time makes opening menus by accident rarer. 0 is the same\n\
+         as dwell_time.",
+    ),
+    (
+        "double_dwell_time",
+        "The same for double clicks, which open things. 0 is dwell_time.",
+    ),
+    (
+        "drag_time",
+        "After a click, moving the pointer within this many milliseconds turns\n\
+         it into a drag. The drag ends once the pointer rests this long again.",
+    ),
+    ("drag_enabled", "Dragging only happens when this is on."),
+    (
+        "drag_cooldown",
+        "For this many milliseconds after a drag ends, moving the pointer doesn't\n\
+         start a dwell, so it can't click on what was just dropped. 0 for none.",
+    ),
+    (
+        "drag_vertical",
+        "What a drag does when the pointer moves off mostly up or down: \"drag\"\n\
+         holds the button, \"scroll\" turns the movement into wheel clicks.",
+    ),
+    (
+        "drag_horizontal",
+        "The same for moving off mostly left or right.",
+    ),
+    (
+        "scroll_step",
+        "Pixels of movement per wheel click when a drag is scrolling.",
+    ),
+    (
+        "scroll_clicks",
+        "Wheel clicks sent for every scroll_step. Terminal programs scroll a\n\
+         line or so per click, smooth scrolling browsers much further.",
+    ),
+    (
+        "scroll_repeat",
+        "While scrolling, resting at least scroll_step away from where it\n\
+         started keeps scrolling that way, scroll_clicks every this many\n\
+         milliseconds, until the pointer rests back near the start. 0 ends\n\
+         the scroll on any rest instead.",
+    ),
+    (
+        "natural_scroll",
+        "Scroll the other way: moving down or right scrolls up or left, for\n\
+         anyone used to natural scrolling on a touchpad.",
+    ),
+    (
+        "gesture_l",
+        "After a gesture dwell (`click-type gesture`), what drawing an L does:\n\
+         \"close-window\", \"middle-click\", \"macro NAME\" to replay a recorded\n\
+         macro, or a command to run with sh -c.\n\
+         Empty does nothing. The stroke ends when the pointer rests for\n\
+         drag_time, and acts where the dwell was.",
+    ),
+    ("gesture_circle", "The same for drawing a circle."),
+    (
+        "gesture_zigzag",
+        "The same for a zig-zag, back and forth at least twice.",
+    ),
+    (
+        "sound_enabled",
+        "Play feedback sounds. Turns off all of the *_sound options below.",
+    ),
+    (
+        "click_sound",
+        "Play a sound on left, right and double clicks.",
+    ),
+    ("drag_start_sound", "Play a sound when a drag starts."),
+    ("drag_end_sound", "Play a sound when a drag ends."),
+    (
+        "click_type_sound",
+        "When click-type picks the next click, play its sound after a low blip,\n\
+         to tell which click the next dwell does without looking.",
+    ),
+    (
+        "sound_file",
+        "A sound file to play instead of the built in tones, anything pw-play,\n\
+         paplay or aplay can open.",
+    ),
+    (
+        "sound_theme",
+        "A sound theme: a name from ~/.local/share/rtmouse/sounds or\n\
+         /usr/share/rtmouse/sounds, or a directory. Its sounds play for the events\n\
+         it has one for, the rest as if there were no theme.",
+    ),
+    (
+        "sound_pitch",
+        "Pitch of the built in click tone, in Hz. Right clicks sound lower and\n\
+         drags higher, double clicks beep twice.",
+    ),
+    (
+        "sound_duration",
+        "Length of the built in click tone, in milliseconds.",
+    ),
+    (
+        "led_click",
+        "Blink this keyboard light on every click, by its XKB name, usually\n\
+         \"Scroll Lock\", \"Num Lock\" or \"Caps Lock\". Empty is off.",
+    ),
+    (
+        "led_enabled",
+        "Keep this keyboard light on while dwell clicking is on. Lighting Caps\n\
+         Lock or Num Lock also turns the lock on, pick one you don't type with.",
+    ),
+    (
+        "write_status_file",
+        "Keep status_file up to date with enabled/disabled/terminated.",
+    ),
+    (
+        "status_file",
+        "Where the status is written. Empty means status in the runtime\n\
+         directory, $XDG_RUNTIME_DIR/rtmouse or --runtime-dir.",
+    ),
+    (
+        "control_socket",
+        "The control socket for `rtmouse-rs status` and the other commands.\n\
+         Empty means control.sock in the runtime directory.",
+    ),
+    (
+        "pid_file",
+        "Holds the running rtmouse's process id. Empty means pid in the\n\
+         runtime directory.",
+    ),
+    (
+        "log_file",
+        "Where --explain keeps its trace. Empty means trace.log in the runtime\n\
+         directory.",
+    ),
+    (
+        "send_event_classes",
+        "Windows whose WM_CLASS matches one of these (case-insensitive) get their\n\
+         clicks with XSendEvent instead of XTest. Some emulators and VMs grab\n\
+         the pointer and react oddly to XTest.",
+    ),
+    (
+        "primary_button",
+        "The physical button XTest presses for left clicks and drags, when the\n\
+         pointer mapping X reports is wrong, say with buttons swapped below X\n\
+         in evdev or the device itself. 0 works it out from the mapping.",
+    ),
+    (
+        "keyboard_classes",
+        "On-screen keyboards, by WM_CLASS (case-insensitive), for\n\
+         keyboard_dwell.",
+    ),
+    (
+        "keyboard_dwell",
+        "What dwelling does over an on-screen keyboard: \"normal\", \"quick\"\n\
+         for keyboard_dwell_time instead of dwell_time, or \"off\" to leave\n\
+         it to the keyboard's own dwell so keys aren't pressed twice.",
+    ),
+    (
+        "keyboard_dwell_time",
+        "The dwell over on-screen keyboards with keyboard_dwell = \"quick\", in\n\
+         milliseconds.",
+    ),
+    (
+        "max_drag_duration",
+        "Release a drag after this many milliseconds even if the pointer is\n\
+         still moving.",
+    ),
+    (
+        "stuck_drag_limit",
+        "Safety net: a drag held down longer than this many milliseconds is\n\
+         released by force, with a warning sound.",
+    ),
+    (
+        "release_stale_buttons",
+        "At startup, let go of any button a previous rtmouse (or anything else\n\
+         faking clicks) left held down. Buttons held by hand stay held, and\n\
+         hold off dwelling until they're let go.",
+    ),
+    (
+        "idle_inhibit",
+        "Keep the screen saver and screen lock away while dragging or while a\n\
+         dwell is about to click, by resetting the X screen saver and running\n\
+         `xdg-screensaver reset` when it's installed.",
+    ),
+    (
+        "disabled",
+        "Start with dwell clicking off, until `rtmouse-rs enable` or a toggle.",
+    ),
+    (
+        "auto_disable_minutes",
+        "Stop dwelling after this many minutes without pointer activity, until\n\
+         a movement of wake_movement_pixels wakes rtmouse again. 0 never does.",
+    ),
+    (
+        "wake_movement_pixels",
+        "How far the pointer has to move in one go to wake a sleeping rtmouse.",
+    ),
+    (
+        "battery_tick_interval",
+        "How often to check the pointer while on battery, in milliseconds.\n\
+         Normally that's every 100ms; set this to 100 to never slow down.",
+    ),
+    (
+        "anchor_dot",
+        "While the dwell timer runs, show a dot where the pointer came to rest.",
+    ),
+    (
+        "anchor_dot_color",
+        "Color of the anchor dot, \"#rrggbb\" or \"#rrggbbaa\". Transparency\n\
+         needs a compositor.",
+    ),
+    ("anchor_dot_radius", "Radius of the anchor dot, in pixels."),
+    (
+        "drag_bar",
+        "During a drag, show a bar next to the pointer that runs down until the\n\
+         drag is released.",
+    ),
+    (
+        "drag_bar_color",
+        "Color of the drag bar, like anchor_dot_color.",
+    ),
+    ("drag_bar_width", "Width of the full drag bar, in pixels."),
+    ("drag_bar_height", "Height of the drag bar, in pixels."),
+    (
+        "countdown",
+        "Show how long until the click next to the pointer: \"ms\" for the\n\
+         milliseconds left, \"count\" for 3, 2, 1, or \"off\".",
+    ),
+    (
+        "countdown_color",
+        "Color of the countdown, like anchor_dot_color.",
+    ),
+    (
+        "overlay_theme",
+        "\"high-contrast\" draws the overlays bigger, in yellow with a black\n\
+         outline, or \"default\".",
+    ),
+    (
+        "click_key",
+        "A key that does the next click right away instead of waiting for the\n\
+         dwell, or ends a drag. An X key name like \"F12\" or \"Pause\", empty\n\
+         for none. rtmouse grabs it, so other programs stop seeing it.",
+    ),
+    (
+        "focused_window_only",
+        "Only click when the pointer is over the focused window. With focus\n\
+         follows mouse this stops clicks landing in windows the pointer was\n\
+         just passing over.",
+    ),
+    (
+        "grab_delay",
+        "While another program has the pointer grabbed, an open menu or a\n\
+         drag and drop, hold a due click back up to this many milliseconds\n\
+         for the grab to go, then click anyway. --explain logs it either way.\n\
+         0 doesn't check.",
+    ),
+    (
+        "screens",
+        "On a display with several X screens (:0.0, :0.1, not Xinerama or\n\
+         RandR monitors), the screen numbers to dwell click on, like [0, 1].\n\
+         Empty for only the default screen. Overlays only show on the default\n\
+         screen.",
+    ),
+    (
+        "history_log",
+        "Log every click, drag and abandoned dwell with its position and the\n\
+         window class to $XDG_STATE_HOME/rtmouse/history, for\n\
+         `rtmouse-rs export`. Off by default, it records what you click on.",
+    ),
+    (
+        "monitor_command",
+        "Run with sh -c to alert a carer elsewhere, with RTMOUSE_EVENT set to\n\
+         enabled, disabled, stuck-drag, not-delivering, no-clicks or\n\
+         clicking-again and RTMOUSE_DETAIL to a sentence saying what\n\
+         happened. Empty is off.",
+    ),
+    (
+        "monitor_no_clicks_after",
+        "Send a no-clicks alert after this many minutes without a click while\n\
+         dwell clicking is on and awake. 0 never does.",
+    ),
+    (
+        "nudge_keys",
+        "While the dwell timer runs, the arrow keys move the pointer\n\
+         nudge_step pixels without restarting it, to fine-tune where the click\n\
+         lands. Only then, the rest of the time they go to applications.",
+    ),
+    (
+        "nudge_step",
+        "How far one arrow key press nudges, in pixels.",
+    ),
+    (
+        "macro_zones",
+        "Dwelling inside one of these replays a macro recorded with\n\
+         `rtmouse-rs macro record NAME` instead of clicking. Each is\n\
+         \"NAME X Y WIDTH HEIGHT\" in root window pixels.",
+    ),
+    (
+        "device_profiles",
+        "Switch profiles by which device is moving the pointer, each entry\n\
+         \"PROFILE DEVICE\" with DEVICE part of a name from `xinput list`, like\n\
+         \"head-tracker TrackIR\". Other devices get the options rtmouse\n\
+         started with.",
+    ),
+    (
+        "pause_button",
+        "Show a small always-on-top window that pauses dwell clicking when\n\
+         dwelled on. While paused, it's the only thing dwelling works on, to\n\
+         resume. Move it by its title bar.",
+    ),
+    (
+        "pause_button_size",
+        "Width and height of the pause button, in pixels.",
+    ),
+    (
+        "panel",
+        "Show the control panel, an always-on-top strip of pause, left,\n\
+         double, right and drag buttons with the dwell's progress under them.\n\
+         Dwelling on a click type button makes the next dwell that kind of click.",
+    ),
+    (
+        "panel_position",
+        "Where the control panel docks on the primary monitor: \"top-left\",\n\
+         \"top\", \"top-right\", \"left\", \"right\", \"bottom-left\", \"bottom\"\n\
+         or \"bottom-right\".",
+    ),
+    (
+        "panel_orientation",
+        "\"horizontal\" for the buttons side by side, \"vertical\" for a column.",
+    ),
+    (
+        "panel_button_size",
+        "Width and height of each control panel button, in pixels.",
+    ),
+    (
+        "shake_toggle",
+        "Shaking the pointer fast back and forth toggles dwell clicking, on\n\
+         or off.",
+    ),
+    (
+        "shake_reversals",
+        "How many times a shake has to turn back.",
+    ),
+    (
+        "shake_speed",
+        "How fast each part of a shake has to move, in pixels per second.\n\
+         Slower movement in between, at the turns, doesn't count against it.",
+    ),
+    (
+        "shake_time",
+        "All of a shake's turns have to be within this many milliseconds.",
+    ),
+];
+
+// Times are in milliseconds. Over a minute is almost certainly a units
+// mistake rather than a preference.
+const MAX_TIME_MS: i64 = 60_000;
+
+// Limits on whole interactions rather than reaction times can be longer
+const MAX_LIMIT_MS: i64 = 3_600_000;
+
+impl DwellConfig {
+    pub fn status_path(&self) -> PathBuf {
+        paths::or_runtime_file(&self.status_file, "status")
+    }
+
+    pub fn control_socket_path(&self) -> PathBuf {
+        paths::or_runtime_file(&self.control_socket, "control.sock")
+    }
+
+    pub fn pid_path(&self) -> PathBuf {
+        paths::or_runtime_file(&self.pid_file, "pid")
+    }
+
+    pub fn log_path(&self) -> PathBuf {
+        paths::or_runtime_file(&self.log_file, "trace.log")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "a boolean",
+            Value::Int(_) => "a number",
+            Value::Str(_) => "a string",
+            Value::Array(_) => "a list",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    // Full dotted key, `[section]` headers included
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigError {
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(line: Option<usize>, key: Option<&str>, message: String) -> ConfigError {
+        ConfigError {
+            line,
+            key: key.map(|k| k.to_string()),
+            message,
+        }
+    }
+}
+
+// All the problems found in one config file.
+#[derive(Debug)]
+pub struct ConfigErrors {
+    pub path: PathBuf,
+    pub errors: Vec<ConfigError>,
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.errors {
+            write!(f, "{}", self.path.display())?;
+            if let Some(line) = e.line {
+                write!(f, ":{}", line)?;
+            }
+            write!(f, ": ")?;
+            if let Some(key) = &e.key {
+                write!(f, "{}: ", key)?;
+            }
+            writeln!(f, "{}", e.message)?;
+        }
+        Ok(())
+    }
+}
+
+struct Cursor<'a> {
+    rest: &'a str,
+    depth: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn skip_ws(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.rest.starts_with(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_ws();
+
+        if self.eat('"') {
+            return self.string().map(Value::Str);
+        }
+
+        if self.eat('[') {
+            if self.depth >= 8 {
+                return Err("lists are nested too deeply".to_string());
+            }
+            self.depth += 1;
+            let mut items = Vec::new();
+            loop {
+                self.skip_ws();
+                if self.eat(']') {
+                    self.depth -= 1;
+                    return Ok(Value::Array(items));
+                }
+                items.push(self.value()?);
+                self.skip_ws();
+                if !self.eat(',') {
+                    self.skip_ws();
+                    if self.eat(']') {
+                        self.depth -= 1;
+                        return Ok(Value::Array(items));
+                    }
+                    return Err(
+                        "expected `,` or `]` in list (lists must fit on one line)".to_string()
+                    );
+                }
+            }
+        }
+
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+'))
+            .unwrap_or(self.rest.len());
+        let word = &self.rest[..end];
+        self.rest = &self.rest[end..];
+
+        match word {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "" => {
+                Err("expected a value: true/false, a number, a \"string\" or a [list]".to_string())
+            }
+            _ => word
+                .replace('_', "")
+                .parse::<i64>()
+                .map(Value::Int)
+                .map_err(|_| {
+                    format!(
+                        "`{}` is not a value. strings need quotes, e.g. \"{}\"",
+                        word, word
+                    )
+                }),
+        }
+    }
+
+    // Called after the opening quote
+    fn string(&mut self) -> Result<String, String> {
+        let mut out = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(out);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, '"')) => out.push('"'),
+                    Some((_, '\\')) => out.push('\\'),
+                    Some((_, c)) => return Err(format!("unknown escape `\\{}` in string", c)),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        Err("string is missing its closing quote".to_string())
+    }
+}
+
+fn is_bare_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Drops a trailing comment, minding `#` inside strings.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Returns every entry that parsed along with errors for the lines that
+// didn't, so a single run can report all of a file's problems.
+pub fn parse(text: &str) -> (Vec<Entry>, Vec<ConfigError>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut section = String::new();
+
+    for (i, raw) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            match name.strip_suffix(']').map(str::trim) {
+                Some(name) if name.split('.').all(is_bare_key) => section = name.to_string(),
+                _ => errors.push(ConfigError::new(
+                    Some(line_no),
+                    None,
+                    format!("bad section header `{}`", line),
+                )),
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v),
+            None => {
+                errors.push(ConfigError::new(
+                    Some(line_no),
+                    None,
+                    format!("expected `key = value`, found `{}`", line),
+                ));
+                continue;
+            }
+        };
+
+        if !is_bare_key(key) {
+            errors.push(ConfigError::new(
+                Some(line_no),
+                None,
+                format!("bad key `{}`, keys are letters, digits, `_` and `-`", key),
+            ));
+            continue;
+        }
+
+        let full_key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", section, key)
+        };
+
+        match parse_value(value) {
+            Ok(value) => entries.push(Entry {
+                key: full_key,
+                value,
+                line: line_no,
+            }),
+            Err(msg) => errors.push(ConfigError::new(Some(line_no), Some(&full_key), msg)),
+        }
+    }
+
+    (entries, errors)
+}
+
+// One whole value, with nothing after it
+fn parse_value(text: &str) -> Result<Value, String> {
+    let mut cursor = Cursor {
+        rest: text,
+        depth: 0,
+    };
+    let value = cursor.value()?;
+    cursor.skip_ws();
+    if cursor.rest.is_empty() {
+        Ok(value)
+    } else {
+        Err(format!("unexpected `{}` after the value", cursor.rest))
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+fn unknown_key_message(key: &str) -> String {
+    let best = OPTIONS
+        .iter()
+        .map(|(k, _)| (edit_distance(key, k), k))
+        .min()
+        .filter(|(d, _)| *d <= 3);
+    match best {
+        Some((_, k)) => format!("unknown key, did you mean `{}`?", k),
+        None => "unknown key".to_string(),
+    }
+}
+
+fn expect_bool(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        v => Err(format!("must be true or false, found {}", v.type_name())),
+    }
+}
+
+fn expect_int(value: &Value, min: i64, max: i64, unit: &str) -> Result<i64, String> {
+    match value {
+        Value::Int(n) if *n < min || *n > max => Err(format!(
+            "must be between {} and {} {}, found {}",
+            min, max, unit, n
+        )),
+        Value::Int(n) => Ok(*n),
+        v => Err(format!(
+            "must be a number of {}, found {}",
+            unit,
+            v.type_name()
+        )),
+    }
+}
+
+fn expect_string(value: &Value) -> Result<String, String> {
+    match value {
+        Value::Str(s) => Ok(s.clone()),
+        v => Err(format!("must be a \"string\", found {}", v.type_name())),
+    }
+}
+
+// "#rrggbb" or "#rrggbbaa", kept as 0xAARRGGBB
+fn expect_color(value: &Value) -> Result<u32, String> {
+    let s = expect_string(value)?;
+    let hex = s.strip_prefix('#').unwrap_or("");
+    let n = u32::from_str_radix(hex, 16).ok().filter(|_| hex.is_ascii());
+    match (hex.len(), n) {
+        (6, Some(n)) => Ok(0xff00_0000 | n),
+        (8, Some(n)) => Ok(n >> 8 | (n & 0xff) << 24),
+        _ => Err(format!(
+            "must be a color like \"#ff3030\" or \"#ff3030cc\", found \"{}\"",
+            s
+        )),
+    }
+}
+
+fn expect_drag_action(value: &Value) -> Result<DragAction, String> {
+    let s = expect_string(value)?;
+    DragAction::parse(&s).ok_or_else(|| format!("must be \"drag\" or \"scroll\", found \"{}\"", s))
+}
+
+fn expect_string_list(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::Array(items) => items.iter().map(expect_string).collect(),
+        v => Err(format!(
+            "must be a list of strings, found {}",
+            v.type_name()
+        )),
+    }
+}
+
+fn expect_int_list(value: &Value, min: i64, max: i64, unit: &str) -> Result<Vec<i64>, String> {
+    match value {
+        Value::Array(items) => items
+            .iter()
+            .map(|item| expect_int(item, min, max, unit))
+            .collect(),
+        v => Err(format!(
+            "must be a list of numbers, found {}",
+            v.type_name()
+        )),
+    }
+}
+
+fn apply(config: &mut DwellConfig, entry: &Entry) -> Result<(), String> {
+    let v = &entry.value;
+    match entry.key.as_str() {
+        "min_movement_pixels" => {
+            config.min_movement_pixels = expect_int(v, 0, 1000, "pixels")? as u32
+        }
+        "min_movement_mm" => {
+            config.min_movement_mm = match expect_int(v, 0, 100, "millimeters")? {
+                0 => None,
+                mm => Some(mm as u32),
+            }
+        }
+        "jump_pixels" => {
+            config.jump_pixels = match expect_int(v, 0, 10000, "pixels")? {
+                0 => None,
+                pixels => Some(pixels as u32),
+            }
+        }
+        "jump_arm_delay" => {
+            config.jump_arm_delay =
+                Duration::from_millis(expect_int(v, 0, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "precision_mode" => config.precision_mode = expect_bool(v)?,
+        "precision_speed" => config.precision_speed = expect_int(v, -100, 100, "percent")? as i32,
+        "dwell_time" => {
+            config.dwell_time =
+                Duration::from_millis(expect_int(v, 1, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "right_dwell_time" | "double_dwell_time" => {
+            let time = match expect_int(v, 0, MAX_TIME_MS, "milliseconds")? {
+                0 => None,
+                ms => Some(Duration::from_millis(ms as u64)),
+            };
+            if entry.key == "right_dwell_time" {
+                config.right_dwell_time = time;
+            } else {
+                config.double_dwell_time = time;
+            }
+        }
+        "drag_time" => {
+            config.drag_time =
+                Duration::from_millis(expect_int(v, 1, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "drag_enabled" => config.drag_enabled = expect_bool(v)?,
+        "drag_cooldown" => {
+            config.drag_cooldown =
+                Duration::from_millis(expect_int(v, 0, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "drag_vertical" => config.drag_vertical = expect_drag_action(v)?,
+        "drag_horizontal" => config.drag_horizontal = expect_drag_action(v)?,
+        "scroll_step" => config.scroll_step = expect_int(v, 1, 500, "pixels")? as u32,
+        "scroll_clicks" => config.scroll_clicks = expect_int(v, 1, 20, "clicks")? as u32,
+        "natural_scroll" => config.natural_scroll = expect_bool(v)?,
+        "scroll_repeat" => {
+            config.scroll_repeat = match expect_int(v, 0, MAX_TIME_MS, "milliseconds")? {
+                0 => None,
+                ms => Some(Duration::from_millis(ms as u64)),
+            }
+        }
+        "gesture_l" => config.gesture_l = expect_string(v)?,
+        "gesture_circle" => config.gesture_circle = expect_string(v)?,
+        "gesture_zigzag" => config.gesture_zigzag = expect_string(v)?,
+        "sound_enabled" => config.sound_enabled = expect_bool(v)?,
+        "click_sound" => config.click_sound = expect_bool(v)?,
+        "drag_start_sound" => config.drag_start_sound = expect_bool(v)?,
+        "drag_end_sound" => config.drag_end_sound = expect_bool(v)?,
+        "click_type_sound" => config.click_type_sound = expect_bool(v)?,
+        "sound_file" => config.sound_file = expect_string(v)?,
+        "sound_theme" => config.sound_theme = expect_string(v)?,
+        "led_click" => config.led_click = expect_string(v)?,
+        "led_enabled" => config.led_enabled = expect_string(v)?,
+        "sound_pitch" => config.sound_pitch = expect_int(v, 100, 5000, "Hz")? as u32,
+        "sound_duration" => {
+            config.sound_duration =
+                Duration::from_millis(expect_int(v, 5, 1000, "milliseconds")? as u64)
+        }
+        "write_status_file" => config.write_status_file = expect_bool(v)?,
+        "status_file" => config.status_file = expect_string(v)?,
+        "control_socket" => config.control_socket = expect_string(v)?,
+        "pid_file" => config.pid_file = expect_string(v)?,
+        "log_file" => config.log_file = expect_string(v)?,
+        "send_event_classes" => config.send_event_classes = expect_string_list(v)?,
+        "keyboard_classes" => config.keyboard_classes = expect_string_list(v)?,
+        "keyboard_dwell" => {
+            let s = expect_string(v)?;
+            config.keyboard_dwell = KeyboardDwell::parse(&s).ok_or_else(|| {
+                format!("must be \"normal\", \"quick\" or \"off\", found \"{}\"", s)
+            })?
+        }
+        "keyboard_dwell_time" => {
+            config.keyboard_dwell_time =
+                Duration::from_millis(expect_int(v, 1, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "primary_button" => {
+            config.primary_button = match expect_int(v, 0, 255, "button")? {
+                0 => None,
+                button => Some(button as u8),
+            }
+        }
+        "max_drag_duration" => {
+            config.max_drag_duration =
+                Duration::from_millis(expect_int(v, 1000, MAX_LIMIT_MS, "milliseconds")? as u64)
+        }
+        "release_stale_buttons" => config.release_stale_buttons = expect_bool(v)?,
+        "idle_inhibit" => config.idle_inhibit = expect_bool(v)?,
+        "stuck_drag_limit" => {
+            config.stuck_drag_limit =
+                Duration::from_millis(expect_int(v, 1000, MAX_LIMIT_MS, "milliseconds")? as u64)
+        }
+        "disabled" => config.disabled = expect_bool(v)?,
+        "auto_disable_minutes" => {
+            config.auto_disable_after = match expect_int(v, 0, 24 * 60, "minutes")? {
+                0 => None,
+                n => Some(Duration::from_secs(n as u64 * 60)),
+            }
+        }
+        "wake_movement_pixels" => {
+            config.wake_movement_pixels = expect_int(v, 1, 10_000, "pixels")? as u32
+        }
+        "battery_tick_interval" => {
+            config.battery_tick_interval =
+                Duration::from_millis(expect_int(v, 100, 1000, "milliseconds")? as u64)
+        }
+        "anchor_dot" => config.anchor_dot = expect_bool(v)?,
+        "anchor_dot_color" => config.anchor_dot_color = expect_color(v)?,
+        "anchor_dot_radius" => config.anchor_dot_radius = expect_int(v, 1, 50, "pixels")? as u32,
+        "drag_bar" => config.drag_bar = expect_bool(v)?,
+        "drag_bar_color" => config.drag_bar_color = expect_color(v)?,
+        "drag_bar_width" => config.drag_bar_width = expect_int(v, 2, 500, "pixels")? as u32,
+        "drag_bar_height" => config.drag_bar_height = expect_int(v, 1, 50, "pixels")? as u32,
+        "countdown" => {
+            config.countdown = match expect_string(v)?.as_str() {
+                "off" => Countdown::Off,
+                "ms" => Countdown::Millis,
+                "count" => Countdown::Count,
+                other => {
+                    return Err(format!(
+                        "must be \"off\", \"ms\" or \"count\", found \"{}\"",
+                        other
+                    ))
+                }
+            }
+        }
+        "countdown_color" => config.countdown_color = expect_color(v)?,
+        "overlay_theme" => {
+            let theme = expect_string(v)?;
+            config.overlay_theme = Theme::parse(&theme).ok_or_else(|| {
+                format!(
+                    "must be \"default\" or \"high-contrast\", found \"{}\"",
+                    theme
+                )
+            })?
+        }
+        "click_key" => config.click_key = expect_string(v)?,
+        "focused_window_only" => config.focused_window_only = expect_bool(v)?,
+        "grab_delay" => {
+            config.grab_delay =
+                Duration::from_millis(expect_int(v, 0, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "history_log" => config.history_log = expect_bool(v)?,
+        "monitor_command" => config.monitor_command = expect_string(v)?,
+        "nudge_keys" => config.nudge_keys = expect_bool(v)?,
+        "nudge_step" => config.nudge_step = expect_int(v, 1, 100, "pixels")? as u32,
+        "shake_toggle" => config.shake_toggle = expect_bool(v)?,
+        "shake_reversals" => config.shake_reversals = expect_int(v, 2, 20, "turns")? as u32,
+        "shake_speed" => {
+            config.shake_speed = expect_int(v, 100, 100_000, "pixels per second")? as u32
+        }
+        "shake_time" => {
+            config.shake_time =
+                Duration::from_millis(expect_int(v, 100, MAX_TIME_MS, "milliseconds")? as u64)
+        }
+        "panel" => config.panel = expect_bool(v)?,
+        "panel_position" => {
+            let s = expect_string(v)?;
+            config.panel_position = PanelPosition::parse(&s).ok_or_else(|| {
+                let names: Vec<_> = PANEL_POSITIONS.iter().map(|(_, n)| *n).collect();
+                format!("must be one of {}, found \"{}\"", names.join(", "), s)
+            })?
+        }
+        "panel_orientation" => {
+            config.panel_vertical = match expect_string(v)?.as_str() {
+                "horizontal" => false,
+                "vertical" => true,
+                other => {
+                    return Err(format!(
+                        "must be \"horizontal\" or \"vertical\", found \"{}\"",
+                        other
+                    ))
+                }
+            }
+        }
+        "panel_button_size" => config.panel_button_size = expect_int(v, 24, 400, "pixels")? as u32,
+        "pause_button" => config.pause_button = expect_bool(v)?,
+        "device_profiles" => {
+            config.device_profiles = expect_string_list(v)?
+                .iter()
+                .map(|s| {
+                    DeviceProfile::parse(s)
+                        .ok_or_else(|| format!("must be \"PROFILE DEVICE NAME\", found \"{}\"", s))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        "pause_button_size" => config.pause_button_size = expect_int(v, 24, 1000, "pixels")? as u32,
+        "macro_zones" => {
+            config.macro_zones = expect_string_list(v)?
+                .iter()
+                .map(|s| {
+                    MacroZone::parse(s)
+                        .filter(|z| crate::macros::valid_name(&z.name))
+                        .ok_or_else(|| {
+                            format!("must be \"NAME X Y WIDTH HEIGHT\", found \"{}\"", s)
+                        })
+                })
+                .collect::<Result<_, _>>()?
+        }
+        "monitor_no_clicks_after" => {
+            config.monitor_no_clicks_after = match expect_int(v, 0, 24 * 60, "minutes")? {
+                0 => None,
+                n => Some(Duration::from_secs(n as u64 * 60)),
+            }
+        }
+        "screens" => {
+            config.screens = expect_int_list(v, 0, 255, "screen")?
+                .into_iter()
+                .map(|n| n as u32)
+                .collect()
+        }
+        key => return Err(unknown_key_message(key)),
+    }
+    Ok(())
+}
+
+fn millis(d: Duration) -> Value {
+    Value::Int(d.as_millis() as i64)
+}
+
+fn color(argb: u32) -> Value {
+    Value::Str(format!("#{:06x}{:02x}", argb & 0xffffff, argb >> 24))
+}
+
+fn string_list(items: &[String]) -> Value {
+    Value::Array(items.iter().cloned().map(Value::Str).collect())
+}
+
+// The inverse of apply()
+fn value_of(config: &DwellConfig, key: &str) -> Value {
+    match key {
+        "min_movement_pixels" => Value::Int(config.min_movement_pixels as i64),
+        "min_movement_mm" => Value::Int(config.min_movement_mm.unwrap_or(0) as i64),
+        "jump_pixels" => Value::Int(config.jump_pixels.unwrap_or(0) as i64),
+        "jump_arm_delay" => millis(config.jump_arm_delay),
+        "precision_mode" => Value::Bool(config.precision_mode),
+        "precision_speed" => Value::Int(config.precision_speed as i64),
+        "dwell_time" => millis(config.dwell_time),
+        "right_dwell_time" => config.right_dwell_time.map_or(Value::Int(0), millis),
+        "double_dwell_time" => config.double_dwell_time.map_or(Value::Int(0), millis),
+        "drag_time" => millis(config.drag_time),
+        "drag_enabled" => Value::Bool(config.drag_enabled),
+        "drag_cooldown" => millis(config.drag_cooldown),
+        "drag_vertical" => Value::Str(config.drag_vertical.as_str().to_string()),
+        "drag_horizontal" => Value::Str(config.drag_horizontal.as_str().to_string()),
+        "scroll_step" => Value::Int(config.scroll_step as i64),
+        "scroll_clicks" => Value::Int(config.scroll_clicks as i64),
+        "natural_scroll" => Value::Bool(config.natural_scroll),
+        "scroll_repeat" => config.scroll_repeat.map_or(Value::Int(0), millis),
+        "gesture_l" => Value::Str(config.gesture_l.clone()),
+        "gesture_circle" => Value::Str(config.gesture_circle.clone()),
+        "gesture_zigzag" => Value::Str(config.gesture_zigzag.clone()),
+        "sound_enabled" => Value::Bool(config.sound_enabled),
+        "click_sound" => Value::Bool(config.click_sound),
+        "drag_start_sound" => Value::Bool(config.drag_start_sound),
+        "drag_end_sound" => Value::Bool(config.drag_end_sound),
+        "click_type_sound" => Value::Bool(config.click_type_sound),
+        "sound_file" => Value::Str(config.sound_file.clone()),
+        "sound_theme" => Value::Str(config.sound_theme.clone()),
+        "sound_pitch" => Value::Int(config.sound_pitch as i64),
+        "sound_duration" => millis(config.sound_duration),
+        "led_click" => Value::Str(config.led_click.clone()),
+        "led_enabled" => Value::Str(config.led_enabled.clone()),
+        "write_status_file" => Value::Bool(config.write_status_file),
+        "status_file" => Value::Str(config.status_file.clone()),
+        "control_socket" => Value::Str(config.control_socket.clone()),
+        "pid_file" => Value::Str(config.pid_file.clone()),
+        "log_file" => Value::Str(config.log_file.clone()),
+        "send_event_classes" => string_list(&config.send_event_classes),
+        "keyboard_classes" => string_list(&config.keyboard_classes),
+        "keyboard_dwell" => Value::Str(config.keyboard_dwell.as_str().to_string()),
+        "keyboard_dwell_time" => millis(config.keyboard_dwell_time),
+        "primary_button" => Value::Int(config.primary_button.unwrap_or(0) as i64),
+        "max_drag_duration" => millis(config.max_drag_duration),
+        "stuck_drag_limit" => millis(config.stuck_drag_limit),
+        "release_stale_buttons" => Value::Bool(config.release_stale_buttons),
+        "idle_inhibit" => Value::Bool(config.idle_inhibit),
+        "disabled" => Value::Bool(config.disabled),
+        "auto_disable_minutes" => Value::Int(
+            config
+                .auto_disable_after
+                .map_or(0, |d| d.as_secs() as i64 / 60),
+        ),
+        "wake_movement_pixels" => Value::Int(config.wake_movement_pixels as i64),
+        "battery_tick_interval" => millis(config.battery_tick_interval),
+        "anchor_dot" => Value::Bool(config.anchor_dot),
+        "anchor_dot_color" => color(config.anchor_dot_color),
+        "anchor_dot_radius" => Value::Int(config.anchor_dot_radius as i64),
+        "drag_bar" => Value::Bool(config.drag_bar),
+        "drag_bar_color" => color(config.drag_bar_color),
+        "drag_bar_width" => Value::Int(config.drag_bar_width as i64),
+        "drag_bar_height" => Value::Int(config.drag_bar_height as i64),
+        "countdown" => Value::Str(config.countdown.as_str().to_string()),
+        "countdown_color" => color(config.countdown_color),
+        "overlay_theme" => Value::Str(config.overlay_theme.as_str().to_string()),
+        "click_key" => Value::Str(config.click_key.clone()),
+        "focused_window_only" => Value::Bool(config.focused_window_only),
+        "grab_delay" => millis(config.grab_delay),
+        "history_log" => Value::Bool(config.history_log),
+        "monitor_command" => Value::Str(config.monitor_command.clone()),
+        "nudge_keys" => Value::Bool(config.nudge_keys),
+        "nudge_step" => Value::Int(config.nudge_step as i64),
+        "shake_toggle" => Value::Bool(config.shake_toggle),
+        "shake_reversals" => Value::Int(config.shake_reversals as i64),
+        "shake_speed" => Value::Int(config.shake_speed as i64),
+        "shake_time" => millis(config.shake_time),
+        "panel" => Value::Bool(config.panel),
+        "panel_position" => Value::Str(config.panel_position.as_str().to_string()),
+        "panel_orientation" => Value::Str(
+            if config.panel_vertical {
+                "vertical"
+            } else {
+                "horizontal"
+            }
+            .to_string(),
+        ),
+        "panel_button_size" => Value::Int(config.panel_button_size as i64),
+        "pause_button" => Value::Bool(config.pause_button),
+        "device_profiles" => Value::Array(
+            config
+                .device_profiles
+                .iter()
+                .map(|d| Value::Str(d.to_text()))
+                .collect(),
+        ),
+        "pause_button_size" => Value::Int(config.pause_button_size as i64),
+        "macro_zones" => Value::Array(
+            config
+                .macro_zones
+                .iter()
+                .map(|z| Value::Str(z.to_text()))
+                .collect(),
+        ),
+        "monitor_no_clicks_after" => Value::Int(
+            config
+                .monitor_no_clicks_after
+                .map_or(0, |d| d.as_secs() as i64 / 60),
+        ),
+        "screens" => Value::Array(
+            config
+                .screens
+                .iter()
+                .map(|&n| Value::Int(n as i64))
+                .collect(),
+        ),
+        key => panic!("Error: value_of: no such key {}", key),
+    }
+}
+
+// Serializes every option, optionally with its description above it.
+pub fn to_toml(config: &DwellConfig, comments: bool) -> String {
+    let mut out = String::new();
+    for (key, doc) in OPTIONS {
+        if comments {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            for line in doc.lines() {
+                out.push_str("# ");
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        out.push_str(&format!("{} = {}\n", key, value_of(config, key)));
+    }
+    out
+}
+
+// Where this machine keeps its files, not part of anyone's tuning
+const MACHINE_OPTIONS: &[&str] = &["status_file", "control_socket", "pid_file", "log_file"];
+
+// A profile file for `profile export`: everything name runs with, so it
+// comes out the same on a machine with a different config
+pub fn profile_to_toml(config: &DwellConfig, name: &str) -> String {
+    let mut out = format!(
+        "# rtmouse profile {}, with every option it runs with. Add it to another\n\
+         # config with `rtmouse-rs profile import FILE`.\n\
+         [{}{}]\n",
+        name, PROFILE_SECTION, name
+    );
+    for (key, _) in OPTIONS.iter().filter(|(k, _)| !MACHINE_OPTIONS.contains(k)) {
+        out.push_str(&format!("{} = {}\n", key, value_of(config, key)));
+    }
+    out
+}
+
+// For `profile import`: the config text with the one profile in profile
+// added at the end, and the profile's name. With replace, a profile of the
+// same name already there is taken out first.
+pub fn import_profile(
+    config: &str,
+    profile: &str,
+    replace: bool,
+) -> Result<(String, String), Vec<ConfigError>> {
+    let (entries, errors) = parse(profile);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let fail = |message: String| Err(vec![ConfigError::new(None, None, message)]);
+    let name = match profile_names(&entries).as_slice() {
+        [name] if entries.iter().all(|e| profile_key(&e.key).is_some()) => name.clone(),
+        _ => return fail("expected one [profile.NAME] section and nothing else".to_string()),
+    };
+
+    let (existing, _) = parse(config);
+    let mut text = config.to_string();
+    if profile_names(&existing).contains(&name) {
+        if !replace {
+            return fail(format!(
+                "the config already has a profile {}, pass --force to replace it",
+                name
+            ));
+        }
+        text = without_section(config, &format!("{}{}", PROFILE_SECTION, name));
+    }
+    if !text.is_empty() && !text.ends_with("\n\n") {
+        text.push_str(if text.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+    text.push_str(profile);
+
+    from_str_for(&text, Some(&name), &[])?;
+    Ok((text, name))
+}
+
+// Drops the lines from a [section] header up to the next header, and the
+// comments just above it that go with it
+fn without_section(text: &str, section: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let mut skipping = false;
+    for line in text.lines() {
+        let trimmed = strip_comment(line).trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|n| n.strip_suffix(']')) {
+            skipping = name.trim() == section;
+            while skipping
+                && out
+                    .last()
+                    .is_some_and(|l| strip_comment(l).trim().is_empty())
+            {
+                out.pop();
+            }
+        }
+        if !skipping {
+            out.push(line);
+        }
+    }
+    out.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+pub fn template() -> String {
+    format!(
+        "# rtmouse config. Every option is listed here with its default value.\n\
+         # Delete or change whatever you like, missing options use the default.\n\
+         \n{}",
+        to_toml(&DwellConfig::default(), true)
+    )
+}
+
+// Writes template() to path, creating its directory. Refuses to replace an
+// existing file unless force is set.
+pub fn write_template(path: &Path, force: bool) -> std::io::Result<()> {
+    write_config(path, &template(), force)
+}
+
+// Refuses to replace an existing file unless force
+pub fn write_config(path: &Path, text: &str, force: bool) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .create_new(!force)
+        .open(path)?;
+    std::io::Write::write_all(&mut file, text.as_bytes())
+}
+
+// Checks that need the whole config, or the filesystem.
+// Whether path could be made, without making it or any directories on the
+// way: the file if it's there already, or else the closest directory above
+// it that is. Loading a config doesn't leave files behind.
+fn check_writable(path: &Path) -> io::Result<()> {
+    let existing = path
+        .ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.exists())
+        .unwrap_or_else(|| Path::new("."));
+    if existing != path && !existing.is_dir() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    let c_path = CString::new(existing.as_os_str().as_bytes())?;
+    if unsafe { libc::access(c_path.as_ptr(), libc::W_OK) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+pub fn validate(config: &DwellConfig) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+
+    // log_file is only written with --explain, which isn't in the config,
+    // and enable_explain says so itself when it can't be
+    let mut written = vec![
+        ("control_socket", config.control_socket_path()),
+        ("pid_file", config.pid_path()),
+    ];
+    if config.write_status_file {
+        written.insert(0, ("status_file", config.status_path()));
+    }
+    for (key, path) in written {
+        if let Err(e) = check_writable(&path) {
+            let fix = if key == "status_file" {
+                "pick another path or set write_status_file = false"
+            } else {
+                "pick another path"
+            };
+            errors.push(ConfigError::new(
+                None,
+                Some(key),
+                format!("`{}` can't be written ({}). {}", path.display(), e, fix),
+            ));
+        }
+    }
+
+    if config.sound_enabled && !config.sound_file.is_empty() {
+        if let Err(e) = fs::File::open(&config.sound_file) {
+            errors.push(ConfigError::new(
+                None,
+                Some("sound_file"),
+                format!("`{}` can't be read ({})", config.sound_file, e),
+            ));
+        }
+    }
+
+    #[cfg(feature = "sound")]
+    if config.sound_enabled && !config.sound_theme.is_empty() {
+        if let Err(e) = crate::sound::load_theme(&config.sound_theme) {
+            errors.push(ConfigError::new(None, Some("sound_theme"), e));
+        }
+    }
+
+    errors
+}
+
+const PROFILE_SECTION: &str = "profile.";
+
+// The profile name and option of a key from a [profile.NAME] section
+fn profile_key(key: &str) -> Option<(&str, &str)> {
+    key.strip_prefix(PROFILE_SECTION)?.split_once('.')
+}
+
+// Every profile the entries have a section for, in file order
+pub fn profile_names(entries: &[Entry]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (name, _) in entries.iter().filter_map(|e| profile_key(&e.key)) {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+const ENV_PREFIX: &str = "RTMOUSE_";
+
+// The option an environment variable like RTMOUSE_DWELL_TIME overrides
+fn env_key(var: &str) -> Option<&'static str> {
+    let key = var.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+    OPTIONS.iter().map(|&(k, _)| k).find(|&k| k == key)
+}
+
+// Environment values aren't TOML, so take them as the option expects:
+// strings as they are, and 1/0, yes/no, on/off for bools
+fn env_value(key: &str, raw: &str) -> Value {
+    match value_of(&DwellConfig::default(), key) {
+        Value::Bool(_) => match raw.to_ascii_lowercase().as_str() {
+            "1" | "yes" | "on" | "true" => Value::Bool(true),
+            "0" | "no" | "off" | "false" => Value::Bool(false),
+            _ => Value::Str(raw.to_string()),
+        },
+        Value::Str(_) => Value::Str(raw.to_string()),
+        _ => parse_value(raw).unwrap_or_else(|_| Value::Str(raw.to_string())),
+    }
+}
+
+// The RTMOUSE_ variables that name an option. Others, like
+// RTMOUSE_DEBUG_TIMING, are left alone.
+pub fn env_overrides(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    let mut found: Vec<_> = vars.filter(|(var, _)| env_key(var).is_some()).collect();
+    found.sort();
+    found
+}
+
+// The options at the top, then profile's section over them, then env from
+// env_overrides() over both. The other profiles are checked too, so a typo
+// in one shows up without running it.
+pub fn from_entries(
+    entries: &[Entry],
+    profile: Option<&str>,
+    env: &[(String, String)],
+) -> Result<DwellConfig, Vec<ConfigError>> {
+    let mut config = DwellConfig::default();
+    let mut unused = DwellConfig::default();
+    let mut errors = Vec::new();
+
+    if let Some(name) = profile {
+        if !profile_names(entries).iter().any(|n| n == name) {
+            errors.push(ConfigError::new(
+                None,
+                None,
+                format!("there's no [profile.{}] section", name),
+            ));
+        }
+    }
+
+    let mut overrides = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(first) = entries[..i].iter().find(|e| e.key == entry.key) {
+            errors.push(ConfigError::new(
+                Some(entry.line),
+                Some(&entry.key),
+                format!("set twice, first on line {}", first.line),
+            ));
+            continue;
+        }
+        let result = match profile_key(&entry.key) {
+            // After everything at the top, wherever they are in the file
+            Some(_) => {
+                overrides.push(entry);
+                continue;
+            }
+            None => apply(&mut config, entry),
+        };
+        if let Err(msg) = result {
+            errors.push(ConfigError::new(Some(entry.line), Some(&entry.key), msg));
+        }
+    }
+    for entry in overrides {
+        let (name, key) = profile_key(&entry.key).unwrap_or_default();
+        let target = if profile == Some(name) {
+            &mut config
+        } else {
+            &mut unused
+        };
+        let option = Entry {
+            key: key.to_string(),
+            ..entry.clone()
+        };
+        if let Err(msg) = apply(target, &option) {
+            errors.push(ConfigError::new(Some(entry.line), Some(&entry.key), msg));
+        }
+    }
+    for (var, raw) in env {
+        let key = match env_key(var) {
+            Some(key) => key,
+            None => continue,
+        };
+        let entry = Entry {
+            key: key.to_string(),
+            value: env_value(key, raw),
+            line: 0,
+        };
+        if let Err(msg) = apply(&mut config, &entry) {
+            errors.push(ConfigError::new(None, Some(var), msg));
+        }
+    }
+
+    for mut e in validate(&config) {
+        // Point at whatever set the key, if anything did
+        if let Some((var, _)) = env.iter().find(|(var, _)| env_key(var) == e.key.as_deref()) {
+            e.key = Some(var.clone());
+        } else if let Some(entry) = entries.iter().find(|en| Some(&en.key) == e.key.as_ref()) {
+            e.line = Some(entry.line);
+        }
+        errors.push(e);
+    }
+
+    if errors.is_empty() {
+        Ok(config)
+    } else {
+        Err(errors)
+    }
+}
+
+// $XDG_CONFIG_HOME/rtmouse/config.toml, falling back to ~/.config
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("rtmouse").join("config.toml"))
+}
+
+pub fn load(path: &Path, profile: Option<&str>) -> Result<DwellConfig, ConfigErrors> {
+    let fail = |errors| ConfigErrors {
+        path: path.to_path_buf(),
+        errors,
+    };
+
+    let text = fs::read_to_string(path)
+        .map_err(|e| fail(vec![ConfigError::new(None, None, e.to_string())]))?;
+    from_str_for(&text, profile, &env_overrides(std::env::vars())).map_err(fail)
+}
+
+pub fn from_str(text: &str) -> Result<DwellConfig, Vec<ConfigError>> {
+    from_str_for(text, None, &[])
+}
+
+pub fn from_str_for(
+    text: &str,
+    profile: Option<&str>,
+    env: &[(String, String)],
+) -> Result<DwellConfig, Vec<ConfigError>> {
+    let (entries, mut errors) = parse(text);
+    match from_entries(&entries, profile, env) {
+        Ok(config) if errors.is_empty() => Ok(config),
+        Ok(_) => Err(errors),
+        Err(more) => {
+            errors.extend(more);
+            errors.sort_by_key(|e| e.line);
+            Err(errors)
+        }
+    }
+}
+
+// The config at path if given, otherwise the one at default_path(), or the
+// defaults if there is no file there.
+pub fn load_from(path: Option<&Path>, profile: Option<&str>) -> Result<DwellConfig, ConfigErrors> {
+    match path {
+        Some(path) => load(path, profile),
+        None => load_default(profile),
+    }
+}
+
+// The config at default_path(), or the defaults if there is no file there.
+pub fn load_default(profile: Option<&str>) -> Result<DwellConfig, ConfigErrors> {
+    match (default_path(), profile) {
+        (Some(path), _) if path.exists() => load(&path, profile),
+        // Nothing to find a profile in
+        (_, Some(name)) => Err(ConfigErrors {
+            path: PathBuf::from("(defaults)"),
+            errors: vec![ConfigError::new(
+                None,
+                None,
+                format!("there's no config file to find profile {} in", name),
+            )],
+        }),
+        _ => from_str_for("", None, &env_overrides(std::env::vars())).map_err(|errors| {
+            ConfigErrors {
+                path: PathBuf::from("(defaults)"),
+                errors,
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_value_types() {
+        let (entries, errors) = parse(
+            "# comment\n\
+             a = true\n\
+             b = -1_000 # trailing\n\
+             c = \"x # y \\\"z\\\"\"\n\
+             [sec]\n\
+             d = [\"one\", \"two\",]\n",
+        );
+        assert!(errors.is_empty());
+        let values: Vec<_> = entries
+            .iter()
+            .map(|e| (e.key.as_str(), &e.value, e.line))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("a", &Value::Bool(true), 2),
+                ("b", &Value::Int(-1000), 3),
+                ("c", &Value::Str("x # y \"z\"".to_string()), 4),
+                (
+                    "sec.d",
+                    &Value::Array(vec![
+                        Value::Str("one".to_string()),
+                        Value::Str("two".to_string())
+                    ]),
+                    6
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_values() {
+        let config = from_str(
+            "dwell_time = 800\n\
+             drag_enabled = false\n\
+             write_status_file = false\n\
+             send_event_classes = [\"qemu\"]\n\
+             anchor_dot_color = \"#11223380\"\n\
+             macro_zones = [\"dismiss 0 0 40 20\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.dwell_time, Duration::from_millis(800));
+        assert!(!config.drag_enabled);
+        assert_eq!(config.send_event_classes, vec!["qemu".to_string()]);
+        assert_eq!(config.anchor_dot_color, 0x80112233);
+        assert_eq!(config.macro_zones[0].name, "dismiss");
+        assert_eq!(config.macro_zones[0].rect.width, 40);
+        assert!(from_str("macro_zones = [\"../x 0 0 1 1\"]\n").is_err());
+    }
+
+    #[test]
+    fn device_profiles_match_names() {
+        let config =
+            from_str("device_profiles = [\"head-tracker  SmartNav 4 \", \"ball trackball\"]\n")
+                .unwrap();
+        let head = &config.device_profiles[0];
+        assert_eq!(head.profile, "head-tracker");
+        assert_eq!(head.to_text(), "head-tracker SmartNav 4");
+        assert!(head.matches("NaturalPoint SMARTNAV 4 Pointer"));
+        assert!(!config.device_profiles[1].matches("Logitech USB Optical Mouse"));
+        assert!(from_str("device_profiles = [\"lonely\"]\n").is_err());
+    }
+
+    #[test]
+    fn every_key_is_accepted() {
+        let mut config = DwellConfig::default();
+        for (key, _) in OPTIONS {
+            let entry = Entry {
+                key: key.to_string(),
+                value: Value::Bool(true),
+                line: 1,
+            };
+            let err = apply(&mut config, &entry).err().unwrap_or_default();
+            assert!(!err.starts_with("unknown key"), "{}", key);
+        }
+    }
+
+    #[test]
+    fn errors_name_key_and_line() {
+        let errors = from_str(
+            "write_status_file = false\n\
+             dwell_time = 0\n\
+             drag_enabled = yes\n\
+             dwel_time = 300\n\
+             drag_time = 200\n\
+             drag_time = 300\n",
+        )
+        .unwrap_err();
+        let found: Vec<_> = errors
+            .iter()
+            .map(|e| (e.line, e.key.as_deref(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Some(2),
+                    Some("dwell_time"),
+                    "must be between 1 and 60000 milliseconds, found 0"
+                ),
+                (
+                    Some(3),
+                    Some("drag_enabled"),
+                    "`yes` is not a value. strings need quotes, e.g. \"yes\""
+                ),
+                (
+                    Some(4),
+                    Some("dwel_time"),
+                    "unknown key, did you mean `dwell_time`?"
+                ),
+                (Some(6), Some("drag_time"), "set twice, first on line 5"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validating_leaves_nothing_behind() {
+        let dir = std::env::temp_dir().join(format!("rtmouse-validate-{}", std::process::id()));
+        let path = dir.join("status");
+        let config = DwellConfig {
+            status_file: path.display().to_string(),
+            ..DwellConfig::default()
+        };
+        assert!(validate(&config).is_empty());
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn profiles_override_the_top() {
+        let text = "write_status_file = false\n\
+                    [profile.head]\n\
+                    dwell_time = 1200\n\
+                    [profile.ball]\n\
+                    drag_enabled = nope\n";
+        // ball isn't the one running, its mistake is reported anyway
+        let errors = from_str_for(text, Some("head"), &[]).unwrap_err();
+        assert_eq!(errors[0].key.as_deref(), Some("profile.ball.drag_enabled"));
+        assert_eq!(errors.len(), 1);
+
+        let text = text.replace("nope", "false");
+        let base = from_str(&text).unwrap();
+        assert_eq!(base.dwell_time, Duration::from_millis(500));
+        assert!(base.drag_enabled);
+        let head = from_str_for(&text, Some("head"), &[]).unwrap();
+        assert_eq!(head.dwell_time, Duration::from_millis(1200));
+        assert!(head.drag_enabled);
+        assert!(from_str_for(&text, Some("feet"), &[]).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let vars = [
+            ("RTMOUSE_DWELL_TIME", "900"),
+            ("RTMOUSE_DISABLED", "yes"),
+            ("RTMOUSE_GESTURE_L", "xdotool key super"),
+            ("RTMOUSE_DEBUG_TIMING", "1"),
+            ("HOME", "/home/x"),
+        ];
+        let env = env_overrides(vars.iter().map(|&(k, v)| (k.to_string(), v.to_string())));
+        assert_eq!(env.len(), 3);
+
+        let text = "dwell_time = 500\n[profile.head]\ndwell_time = 700\n";
+        let config = from_str_for(text, Some("head"), &env).unwrap();
+        assert_eq!(config.dwell_time, Duration::from_millis(900));
+        assert!(config.disabled);
+        assert_eq!(config.gesture_l, "xdotool key super");
+
+        let bad = [("RTMOUSE_DRAG_ENABLED".to_string(), "maybe".to_string())];
+        let errors = from_str_for(text, None, &bad).unwrap_err();
+        assert_eq!(errors[0].key.as_deref(), Some("RTMOUSE_DRAG_ENABLED"));
+        assert_eq!(errors[0].line, None);
+    }
+
+    #[test]
+    fn profiles_export_and_import() {
+        let config = DwellConfig {
+            dwell_time: Duration::from_millis(900),
+            write_status_file: false,
+            ..DwellConfig::default()
+        };
+        let exported = profile_to_toml(&config, "clinic");
+        assert!(!exported.contains("pid_file"));
+
+        let mine = "write_status_file = false\ndwell_time = 300\n";
+        let (text, name) = import_profile(mine, &exported, false).unwrap();
+        assert_eq!(name, "clinic");
+        assert!(text.starts_with(mine));
+        assert_eq!(
+            from_str(&text).unwrap().dwell_time,
+            Duration::from_millis(300)
+        );
+        let clinic = from_str_for(&text, Some("clinic"), &[]).unwrap();
+        assert_eq!(clinic.dwell_time, Duration::from_millis(900));
+
+        assert!(import_profile(&text, &exported, false).is_err());
+        let again = exported.replace("dwell_time = 900", "dwell_time = 950");
+        let (text, _) = import_profile(&text, &again, true).unwrap();
+        let clinic = from_str_for(&text, Some("clinic"), &[]).unwrap();
+        assert_eq!(clinic.dwell_time, Duration::from_millis(950));
+
+        assert!(import_profile(mine, "dwell_time = 900\n", false).is_err());
+    }
+
+    #[test]
+    fn template_round_trips_to_defaults() {
+        let template = template();
+        let config = from_str(&template).unwrap();
+        assert_eq!(
+            to_toml(&config, false),
+            to_toml(&DwellConfig::default(), false)
+        );
+        for (key, _) in OPTIONS {
+            assert!(template.contains(&format!("\n{} = ", key)), "{}", key);
+        }
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let config = DwellConfig {
+            status_file: "/tmp/a \"b\" \\ c".to_string(),
+            send_event_classes: vec!["x".to_string(), "y\tz".to_string()],
+            ..DwellConfig::default()
+        };
+        let back = from_str(&to_toml(&config, false)).unwrap();
+        assert_eq!(back.status_file, config.status_file);
+        assert_eq!(back.send_event_classes, config.send_event_classes);
+    }
+
+    #[test]
+    fn unwritable_status_file_is_reported() {
+        let errors = from_str("status_file = \"/dev/null/dir/status\"\n").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].key.as_deref(), Some("status_file"));
+        assert_eq!(errors[0].line, Some(1));
+    }
+
+    #[test]
+    fn empty_paths_mean_the_runtime_dir() {
+        let config = from_str("write_status_file = false\npid_file = \"/tmp/x.pid\"\n").unwrap();
+        assert_eq!(
+            config.control_socket_path(),
+            paths::runtime_dir().join("control.sock")
+        );
+        assert_eq!(config.status_path(), paths::runtime_dir().join("status"));
+        assert_eq!(config.pid_path(), PathBuf::from("/tmp/x.pid"));
+    }
+}
@@ -0,0 +1,383 @@
+// The control socket. The running daemon listens on control_socket_path()
+// and client commands like `rtmouse-rs status` connect to it, send one
+// request line and read the reply until the daemon hangs up. Replies are
+// `key value` lines so they're easy to read with socat too.
+//
+// `subscribe` is the exception: after the status reply the connection
+// stays open and gets a line for every click and state change, see
+// broadcast.
+//
+// For X clients that would rather not open a socket there's also a
+// ClientMessage protocol, see from_client_message.
+
+use crate::config::Theme;
+use crate::dwell::ClickType;
+use crate::macros;
+use crate::paths;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::raw::c_long;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Status,
+    Enable,
+    Disable,
+    Toggle,
+    ClickType(ClickType),
+    Theme(Theme),
+    // A sound theme's name or directory, or none
+    SoundTheme(String),
+    // Starts clicks_today over, for whoever checks on the user
+    ResetClicks,
+    // Click macros by name, see macros.rs
+    RecordMacro(String),
+    StopMacro,
+    PlayMacro(String),
+    // Keeps the connection for events afterwards
+    Subscribe,
+}
+
+impl Request {
+    pub fn to_line(&self) -> String {
+        match self {
+            Request::Status => "status".to_string(),
+            Request::Enable => "enable".to_string(),
+            Request::Disable => "disable".to_string(),
+            Request::Toggle => "toggle".to_string(),
+            Request::ClickType(click) => format!("click-type {}", click.as_str()),
+            Request::Theme(theme) => format!("theme {}", theme.as_str()),
+            Request::SoundTheme(name) => format!("sound-theme {}", name),
+            Request::ResetClicks => "reset-clicks".to_string(),
+            Request::RecordMacro(name) => format!("macro record {}", name),
+            Request::StopMacro => "macro stop".to_string(),
+            Request::PlayMacro(name) => format!("macro play {}", name),
+            Request::Subscribe => "subscribe".to_string(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Request> {
+        let mut words = line.split_whitespace();
+        if let Some(rest) = line.trim().strip_prefix("macro ") {
+            return Request::parse_macro(rest);
+        }
+        let request = match (words.next()?, words.next()) {
+            ("status", None) => Request::Status,
+            ("enable", None) => Request::Enable,
+            ("disable", None) => Request::Disable,
+            ("toggle", None) => Request::Toggle,
+            ("click-type", Some(click)) => Request::ClickType(ClickType::parse(click)?),
+            ("theme", Some(theme)) => Request::Theme(Theme::parse(theme)?),
+            ("sound-theme", Some(name)) => Request::SoundTheme(name.to_string()),
+            ("reset-clicks", None) => Request::ResetClicks,
+            ("subscribe", None) => Request::Subscribe,
+            _ => return None,
+        };
+        if words.next().is_some() {
+            return None;
+        }
+        Some(request)
+    }
+
+    fn parse_macro(line: &str) -> Option<Request> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["record", name] if macros::valid_name(name) => {
+                Some(Request::RecordMacro(name.to_string()))
+            }
+            ["stop"] => Some(Request::StopMacro),
+            ["play", name] if macros::valid_name(name) => {
+                Some(Request::PlayMacro(name.to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    // A ClientMessage of type CLIENT_MESSAGE_ATOM sent to the root window,
+    // format 32. data.l[0] is the request, data.l[1] its argument:
+    //
+    //   1 enable, 2 disable, 3 toggle
+    //   4 click type: 0 left, 1 right, 2 double, 3 drag, 4 gesture
+    //   5 theme: 0 default, 1 high-contrast
+    //   6 reset the clicks_today count
+    //
+    // There is no reply, so there's no status request either. Nor macros or
+    // sound themes, their names don't fit.
+    pub fn from_client_message(longs: &[c_long]) -> Option<Request> {
+        let arg = *longs.get(1)?;
+        let request = match *longs.first()? {
+            1 => Request::Enable,
+            2 => Request::Disable,
+            3 => Request::Toggle,
+            4 => Request::ClickType(*CLICK_TYPES.get(arg as usize)?),
+            5 => Request::Theme(*THEMES.get(arg as usize)?),
+            6 => Request::ResetClicks,
+            _ => return None,
+        };
+        Some(request)
+    }
+}
+
+pub const CLIENT_MESSAGE_ATOM: &str = "_RTMOUSE_CONTROL";
+
+// In client message order
+const CLICK_TYPES: [ClickType; 5] = [
+    ClickType::Left,
+    ClickType::Right,
+    ClickType::Double,
+    ClickType::Drag,
+    ClickType::Gesture,
+];
+const THEMES: [Theme; 2] = [Theme::Default, Theme::HighContrast];
+
+// A slow or stuck client must not hold up the tick loop for long
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub fn listen_at(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another rtmouse is already running",
+            ));
+        }
+        // Left behind by an rtmouse that didn't exit cleanly
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    // Whoever can connect can turn clicking off, so it's ours only even when
+    // the config puts it somewhere shared
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+pub fn listen(path: &Path) -> io::Result<UnixListener> {
+    paths::ensure_dir_for(path)?;
+    listen_at(path)
+}
+
+// The stream back if it subscribed
+fn serve_one(
+    stream: UnixStream,
+    handle: &mut dyn FnMut(Request) -> String,
+) -> io::Result<Option<UnixStream>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).take(256).read_line(&mut line)?;
+    let request = Request::parse(&line);
+    let reply = match request.clone() {
+        Some(request) => handle(request),
+        None => format!("error unknown request `{}`\n", line.trim()),
+    };
+    (&stream).write_all(reply.as_bytes())?;
+    if request != Some(Request::Subscribe) {
+        return Ok(None);
+    }
+    stream.set_nonblocking(true)?;
+    Ok(Some(stream))
+}
+
+// Answers every client that's waiting, without blocking when none are.
+// Returns the ones that subscribed, for broadcast.
+pub fn poll(listener: &UnixListener, mut handle: impl FnMut(Request) -> String) -> Vec<UnixStream> {
+    let mut subscribed = Vec::new();
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // The client going away halfway is its own problem
+                if let Ok(Some(stream)) = serve_one(stream, &mut handle) {
+                    subscribed.push(stream);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+            Err(e) => {
+                eprintln!("Error: control socket: {}", e);
+                break;
+            }
+        }
+    }
+    subscribed
+}
+
+// A line to every subscriber. The writes don't block, so a subscriber that
+// stops reading is dropped once its buffer fills rather than holding up
+// the tick loop, and one that hung up is dropped at the next line.
+pub fn broadcast(subscribers: &mut Vec<UnixStream>, line: &str) {
+    subscribers.retain(|stream| {
+        let mut stream = stream;
+        stream.write_all(line.as_bytes()).is_ok()
+    });
+}
+
+pub fn send_to(path: &Path, request: Request) -> io::Result<Vec<(String, String)>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    writeln!(stream, "{}", request.to_line())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+
+    let fields: Vec<(String, String)> = reply
+        .lines()
+        .map(|l| match l.split_once(' ') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (l.to_string(), String::new()),
+        })
+        .collect();
+    if let Some((_, message)) = fields.iter().find(|(k, _)| k == "error") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message.clone()));
+    }
+    Ok(fields)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Numbers and booleans stay unquoted so scripts don't have to convert them.
+pub fn to_json(fields: &[(String, String)]) -> String {
+    let members: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| {
+            let value = if v == "true" || v == "false" || v.parse::<i64>().is_ok() {
+                v.clone()
+            } else {
+                json_string(v)
+            };
+            format!("{}: {}", json_string(k), value)
+        })
+        .collect();
+    format!("{{{}}}", members.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rtmouse-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        let listener = listen_at(&path).unwrap();
+        assert!(listen_at(&path).is_err());
+
+        let client = {
+            let path = path.clone();
+            std::thread::spawn(move || send_to(&path, Request::Status))
+        };
+        while !client.is_finished() {
+            let subscribed = poll(&listener, |request| {
+                assert_eq!(request, Request::Status);
+                "state enabled\ndecision idle 200/500ms\n".to_string()
+            });
+            assert!(subscribed.is_empty());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let fields = client.join().unwrap().unwrap();
+
+        assert_eq!(
+            fields,
+            vec![
+                ("state".to_string(), "enabled".to_string()),
+                ("decision".to_string(), "idle 200/500ms".to_string()),
+            ]
+        );
+
+        // A subscriber gets the reply, then every line broadcast until it
+        // hangs up
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(client, "subscribe").unwrap();
+        let mut subscribers = Vec::new();
+        while subscribers.is_empty() {
+            subscribers = poll(&listener, |_| "state enabled\n".to_string());
+        }
+        broadcast(&mut subscribers, "state disabled\n");
+        let mut lines = BufReader::new(&client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "state enabled");
+        assert_eq!(lines.next().unwrap().unwrap(), "state disabled");
+        drop(lines);
+        drop(client);
+        broadcast(&mut subscribers, "state enabled\n");
+        assert!(subscribers.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_requests() {
+        for r in &[
+            Request::Status,
+            Request::Enable,
+            Request::Disable,
+            Request::Toggle,
+            Request::ClickType(ClickType::Double),
+            Request::Theme(Theme::HighContrast),
+            Request::SoundTheme("soft".to_string()),
+            Request::ResetClicks,
+            Request::RecordMacro("ok-dialog".to_string()),
+            Request::StopMacro,
+            Request::PlayMacro("ok-dialog".to_string()),
+            Request::Subscribe,
+        ] {
+            assert_eq!(
+                Request::parse(&format!("{}\n", r.to_line())),
+                Some(r.clone())
+            );
+        }
+        assert_eq!(Request::parse("macro play ../../x"), None);
+        assert_eq!(Request::parse("explode"), None);
+        assert_eq!(Request::parse("click-type middle"), None);
+        assert_eq!(Request::parse("status now"), None);
+    }
+
+    #[test]
+    fn parses_client_messages() {
+        for (longs, r) in &[
+            ([1, 0, 0, 0, 0], Request::Enable),
+            ([2, 0, 0, 0, 0], Request::Disable),
+            ([3, 0, 0, 0, 0], Request::Toggle),
+            ([4, 0, 0, 0, 0], Request::ClickType(ClickType::Left)),
+            ([4, 3, 0, 0, 0], Request::ClickType(ClickType::Drag)),
+            ([4, 4, 0, 0, 0], Request::ClickType(ClickType::Gesture)),
+            ([5, 1, 0, 0, 0], Request::Theme(Theme::HighContrast)),
+            ([6, 0, 0, 0, 0], Request::ResetClicks),
+        ] {
+            assert_eq!(Request::from_client_message(longs), Some(r.clone()));
+        }
+        assert_eq!(Request::from_client_message(&[4, 7, 0, 0, 0]), None);
+        assert_eq!(Request::from_client_message(&[4, -1, 0, 0, 0]), None);
+        assert_eq!(Request::from_client_message(&[0, 0, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn json_keeps_numbers_and_bools_bare() {
+        let fields = vec![
+            ("pid".to_string(), "42".to_string()),
+            ("dragging".to_string(), "false".to_string()),
+            ("decision".to_string(), "say \"hi\"".to_string()),
+        ];
+        assert_eq!(
+            to_json(&fields),
+            r#"{"pid": 42, "dragging": false, "decision": "say \"hi\""}"#
+        );
+    }
+}
@@ -0,0 +1,177 @@
+// `rtmouse diagnose`: everything we'd ask for in a bug report, in one file,
+// with the user's name, home directory and host name taken out.
+
+use crate::config;
+use std::ffi::CStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::ptr::null;
+use x11::{xinput2, xlib};
+
+// How many lines of the --explain trace to include
+const TRACE_LINES: usize = 200;
+
+fn x_section(out: &mut String) {
+    let display = unsafe { xlib::XOpenDisplay(null()) };
+    if display.is_null() {
+        out.push_str("could not open the display\n");
+        return;
+    }
+
+    unsafe {
+        let vendor = CStr::from_ptr(xlib::XServerVendor(display)).to_string_lossy();
+        out.push_str(&format!(
+            "server: {} {}\n",
+            vendor,
+            xlib::XVendorRelease(display)
+        ));
+
+        out.push_str("extensions:\n");
+        let mut n = 0;
+        let list = xlib::XListExtensions(display, &mut n);
+        if !list.is_null() {
+            let mut names: Vec<String> = (0..n as usize)
+                .map(|i| CStr::from_ptr(*list.add(i)).to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            for name in names {
+                out.push_str(&format!("  {}\n", name));
+            }
+            xlib::XFreeExtensionList(list);
+        }
+
+        out.push_str("input devices:\n");
+        let mut n = 0;
+        let devices = xinput2::XIQueryDevice(display, xinput2::XIAllDevices, &mut n);
+        if devices.is_null() {
+            out.push_str("  (XInput2 not available)\n");
+        } else {
+            for i in 0..n as usize {
+                let dev = &*devices.add(i);
+                let kind = match dev._use {
+                    xinput2::XIMasterPointer => "master pointer",
+                    xinput2::XIMasterKeyboard => "master keyboard",
+                    xinput2::XISlavePointer => "pointer",
+                    xinput2::XISlaveKeyboard => "keyboard",
+                    xinput2::XIFloatingSlave => "floating",
+                    _ => "unknown",
+                };
+                out.push_str(&format!(
+                    "  {:3} {:16} {}{}\n",
+                    dev.deviceid,
+                    kind,
+                    CStr::from_ptr(dev.name).to_string_lossy(),
+                    if dev.enabled != 0 { "" } else { " (disabled)" }
+                ));
+            }
+            xinput2::XIFreeDeviceInfo(devices);
+        }
+
+        xlib::XCloseDisplay(display);
+    }
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+}
+
+// Replaces every occurrence of the given secrets, longest first so a home
+// directory containing the user name goes before the name does.
+pub fn redact(text: &str, secrets: &[(String, &str)]) -> String {
+    let mut secrets: Vec<&(String, &str)> = secrets.iter().filter(|(s, _)| s.len() > 1).collect();
+    secrets.sort_by_key(|(s, _)| std::cmp::Reverse(s.len()));
+
+    let mut out = text.to_string();
+    for (secret, replacement) in secrets {
+        out = out.replace(secret.as_str(), replacement);
+    }
+    out
+}
+
+// With --config and --profile, like rtmouse itself
+pub fn report(config_path: Option<&Path>, profile: Option<&str>) -> String {
+    let mut out = String::new();
+
+    out.push_str(&format!("rtmouse {}\n\n", env!("CARGO_PKG_VERSION")));
+
+    out.push_str("== session ==\n");
+    for var in &[
+        "XDG_SESSION_TYPE",
+        "XDG_CURRENT_DESKTOP",
+        "DISPLAY",
+        "WAYLAND_DISPLAY",
+    ] {
+        out.push_str(&format!(
+            "{}={}\n",
+            var,
+            std::env::var(var).unwrap_or_default()
+        ));
+    }
+
+    out.push_str("\n== config ==\n");
+    let config = match config::load_from(config_path, profile) {
+        Ok(c) => {
+            out.push_str(&config::to_toml(&c, false));
+            c
+        }
+        Err(e) => {
+            out.push_str(&e.to_string());
+            config::DwellConfig::default()
+        }
+    };
+
+    out.push_str("\n== X server ==\n");
+    x_section(&mut out);
+
+    out.push_str(&format!("\n== last {} trace lines ==\n", TRACE_LINES));
+    match fs::read_to_string(config.log_path()) {
+        Ok(trace) => {
+            let lines: Vec<&str> = trace.lines().collect();
+            for line in &lines[lines.len().saturating_sub(TRACE_LINES)..] {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        Err(_) => out.push_str("no trace, run rtmouse with --explain to record one\n"),
+    }
+
+    let mut secrets = Vec::new();
+    if let Ok(home) = std::env::var("HOME") {
+        secrets.push((home, "~"));
+    }
+    if let Ok(user) = std::env::var("USER") {
+        secrets.push((user, "<user>"));
+    }
+    if let Some(host) = hostname() {
+        secrets.push((host, "<host>"));
+    }
+    redact(&out, &secrets)
+}
+
+// Writes the report to rtmouse-diagnose.txt in the current directory.
+pub fn run(config_path: Option<&Path>, profile: Option<&str>) -> std::io::Result<PathBuf> {
+    let path = PathBuf::from("rtmouse-diagnose.txt");
+    fs::write(&path, report(config_path, profile))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_home_before_user() {
+        let secrets = vec![
+            ("alice".to_string(), "<user>"),
+            ("/home/alice".to_string(), "~"),
+            ("box".to_string(), "<host>"),
+        ];
+        assert_eq!(
+            redact("/home/alice/x alice@box", &secrets),
+            "~/x <user>@<host>"
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// `rtmouse doctor`: goes through what rtmouse needs from the system and says
+// what's missing and how to get it. The first thing to ask someone to run
+// when rtmouse won't start or won't click.
+
+use crate::config::{self, DwellConfig};
+use crate::paths;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use std::ptr::null;
+use x11::{xinput2, xlib, xtest};
+
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    // What was found, shown either way
+    pub detail: String,
+    // What to do about it, only shown when the check failed
+    pub hint: &'static str,
+}
+
+fn pass(name: &'static str, detail: String) -> Check {
+    Check {
+        name,
+        ok: true,
+        detail,
+        hint: "",
+    }
+}
+
+fn fail(name: &'static str, detail: String, hint: &'static str) -> Check {
+    Check {
+        name,
+        ok: false,
+        detail,
+        hint,
+    }
+}
+
+// From XDG_SESSION_TYPE, WAYLAND_DISPLAY and DISPLAY. Under Wayland with
+// XWayland the display opens fine, but clicks only ever reach X programs.
+pub fn session_check(session_type: &str, wayland: bool, display: bool) -> Check {
+    let is_wayland = session_type == "wayland" || (session_type.is_empty() && wayland);
+    match (is_wayland, display) {
+        (false, true) => pass("session", session_or_x11(session_type).to_string()),
+        (true, true) => fail(
+            "session",
+            "wayland, with XWayland".to_string(),
+            "clicks only reach X programs, log into an Xorg session to click everything",
+        ),
+        (true, false) => fail(
+            "session",
+            "wayland, no DISPLAY".to_string(),
+            "rtmouse needs X, log into an Xorg session",
+        ),
+        (false, false) => fail(
+            "session",
+            "DISPLAY isn't set".to_string(),
+            "run rtmouse from inside your X session, or set DISPLAY=:0",
+        ),
+    }
+}
+
+fn session_or_x11(session_type: &str) -> &str {
+    if session_type.is_empty() {
+        "x11"
+    } else {
+        session_type
+    }
+}
+
+fn x_checks(out: &mut Vec<Check>) {
+    let display = unsafe { xlib::XOpenDisplay(null()) };
+    if display.is_null() {
+        out.push(fail(
+            "display",
+            format!(
+                "could not open `{}`",
+                std::env::var("DISPLAY").unwrap_or_default()
+            ),
+            "check DISPLAY, and that xhost or XAUTHORITY lets you in",
+        ));
+        return;
+    }
+    out.push(pass(
+        "display",
+        std::env::var("DISPLAY").unwrap_or_default(),
+    ));
+
+    unsafe {
+        let (mut opcode, mut event, mut error) = (0, 0, 0);
+        let ext = CString::new("XInputExtension").unwrap();
+        let has_xi =
+            xlib::XQueryExtension(display, ext.as_ptr(), &mut opcode, &mut event, &mut error) != 0;
+        let (mut major, mut minor) = (2, 0);
+        out.push(
+            if has_xi && xinput2::XIQueryVersion(display, &mut major, &mut minor) == 0 {
+                pass("xinput", format!("{}.{}", major, minor))
+            } else {
+                fail(
+                    "xinput",
+                    "no XInput 2".to_string(),
+                    "rtmouse needs XInput 2 to see the pointer move, update the X server",
+                )
+            },
+        );
+
+        let (mut major, mut minor) = (0, 0);
+        out.push(
+            if xtest::XTestQueryExtension(display, &mut event, &mut error, &mut major, &mut minor)
+                != 0
+            {
+                pass("xtest", format!("{}.{}", major, minor))
+            } else {
+                fail(
+                    "xtest",
+                    "no XTEST extension".to_string(),
+                    "clicks are sent with XTest, enable the XTEST extension in the X server",
+                )
+            },
+        );
+
+        xlib::XCloseDisplay(display);
+    }
+}
+
+#[cfg(feature = "sound")]
+fn sound_check(config: &DwellConfig) -> Check {
+    if !config.sound_enabled {
+        return pass("sound", "sound_enabled is off".to_string());
+    }
+    match crate::sound::player_name() {
+        Some(player) => pass("sound", player.to_string()),
+        None => fail(
+            "sound",
+            "no sound player".to_string(),
+            "install pw-play, paplay or aplay, or set sound_enabled = false",
+        ),
+    }
+}
+
+#[cfg(not(feature = "sound"))]
+fn sound_check(_config: &DwellConfig) -> Check {
+    pass("sound", "built without sounds".to_string())
+}
+
+// Whether we can make files next to path, by making one and removing it
+fn writable_check(name: &'static str, path: &Path) -> Check {
+    let probe = path.with_file_name(".rtmouse-doctor");
+    let written = paths::ensure_dir_for(path).and_then(|()| fs::write(&probe, ""));
+    let _ = fs::remove_file(&probe);
+    match written {
+        Ok(()) => pass(name, path.display().to_string()),
+        Err(e) => fail(
+            name,
+            format!("{}: {}", path.display(), e),
+            "pass --runtime-dir, or point this setting at a directory you can write",
+        ),
+    }
+}
+
+// config_path and profile are --config and --profile, so this checks the
+// same config rtmouse would run with
+pub fn checks(config_path: Option<&Path>, profile: Option<&str>) -> Vec<Check> {
+    let mut out = Vec::new();
+
+    let var = |name| std::env::var(name).unwrap_or_default();
+    out.push(session_check(
+        &var("XDG_SESSION_TYPE"),
+        !var("WAYLAND_DISPLAY").is_empty(),
+        !var("DISPLAY").is_empty(),
+    ));
+    x_checks(&mut out);
+
+    let config = match config::load_from(config_path, profile) {
+        Ok(c) => {
+            out.push(pass("config", "loads".to_string()));
+            c
+        }
+        Err(e) => {
+            out.push(fail(
+                "config",
+                e.to_string(),
+                "fix the line it names, or see `rtmouse-rs --print-config`",
+            ));
+            DwellConfig::default()
+        }
+    };
+    out.push(sound_check(&config));
+    out.push(writable_check("status_file", &config.status_path()));
+    out.push(writable_check(
+        "control_socket",
+        &config.control_socket_path(),
+    ));
+    out
+}
+
+pub fn report(checks: &[Check]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        out.push_str(&format!(
+            "{:<4}  {:<14}  {}\n",
+            if check.ok { "ok" } else { "FAIL" },
+            check.name,
+            check.detail
+        ));
+        if !check.ok {
+            out.push_str(&format!("{:<22}{}\n", "", check.hint));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wayland_sessions_fail() {
+        assert!(session_check("x11", false, true).ok);
+        assert!(session_check("", false, true).ok);
+        assert!(!session_check("wayland", true, true).ok);
+        // WAYLAND_DISPLAY without XDG_SESSION_TYPE still counts
+        assert!(!session_check("", true, true).ok);
+        assert!(!session_check("x11", false, false).ok);
+
+        let text = report(&[session_check("wayland", true, false)]);
+        assert!(text.starts_with("FAIL  session"));
+        assert!(text.lines().nth(1).unwrap().contains("Xorg session"));
+    }
+}
@@ -0,0 +1,1897 @@
+// The dwell clicking logic, with no X11 in it. The frontend feeds it a
+// sample of the pointer every tick and carries out the commands it returns.
+
+use crate::config::{DragAction, DwellConfig};
+use crate::geometry::Rect;
+use crate::gesture::{self, Shape};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Decides when a resting pointer should click. The state machine does
+// everything around it: motion, held buttons, sleep, focus, what kind of
+// click and its sound. DwellTimer is the usual one, a switch, a blink
+// detector or a voice command could be others.
+pub trait ClickTrigger {
+    // The pointer has been resting for idle, with no button held and rtmouse
+    // awake. Whether to click now.
+    fn ready(&mut self, idle: Duration) -> bool;
+
+    // How long until ready() by itself, for the countdown overlay. None for
+    // triggers that wait on something other than time.
+    fn time_left(&self, idle: Duration) -> Option<Duration>;
+
+    // Another profile took over, see DwellStateMachine::set_config
+    fn set_dwell_time(&mut self, _dwell_time: Duration) {}
+}
+
+// Clicks once the pointer has rested for dwell_time
+pub struct DwellTimer {
+    pub dwell_time: Duration,
+}
+
+impl ClickTrigger for DwellTimer {
+    fn ready(&mut self, idle: Duration) -> bool {
+        idle >= self.dwell_time
+    }
+
+    fn time_left(&self, idle: Duration) -> Option<Duration> {
+        Some(self.dwell_time.saturating_sub(idle))
+    }
+
+    fn set_dwell_time(&mut self, dwell_time: Duration) {
+        self.dwell_time = dwell_time;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub x: i32,
+    pub y: i32,
+    // Bitmask of physical buttons currently held down, bit n for button n
+    pub buttons: u64,
+    pub now: Instant,
+    // focused_window_only is on and the pointer isn't over the focused
+    // window. Only needs to be right when a click is due.
+    pub unfocused: bool,
+    // Another client has the pointer grabbed. Like unfocused, only needs
+    // to be right when a click is due, and only with grab_delay.
+    pub grabbed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Enabled,
+    Disabled,
+    Terminated,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Enabled => "enabled",
+            Status::Disabled => "disabled",
+            Status::Terminated => "terminated",
+        }
+    }
+}
+
+// What the next dwell does. One picked with set_click_type only lasts for a
+// single dwell, after that it's back to the default from the config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClickType {
+    Left,
+    Right,
+    Double,
+    Drag,
+    // Draws a stroke for gesture::recognize instead of clicking
+    Gesture,
+}
+
+impl ClickType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClickType::Left => "left",
+            ClickType::Right => "right",
+            ClickType::Double => "double",
+            ClickType::Drag => "drag",
+            ClickType::Gesture => "gesture",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<ClickType> {
+        match s {
+            "left" => Some(ClickType::Left),
+            "right" => Some(ClickType::Right),
+            "double" => Some(ClickType::Double),
+            "drag" => Some(ClickType::Drag),
+            "gesture" => Some(ClickType::Gesture),
+            _ => None,
+        }
+    }
+}
+
+// A button on one of rtmouse's own windows, see panel.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanelButton {
+    Pause,
+    // Makes the next dwell this kind of click
+    Click(ClickType),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    // A dwell fired. Click(ClickType::Drag) is a drag starting.
+    Click(ClickType),
+    DragEnd,
+    // The next click's type was picked
+    Selected(ClickType),
+}
+
+// Which way the content goes, like the wheel: Down is the wheel turned
+// towards you
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrollDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    // Press and Release are always the primary button
+    Press,
+    Release,
+    // Press and release of the secondary button together
+    SecondaryClick,
+    // One wheel click
+    Scroll(ScrollDirection),
+    // Put the pointer at x, y on the root window
+    MoveTo { x: i32, y: i32 },
+    // A stroke was recognized, x, y is where its dwell was
+    Gesture { shape: Shape, x: i32, y: i32 },
+    PlaySound(Sound),
+    WriteStatus(Status),
+    // A dwell landed in macro_zones[n], replay its macro
+    RunMacro(usize),
+}
+
+// Why the last update did or didn't do something, for --explain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Disabled,
+    IgnoringFirstMotion,
+    Moving {
+        dx: i64,
+        dy: i64,
+    },
+    Inhibited {
+        buttons: u64,
+    },
+    WaitingForMotion,
+    NotFocused,
+    // The click is due but the pointer is grabbed, see grab_delay
+    Grabbed {
+        late: Duration,
+    },
+    OffScreen,
+    // DPMS has the monitors in standby, suspend or off
+    DisplayOff,
+    // A macro is moving the pointer and clicking, see macros.rs
+    Replaying,
+    // A shake just toggled dwell clicking, and is still settling
+    Shaken,
+    // Over an on-screen keyboard, with keyboard_dwell = "off"
+    OverKeyboard,
+    Idle {
+        idle: Duration,
+        dwell_time: Duration,
+    },
+    // Resting, with a trigger that isn't a timer
+    Armed {
+        idle: Duration,
+    },
+    Clicked(ClickType),
+    // Dwelled in a macro zone, with the macro's name
+    Macro(String),
+    // Dwelled on a pause button, see set_panel
+    PauseButton {
+        paused: bool,
+    },
+    // Dwelled on a control panel's click type button
+    Picked(ClickType),
+    DragStarted,
+    // The first movement after a drag dwell picked what it does
+    DragDirection {
+        vertical: bool,
+        action: DragAction,
+    },
+    Scrolled {
+        steps: i32,
+    },
+    // Resting during a gesture stroke, which ends at drag_time
+    Stroking {
+        idle: Duration,
+        drag_time: Duration,
+    },
+    Gestured(Option<Shape>),
+    Dragging {
+        idle: Duration,
+        drag_time: Duration,
+    },
+    // Moving, but too soon after a drag to start a dwell
+    CoolingDown {
+        left: Duration,
+    },
+    // An absolute device put the pointer down somewhere new
+    Jumped {
+        dx: i64,
+        dy: i64,
+    },
+    // Resting after a jump, before the dwell timer can start
+    Landing {
+        left: Duration,
+    },
+    DragReleased,
+    DragTooLong {
+        max: Duration,
+    },
+    FellAsleep {
+        after: Duration,
+    },
+    Asleep,
+    Woke,
+}
+
+fn millis(d: Duration) -> u128 {
+    d.as_millis()
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Decision::Disabled => write!(f, "disabled"),
+            Decision::IgnoringFirstMotion => {
+                write!(f, "moving: first motion after enabling, not arming")
+            }
+            Decision::Moving { dx, dy } => write!(f, "moving: dx={} dy={}", dx, dy),
+            Decision::Inhibited { buttons } => {
+                write!(f, "inhibited by button")?;
+                for b in 0..64 {
+                    if buttons & (1 << b) != 0 {
+                        write!(f, " {}", b)?;
+                    }
+                }
+                Ok(())
+            }
+            Decision::WaitingForMotion => write!(f, "idle: waiting for the pointer to move"),
+            Decision::OffScreen => {
+                write!(f, "off screen: the pointer is on a screen we don't watch")
+            }
+            Decision::DisplayOff => {
+                write!(f, "paused: the display is powered down")
+            }
+            Decision::Replaying => write!(f, "paused: replaying a macro"),
+            Decision::Shaken => write!(f, "paused: a shake toggled dwell clicking"),
+            Decision::OverKeyboard => {
+                write!(
+                    f,
+                    "paused: the keyboard under the pointer has its own dwell"
+                )
+            }
+            Decision::Grabbed { late } => write!(
+                f,
+                "holding the click {}ms: another client has the pointer grabbed",
+                millis(*late)
+            ),
+            Decision::NotFocused => {
+                write!(f, "not clicking: the pointer isn't over the focused window")
+            }
+            Decision::Idle { idle, dwell_time } => {
+                write!(f, "idle {}/{}ms", millis(*idle), millis(*dwell_time))
+            }
+            Decision::Armed { idle } => {
+                write!(f, "idle {}ms, waiting for the trigger", millis(*idle))
+            }
+            Decision::Clicked(click) => write!(f, "{} click", click.as_str()),
+            Decision::Macro(name) => write!(f, "macro zone, playing {}", name),
+            Decision::PauseButton { paused: true } => write!(f, "pause button, paused"),
+            Decision::PauseButton { paused: false } => write!(f, "pause button, resumed"),
+            Decision::Picked(click) => write!(f, "panel: next click {}", click.as_str()),
+            Decision::DragStarted => write!(f, "press, drag started"),
+            Decision::DragDirection { vertical, action } => write!(
+                f,
+                "moved off {}, {}",
+                if *vertical {
+                    "vertically"
+                } else {
+                    "horizontally"
+                },
+                action.as_str()
+            ),
+            Decision::Scrolled { steps } => write!(f, "scrolled {} steps", steps),
+            Decision::Stroking { idle, drag_time } => write!(
+                f,
+                "drawing a gesture, idle {}/{}ms",
+                millis(*idle),
+                millis(*drag_time)
+            ),
+            Decision::Gestured(Some(shape)) => write!(f, "gesture: {}", shape.as_str()),
+            Decision::Gestured(None) => write!(f, "gesture not recognized"),
+            Decision::Dragging { idle, drag_time } => write!(
+                f,
+                "dragging, idle {}/{}ms",
+                millis(*idle),
+                millis(*drag_time)
+            ),
+            Decision::CoolingDown { left } => write!(
+                f,
+                "moving: {}ms of drag_cooldown left, not arming",
+                millis(*left)
+            ),
+            Decision::Jumped { dx, dy } => {
+                write!(f, "jumped: dx={} dy={}, not steering", dx, dy)
+            }
+            Decision::Landing { left } => write!(
+                f,
+                "landed: {}ms of jump_arm_delay left, not arming",
+                millis(*left)
+            ),
+            Decision::DragReleased => write!(f, "release, drag ended"),
+            Decision::DragTooLong { max } => {
+                write!(f, "release, drag has been held for {}ms", millis(*max))
+            }
+            Decision::FellAsleep { after } => write!(
+                f,
+                "asleep: no activity for {} minutes",
+                after.as_secs() / 60
+            ),
+            Decision::Asleep => write!(f, "asleep: waiting for a large movement"),
+            Decision::Woke => write!(f, "woke up"),
+        }
+    }
+}
+
+// What a drag dwell is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Drag {
+    // The button is down
+    Held,
+    // drag_vertical or drag_horizontal is scroll, so nothing happens until
+    // the first movement says which way it's going
+    Undecided,
+    // scroll_clicks wheel clicks for every scroll_step along one axis. from
+    // is where the last ones were, origin where the scrolling started, and
+    // repeated when scroll_repeat last scrolled.
+    Scrolling {
+        vertical: bool,
+        from: i32,
+        origin: i32,
+        repeated: Option<Instant>,
+    },
+}
+
+pub struct DwellStateMachine {
+    config: DwellConfig,
+    trigger: Box<dyn ClickTrigger>,
+
+    active: bool,
+    just_became_active: bool,
+
+    anchor_x: i32,
+    anchor_y: i32,
+    moving: bool,
+    // The latest motion came from an absolute device, see jump_pixels
+    absolute: bool,
+    // When it last jumped, for jump_arm_delay
+    jumped_at: Option<Instant>,
+
+    // Start of the current idle period. None means the timer is disarmed and
+    // nothing will happen until the pointer moves again.
+    idle_since: Option<Instant>,
+
+    drag: Option<Drag>,
+    drag_started: Option<Instant>,
+    // When the last drag ended, for drag_cooldown
+    drag_ended: Option<Instant>,
+
+    // Where the pointer has been since a gesture dwell, starting at the dwell
+    stroke: Option<Vec<(i32, i32)>>,
+
+    pending_click: Option<ClickType>,
+
+    // Last time the pointer moved or a button was held, for auto_disable_after
+    last_activity: Option<Instant>,
+    asleep: bool,
+
+    // Where rtmouse's own buttons are on screen, see panel.rs
+    panel: Vec<(Rect, PanelButton)>,
+    // keyboard_dwell_time, while it's over an on-screen keyboard
+    keyboard_dwell: Option<Duration>,
+
+    decision: Decision,
+}
+
+impl DwellStateMachine {
+    pub fn new(config: DwellConfig) -> DwellStateMachine {
+        let timer = DwellTimer {
+            dwell_time: config.dwell_time,
+        };
+        DwellStateMachine::with_trigger(config, Box::new(timer))
+    }
+
+    pub fn with_trigger(config: DwellConfig, trigger: Box<dyn ClickTrigger>) -> DwellStateMachine {
+        DwellStateMachine {
+            config,
+            trigger,
+            active: false,
+            just_became_active: false,
+            anchor_x: 0,
+            anchor_y: 0,
+            moving: false,
+            absolute: false,
+            jumped_at: None,
+            idle_since: None,
+            drag: None,
+            drag_started: None,
+            drag_ended: None,
+            stroke: None,
+            pending_click: None,
+            last_activity: None,
+            asleep: false,
+            panel: Vec::new(),
+            keyboard_dwell: None,
+            decision: Decision::Disabled,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.moving
+    }
+
+    // In a drag dwell, whether the button is held, it's scrolling or it's
+    // still waiting to see which
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // Where the pointer came to rest, while the dwell timer is running
+    pub fn armed_anchor(&self) -> Option<(i32, i32)> {
+        if self.active && !self.asleep && !self.moving && self.idle_since.is_some() {
+            Some((self.anchor_x, self.anchor_y))
+        } else {
+            None
+        }
+    }
+
+    // How long until the armed dwell timer clicks, if the pointer stays put.
+    // None as well with a trigger that isn't a timer.
+    pub fn dwell_time_left(&self, now: Instant) -> Option<Duration> {
+        if self.drag.is_some() || self.stroke.is_some() {
+            return None;
+        }
+        self.armed_anchor()?;
+        let idle = now.saturating_duration_since(self.idle_since?);
+        self.time_left(idle)
+    }
+
+    // How long the next click's dwell is: keyboard_dwell_time over an
+    // on-screen keyboard, right_dwell_time and double_dwell_time when
+    // they're set, otherwise dwell_time
+    pub fn dwell_time(&self) -> Duration {
+        if let Some(time) = self.keyboard_dwell {
+            return time;
+        }
+        let own = match self.next_click_type() {
+            ClickType::Right => self.config.right_dwell_time,
+            ClickType::Double => self.config.double_dwell_time,
+            _ => None,
+        };
+        own.unwrap_or(self.config.dwell_time)
+    }
+
+    // The trigger only knows dwell_time, so for a click with its own it's
+    // told the pointer has rested that much more or less than it has
+    fn trigger_idle(&self, idle: Duration) -> Duration {
+        let (own, usual) = (self.dwell_time(), self.config.dwell_time);
+        if own >= usual {
+            idle.saturating_sub(own - usual)
+        } else {
+            idle + (usual - own)
+        }
+    }
+
+    // What's left of the next click's dwell, not just the trigger's
+    fn time_left(&self, idle: Duration) -> Option<Duration> {
+        let extra = self.dwell_time().saturating_sub(self.config.dwell_time);
+        let left = self.trigger.time_left(self.trigger_idle(idle))?;
+        Some(left + extra.saturating_sub(idle))
+    }
+
+    // While dragging, how long until the drag is released if the pointer
+    // stays where it is
+    pub fn drag_time_left(&self, now: Instant) -> Option<Duration> {
+        self.drag?;
+        let idle_left = match self.idle_since {
+            Some(t) if !self.moving => self
+                .config
+                .drag_time
+                .saturating_sub(now.saturating_duration_since(t)),
+            _ => self.config.drag_time,
+        };
+        let max_left = match self.drag_started {
+            Some(t) => self
+                .config
+                .max_drag_duration
+                .saturating_sub(now.saturating_duration_since(t)),
+            None => self.config.max_drag_duration,
+        };
+        Some(idle_left.min(max_left))
+    }
+
+    // Active, but dozing after auto_disable_after without activity
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    pub fn last_decision(&self) -> &Decision {
+        &self.decision
+    }
+
+    // A dwell on one of these buttons does what it says instead of clicking
+    // it. A pause button turns dwell clicking off, and update_paused turns it
+    // back on. The frontend keeps this up to date as the buttons move.
+    pub fn set_panel(&mut self, buttons: Vec<(Rect, PanelButton)>) {
+        self.panel = buttons;
+    }
+
+    fn panel_button_at(&self, x: i32, y: i32) -> Option<PanelButton> {
+        self.panel
+            .iter()
+            .find(|(r, _)| r.contains(x, y))
+            .map(|&(_, button)| button)
+    }
+
+    // While disabled, the pause buttons are the only thing dwelling works on.
+    // It takes a movement and then a rest on it like any dwell, so the
+    // pointer left there after pausing doesn't resume right away.
+    pub fn update_paused(&mut self, x: i32, y: i32, now: Instant) -> Vec<Command> {
+        if self.active {
+            return Vec::new();
+        }
+        let over = self.panel_button_at(x, y) == Some(PanelButton::Pause);
+        let (dx, dy) = ((x - self.anchor_x) as i64, (y - self.anchor_y) as i64);
+        let min = self.config.min_movement_pixels as i64;
+        if dx * dx + dy * dy > min * min {
+            self.anchor_x = x;
+            self.anchor_y = y;
+            self.idle_since = Some(now).filter(|_| over);
+        }
+        match self.idle_since {
+            Some(since)
+                if over && now.saturating_duration_since(since) >= self.config.dwell_time =>
+            {
+                let mut out = self.set_active(true);
+                out.push(Command::PlaySound(Sound::Click(ClickType::Left)));
+                self.decision = Decision::PauseButton { paused: false };
+                out
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Another profile's options, for device_profiles. Whatever is going on,
+    // a drag or a dwell, carries on under them.
+    pub fn set_config(&mut self, config: DwellConfig) {
+        self.trigger.set_dwell_time(config.dwell_time);
+        self.config = config;
+    }
+
+    // The pointer is over an on-screen keyboard with keyboard_dwell =
+    // "quick", or no longer is
+    pub fn set_keyboard_dwell(&mut self, time: Option<Duration>) {
+        self.keyboard_dwell = time;
+    }
+
+    // min_movement_mm worked out for the monitor the pointer is on
+    pub fn set_min_movement(&mut self, pixels: u32) {
+        self.config.min_movement_pixels = pixels;
+    }
+
+    // Whether the pointer is being moved by a tablet or touchscreen in
+    // absolute mode, whose big moves are jumps
+    pub fn set_absolute(&mut self, absolute: bool) {
+        self.absolute = absolute;
+    }
+
+    fn jump_delay_left(&self, now: Instant) -> Option<Duration> {
+        let left = self
+            .config
+            .jump_arm_delay
+            .saturating_sub(now.saturating_duration_since(self.jumped_at?));
+        (!left.is_zero()).then_some(left)
+    }
+
+    pub fn set_click_type(&mut self, click: ClickType) -> Vec<Command> {
+        self.pending_click = Some(click);
+        vec![Command::PlaySound(Sound::Selected(click))]
+    }
+
+    // What the next dwell will do
+    pub fn next_click_type(&self) -> ClickType {
+        match self.pending_click {
+            Some(click) => click,
+            None if self.config.drag_enabled => ClickType::Drag,
+            None => ClickType::Left,
+        }
+    }
+
+    pub fn set_active(&mut self, active: bool) -> Vec<Command> {
+        let mut out = Vec::new();
+
+        if active && !self.active {
+            // Whatever movement is seen first after enabling is the user
+            // getting into position, don't start a dwell from it.
+            self.just_became_active = true;
+            self.idle_since = None;
+        }
+        if active && self.asleep {
+            // Enabling by hand is as good as the wake gesture
+            self.asleep = false;
+            self.just_became_active = true;
+            self.last_activity = None;
+        }
+        if !active {
+            self.let_go(&mut out);
+            self.stroke = None;
+        }
+
+        self.active = active;
+        out.push(Command::WriteStatus(if active {
+            Status::Enabled
+        } else {
+            Status::Disabled
+        }));
+        out
+    }
+
+    // The frontend let go of the button behind our back (see the stuck drag
+    // watchdog). Stop dragging and wait for the pointer to move again.
+    pub fn drag_released(&mut self) {
+        self.drag = None;
+        self.idle_since = None;
+        self.decision = Decision::DragReleased;
+    }
+
+    // The pointer is on an X screen we don't watch. Let go of any drag, and
+    // once it's back wait for it to move before dwelling: its coordinates
+    // over there can't be compared with ours.
+    pub fn pointer_left(&mut self) -> Vec<Command> {
+        self.pause(Decision::OffScreen)
+    }
+
+    // The same while the monitors are powered down, so nothing gets clicked
+    // that nobody can see
+    pub fn display_off(&mut self) -> Vec<Command> {
+        self.pause(Decision::DisplayOff)
+    }
+
+    // And while a macro replays, the pointer jumping around is the macro
+    // rather than someone moving it
+    pub fn replaying(&mut self) -> Vec<Command> {
+        self.pause(Decision::Replaying)
+    }
+
+    // And just after a shake toggled dwell clicking on, while the shaking
+    // dies down, see shake.rs
+    pub fn shaken(&mut self) -> Vec<Command> {
+        self.pause(Decision::Shaken)
+    }
+
+    // And over an on-screen keyboard that does its own dwelling
+    pub fn over_keyboard(&mut self) -> Vec<Command> {
+        self.pause(Decision::OverKeyboard)
+    }
+
+    fn pause(&mut self, decision: Decision) -> Vec<Command> {
+        let mut out = Vec::new();
+        self.let_go(&mut out);
+        self.stroke = None;
+        self.idle_since = None;
+        self.moving = false;
+        self.decision = decision;
+        out
+    }
+
+    // Never leave a button held down behind us on the way out.
+    pub fn shutdown(&mut self) -> Vec<Command> {
+        let mut out = Vec::new();
+        self.let_go(&mut out);
+        self.active = false;
+        out.push(Command::WriteStatus(Status::Terminated));
+        out
+    }
+
+    // Performs the next click type right away
+    fn fire(&mut self, now: Instant, out: &mut Vec<Command>) {
+        let (x, y) = (self.anchor_x, self.anchor_y);
+        match self.panel_button_at(x, y) {
+            Some(PanelButton::Pause) => {
+                out.extend(self.set_active(false));
+                out.push(Command::PlaySound(Sound::Click(ClickType::Left)));
+                self.idle_since = None;
+                self.decision = Decision::PauseButton { paused: true };
+                return;
+            }
+            Some(PanelButton::Click(click)) => {
+                out.extend(self.set_click_type(click));
+                self.idle_since = None;
+                self.decision = Decision::Picked(click);
+                return;
+            }
+            None => {}
+        }
+        if let Some(zone) = self
+            .config
+            .macro_zones
+            .iter()
+            .position(|z| z.rect.contains(x, y))
+        {
+            // A picked click type waits for the next dwell outside the zones
+            out.push(Command::RunMacro(zone));
+            out.push(Command::PlaySound(Sound::Click(ClickType::Left)));
+            self.idle_since = None;
+            self.decision = Decision::Macro(self.config.macro_zones[zone].name.clone());
+            return;
+        }
+
+        let click = self.next_click_type();
+        self.pending_click = None;
+
+        match click {
+            ClickType::Drag => {
+                let scrolls = self.config.drag_vertical == DragAction::Scroll
+                    || self.config.drag_horizontal == DragAction::Scroll;
+                self.drag = Some(if scrolls {
+                    Drag::Undecided
+                } else {
+                    out.push(Command::Press);
+                    Drag::Held
+                });
+                self.drag_started = Some(now);
+                self.idle_since = Some(now);
+                self.decision = Decision::DragStarted;
+            }
+            ClickType::Left | ClickType::Double => {
+                out.push(Command::Press);
+                out.push(Command::Release);
+                if click == ClickType::Double {
+                    out.push(Command::Press);
+                    out.push(Command::Release);
+                }
+
+                self.idle_since = None;
+                self.decision = Decision::Clicked(click);
+            }
+            ClickType::Right => {
+                out.push(Command::SecondaryClick);
+
+                self.idle_since = None;
+                self.decision = Decision::Clicked(click);
+            }
+            ClickType::Gesture => {
+                self.stroke = Some(vec![(self.anchor_x, self.anchor_y)]);
+                self.drag_started = Some(now);
+                self.idle_since = Some(now);
+                self.decision = Decision::Clicked(click);
+            }
+        }
+        out.push(Command::PlaySound(Sound::Click(click)));
+    }
+
+    fn end_drag(&mut self, now: Instant, out: &mut Vec<Command>) {
+        if self.drag == Some(Drag::Undecided) {
+            // Rested without moving off, a click like a drag that never went
+            // anywhere
+            out.push(Command::Press);
+            self.drag = Some(Drag::Held);
+        }
+        self.let_go(out);
+        out.push(Command::PlaySound(Sound::DragEnd));
+
+        self.drag_ended = Some(now);
+        self.idle_since = None;
+        self.decision = Decision::DragReleased;
+    }
+
+    fn end_stroke(&mut self, out: &mut Vec<Command>) {
+        let stroke = match self.stroke.take() {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        let shape = gesture::recognize(&stroke);
+        if let Some(shape) = shape {
+            let (x, y) = stroke[0];
+            out.push(Command::Gesture { shape, x, y });
+            out.push(Command::PlaySound(Sound::DragEnd));
+        }
+        self.drag_started = None;
+        self.idle_since = None;
+        self.decision = Decision::Gestured(shape);
+    }
+
+    // Ends any drag dwell, releasing the button if it's down
+    fn let_go(&mut self, out: &mut Vec<Command>) {
+        if self.drag.take() == Some(Drag::Held) {
+            out.push(Command::Release);
+        }
+    }
+
+    // The pointer moved during a drag dwell. The first time, which way it
+    // went picks a drag or scrolling. Returns the decision if it's anything
+    // more than moving.
+    fn steer_drag(&mut self, x: i32, y: i32, out: &mut Vec<Command>) -> Option<Decision> {
+        match self.drag? {
+            Drag::Held => None,
+            Drag::Scrolling { .. } => self.scroll(x, y, out),
+            Drag::Undecided => {
+                let vertical = (y - self.anchor_y).abs() > (x - self.anchor_x).abs();
+                let action = if vertical {
+                    self.config.drag_vertical
+                } else {
+                    self.config.drag_horizontal
+                };
+                if action == DragAction::Drag {
+                    // Pressed where the dwell was, not min_movement_pixels
+                    // away where the pointer is now
+                    out.push(Command::MoveTo {
+                        x: self.anchor_x,
+                        y: self.anchor_y,
+                    });
+                    out.push(Command::Press);
+                    out.push(Command::MoveTo { x, y });
+                    self.drag = Some(Drag::Held);
+                } else {
+                    let from = if vertical {
+                        self.anchor_y
+                    } else {
+                        self.anchor_x
+                    };
+                    self.drag = Some(Drag::Scrolling {
+                        vertical,
+                        from,
+                        origin: from,
+                        repeated: None,
+                    });
+                    self.scroll(x, y, out);
+                }
+                Some(Decision::DragDirection { vertical, action })
+            }
+        }
+    }
+
+    // scroll_clicks wheel clicks for each of steps, down or right when
+    // they're positive, the other way round with natural_scroll
+    fn wheel(&self, vertical: bool, steps: i32, out: &mut Vec<Command>) {
+        let direction = match (vertical, (steps > 0) != self.config.natural_scroll) {
+            (true, true) => ScrollDirection::Down,
+            (true, false) => ScrollDirection::Up,
+            (false, true) => ScrollDirection::Right,
+            (false, false) => ScrollDirection::Left,
+        };
+        for _ in 0..steps.unsigned_abs() * self.config.scroll_clicks {
+            out.push(Command::Scroll(direction));
+        }
+    }
+
+    // Scrolling carries on from where the pointer landed, instead of
+    // turning the jump into a burst of wheel clicks
+    fn follow_jump(&mut self, dx: i32, dy: i32) {
+        if let Some(Drag::Scrolling {
+            vertical,
+            from,
+            origin,
+            ..
+        }) = &mut self.drag
+        {
+            let moved = if *vertical { dy } else { dx };
+            *from += moved;
+            *origin += moved;
+        }
+    }
+
+    fn scroll(&mut self, x: i32, y: i32, out: &mut Vec<Command>) -> Option<Decision> {
+        let (vertical, from, origin) = match self.drag {
+            Some(Drag::Scrolling {
+                vertical,
+                from,
+                origin,
+                ..
+            }) => (vertical, from, origin),
+            _ => return None,
+        };
+        let step = self.config.scroll_step as i32;
+        let steps = (if vertical { y } else { x } - from) / step;
+        if steps == 0 {
+            return None;
+        }
+        self.wheel(vertical, steps, out);
+        self.drag = Some(Drag::Scrolling {
+            vertical,
+            from: from + steps * step,
+            origin,
+            repeated: None,
+        });
+        Some(Decision::Scrolled { steps })
+    }
+
+    // With scroll_repeat, resting at least a scroll_step away from where
+    // the scrolling started keeps it going that way, one step every
+    // scroll_repeat, like holding a scroll bar arrow. Returns false when
+    // resting is left to end the scroll as usual.
+    fn repeat_scroll(&mut self, idle_since: Instant, now: Instant, out: &mut Vec<Command>) -> bool {
+        let every = match self.config.scroll_repeat {
+            Some(every) => every,
+            None => return false,
+        };
+        let (vertical, from, origin, repeated) = match self.drag {
+            Some(Drag::Scrolling {
+                vertical,
+                from,
+                origin,
+                repeated,
+            }) => (vertical, from, origin, repeated),
+            _ => return false,
+        };
+        let away = if vertical {
+            self.anchor_y
+        } else {
+            self.anchor_x
+        } - origin;
+        if away.abs() < self.config.scroll_step as i32 {
+            return false;
+        }
+        let last = repeated.map_or(idle_since, |t| t.max(idle_since));
+        if now.saturating_duration_since(last) >= every {
+            let steps = away.signum();
+            self.wheel(vertical, steps, out);
+            self.drag = Some(Drag::Scrolling {
+                vertical,
+                from,
+                origin,
+                repeated: Some(now),
+            });
+            self.decision = Decision::Scrolled { steps };
+        }
+        true
+    }
+
+    // The frontend moved the pointer to x, y itself, for nudge_keys. The
+    // dwell timer keeps running, only the spot it clicks on changed.
+    pub fn nudged(&mut self, x: i32, y: i32) {
+        self.anchor_x = x;
+        self.anchor_y = y;
+    }
+
+    // The click key: click now without waiting for the dwell, or end the drag
+    // if one is going.
+    pub fn click_now(&mut self, now: Instant) -> Vec<Command> {
+        let mut out = Vec::new();
+        if !self.active || self.asleep {
+            return out;
+        }
+        if self.drag.is_some() {
+            self.end_drag(now, &mut out);
+        } else if self.stroke.is_some() {
+            self.end_stroke(&mut out);
+        } else {
+            self.fire(now, &mut out);
+        }
+        out
+    }
+
+    pub fn update(&mut self, sample: Sample) -> Vec<Command> {
+        let mut out = Vec::new();
+
+        if !self.active {
+            self.decision = Decision::Disabled;
+            return out;
+        }
+
+        // Checked before motion, which would otherwise keep a drag going
+        if let Some(started) = self.drag_started {
+            let too_long =
+                sample.now.saturating_duration_since(started) >= self.config.max_drag_duration;
+            if self.stroke.is_some() && too_long {
+                // Too long to be one of the shapes, whatever it looks like
+                self.stroke = None;
+                self.drag_started = None;
+                self.idle_since = None;
+                self.decision = Decision::Gestured(None);
+                return out;
+            }
+            if self.drag.is_some() && too_long {
+                self.let_go(&mut out);
+                out.push(Command::PlaySound(Sound::DragEnd));
+
+                self.drag_started = None;
+                self.drag_ended = Some(sample.now);
+                self.idle_since = None;
+                self.decision = Decision::DragTooLong {
+                    max: self.config.max_drag_duration,
+                };
+                return out;
+            }
+        }
+
+        let dx = (sample.x - self.anchor_x) as i64;
+        let dy = (sample.y - self.anchor_y) as i64;
+
+        if self.asleep {
+            let wake = self.config.wake_movement_pixels as i64;
+            if dx * dx + dy * dy > wake * wake {
+                self.asleep = false;
+                self.anchor_x = sample.x;
+                self.anchor_y = sample.y;
+                self.moving = false;
+                self.idle_since = None;
+                self.last_activity = Some(sample.now);
+                out.push(Command::WriteStatus(Status::Enabled));
+                self.decision = Decision::Woke;
+            } else {
+                self.decision = Decision::Asleep;
+            }
+            return out;
+        }
+
+        let movement_threshold = if self.moving {
+            1
+        } else {
+            self.config.min_movement_pixels as i64
+        };
+
+        self.moving = dx * dx + dy * dy > movement_threshold * movement_threshold;
+
+        if self.moving || sample.buttons != 0 || self.last_activity.is_none() {
+            self.last_activity = Some(sample.now);
+        }
+        if let (Some(after), Some(last)) = (self.config.auto_disable_after, self.last_activity) {
+            if self.drag.is_none()
+                && self.stroke.is_none()
+                && sample.now.saturating_duration_since(last) >= after
+            {
+                self.asleep = true;
+                self.idle_since = None;
+                out.push(Command::WriteStatus(Status::Disabled));
+                self.decision = Decision::FellAsleep { after };
+                return out;
+            }
+        }
+
+        if self.moving {
+            let jumped = self.absolute
+                && self
+                    .config
+                    .jump_pixels
+                    .is_some_and(|p| dx * dx + dy * dy > p as i64 * p as i64);
+            let steered = if jumped {
+                self.jumped_at = Some(sample.now);
+                self.follow_jump(dx as i32, dy as i32);
+                Some(Decision::Jumped { dx, dy })
+            } else {
+                if let Some(stroke) = &mut self.stroke {
+                    stroke.push((sample.x, sample.y));
+                }
+                self.steer_drag(sample.x, sample.y, &mut out)
+            };
+            self.anchor_x = sample.x;
+            self.anchor_y = sample.y;
+
+            let cooldown_left = self
+                .drag_ended
+                .map(|t| {
+                    self.config
+                        .drag_cooldown
+                        .saturating_sub(sample.now.saturating_duration_since(t))
+                })
+                .filter(|left| !left.is_zero());
+            if self.just_became_active {
+                self.just_became_active = false;
+                self.idle_since = None;
+                self.decision = Decision::IgnoringFirstMotion;
+            } else if let Some(left) = cooldown_left {
+                self.idle_since = None;
+                self.decision = Decision::CoolingDown { left };
+            } else {
+                self.idle_since = Some(sample.now);
+                self.decision = steered.unwrap_or(Decision::Moving { dx, dy });
+            }
+            return out;
+        }
+
+        // A physical button is held. Don't click over the top of it, unless
+        // it's our own drag press coming back at us.
+        if sample.buttons != 0 && (!self.config.drag_enabled || self.drag.is_none()) {
+            self.idle_since = None;
+            self.decision = Decision::Inhibited {
+                buttons: sample.buttons,
+            };
+            return out;
+        }
+
+        let idle_since = match self.idle_since {
+            Some(t) => t,
+            None => {
+                self.decision = Decision::WaitingForMotion;
+                return out;
+            }
+        };
+        if self.drag.is_none() && self.stroke.is_none() {
+            if let Some(left) = self.jump_delay_left(sample.now) {
+                // The timer starts from when the delay is over
+                self.idle_since = Some(sample.now + left);
+                self.decision = Decision::Landing { left };
+                return out;
+            }
+        }
+        let idle = sample.now.saturating_duration_since(idle_since);
+        let ready = self.drag.is_none()
+            && self.stroke.is_none()
+            && self.trigger.ready(self.trigger_idle(idle));
+
+        let late = idle.saturating_sub(self.dwell_time());
+        if ready && sample.unfocused {
+            // Like a click that went nowhere, wait for the next motion
+            self.idle_since = None;
+            self.decision = Decision::NotFocused;
+        } else if ready && sample.grabbed && late < self.config.grab_delay {
+            self.decision = Decision::Grabbed { late };
+        } else if ready {
+            self.fire(sample.now, &mut out);
+        } else if self.repeat_scroll(idle_since, sample.now, &mut out) {
+            // Still scrolling
+        } else if self.drag.is_some() && idle >= self.config.drag_time {
+            self.end_drag(sample.now, &mut out);
+        } else if self.drag.is_some() {
+            self.decision = Decision::Dragging {
+                idle,
+                drag_time: self.config.drag_time,
+            };
+        } else if self.stroke.is_some() && idle >= self.config.drag_time {
+            self.end_stroke(&mut out);
+        } else if self.stroke.is_some() {
+            self.decision = Decision::Stroking {
+                idle,
+                drag_time: self.config.drag_time,
+            };
+        } else {
+            self.decision = match self.time_left(idle) {
+                Some(left) => Decision::Idle {
+                    idle,
+                    dwell_time: idle + left,
+                },
+                None => Decision::Armed { idle },
+            };
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Driver {
+        sm: DwellStateMachine,
+        start: Instant,
+    }
+
+    impl Driver {
+        fn new(config: DwellConfig) -> Driver {
+            let mut sm = DwellStateMachine::new(config);
+            sm.set_active(true);
+            Driver {
+                sm,
+                start: Instant::now(),
+            }
+        }
+
+        fn at(&mut self, ms: u64, x: i32, y: i32, buttons: u64) -> Vec<Command> {
+            self.sm.update(Sample {
+                x,
+                y,
+                buttons,
+                now: self.start + Duration::from_millis(ms),
+                unfocused: false,
+                grabbed: false,
+            })
+        }
+    }
+
+    fn no_drag() -> DwellConfig {
+        DwellConfig {
+            drag_enabled: false,
+            ..DwellConfig::default()
+        }
+    }
+
+    #[test]
+    fn first_motion_after_enabling_does_not_dwell() {
+        let mut d = Driver::new(no_drag());
+        assert!(d.at(0, 100, 100, 0).is_empty());
+        assert!(d.at(2000, 100, 100, 0).is_empty());
+    }
+
+    #[test]
+    fn clicks_after_dwell_time() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert!(d.at(500, 200, 200, 0).is_empty());
+        assert_eq!(
+            d.at(600, 200, 200, 0),
+            vec![
+                Command::Press,
+                Command::Release,
+                Command::PlaySound(Sound::Click(ClickType::Left))
+            ]
+        );
+        assert!(d.at(2000, 200, 200, 0).is_empty());
+    }
+
+    #[test]
+    fn unfocused_dwell_does_not_click() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        let out = d.sm.update(Sample {
+            x: 200,
+            y: 200,
+            buttons: 0,
+            now: d.start + Duration::from_millis(600),
+            unfocused: true,
+            grabbed: false,
+        });
+        assert!(out.is_empty());
+        assert_eq!(d.sm.last_decision(), &Decision::NotFocused);
+        assert!(d.at(2000, 200, 200, 0).is_empty());
+    }
+
+    #[test]
+    fn grabbed_pointer_holds_the_click() {
+        let mut d = Driver::new(DwellConfig {
+            grab_delay: Duration::from_millis(300),
+            ..no_drag()
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        let mut grabbed = |ms| {
+            d.sm.update(Sample {
+                x: 200,
+                y: 200,
+                buttons: 0,
+                now: d.start + Duration::from_millis(ms),
+                unfocused: false,
+                grabbed: true,
+            })
+        };
+        assert!(grabbed(600).is_empty());
+        assert!(grabbed(800).is_empty());
+        // and then clicks anyway
+        assert_eq!(
+            grabbed(900).last(),
+            Some(&Command::PlaySound(Sound::Click(ClickType::Left)))
+        );
+    }
+
+    #[test]
+    fn absolute_jumps_wait_before_arming() {
+        let mut d = Driver::new(no_drag());
+        d.sm.set_absolute(true);
+        d.at(0, 100, 100, 0);
+        d.at(100, 600, 600, 0);
+        assert_eq!(d.sm.last_decision(), &Decision::Jumped { dx: 500, dy: 500 });
+        assert!(d.at(200, 600, 600, 0).is_empty());
+        assert_eq!(
+            d.sm.last_decision(),
+            &Decision::Landing {
+                left: Duration::from_millis(400)
+            }
+        );
+        // dwell_time counts from the end of jump_arm_delay
+        assert!(d.at(1000, 600, 600, 0).is_empty());
+        assert_eq!(d.at(1100, 600, 600, 0)[0], Command::Press);
+
+        // Moving there normally arms straight away
+        d.at(1200, 650, 600, 0);
+        assert_eq!(d.at(1700, 650, 600, 0)[0], Command::Press);
+
+        // And from a mouse the same move isn't a jump
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 600, 600, 0);
+        assert_eq!(d.at(600, 600, 600, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn small_movement_does_not_restart_dwell() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(200, 200, 200, 0);
+        d.at(300, 203, 203, 0);
+        d.at(400, 205, 205, 0);
+        assert_eq!(d.at(600, 205, 205, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn nudging_keeps_the_timer_running() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(200, 200, 200, 0);
+        d.sm.nudged(230, 200);
+        assert!(d.at(300, 230, 200, 0).is_empty());
+        assert_eq!(d.sm.armed_anchor(), Some((230, 200)));
+        assert_eq!(d.at(600, 230, 200, 0)[0], Command::Press);
+    }
+
+    // Clicks when told to, like a switch would
+    struct Switch(std::rc::Rc<std::cell::Cell<bool>>);
+
+    impl ClickTrigger for Switch {
+        fn ready(&mut self, _idle: Duration) -> bool {
+            self.0.replace(false)
+        }
+
+        fn time_left(&self, _idle: Duration) -> Option<Duration> {
+            None
+        }
+    }
+
+    #[test]
+    fn other_triggers_plug_in() {
+        let pressed = std::rc::Rc::new(std::cell::Cell::new(false));
+        let mut sm = DwellStateMachine::with_trigger(no_drag(), Box::new(Switch(pressed.clone())));
+        sm.set_active(true);
+        let mut d = Driver {
+            sm,
+            start: Instant::now(),
+        };
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert!(d.at(5000, 200, 200, 0).is_empty());
+        assert_eq!(
+            d.sm.last_decision().to_string(),
+            "idle 4900ms, waiting for the trigger"
+        );
+        assert_eq!(d.sm.dwell_time_left(d.start + Duration::from_secs(5)), None);
+
+        // Held buttons still inhibit it
+        pressed.set(true);
+        assert!(d.at(5100, 200, 200, 1 << 1).is_empty());
+        d.at(5200, 250, 250, 0);
+        pressed.set(true);
+        assert_eq!(d.at(5300, 250, 250, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn held_button_inhibits_click() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert!(d.at(300, 200, 200, 1 << 1).is_empty());
+        assert!(d.at(600, 200, 200, 0).is_empty());
+    }
+
+    #[test]
+    fn explains_decisions() {
+        let mut d = Driver::new(no_drag());
+        let mut trace = Vec::new();
+        for (ms, x, buttons) in &[
+            (0, 100, 0),
+            (100, 112, 0),
+            (300, 112, 0),
+            (400, 112, 1 << 3),
+            (500, 112, 0),
+        ] {
+            d.at(*ms, *x, 100, *buttons);
+            trace.push(d.sm.last_decision().to_string());
+        }
+        assert_eq!(
+            trace,
+            vec![
+                "moving: first motion after enabling, not arming",
+                "moving: dx=12 dy=0",
+                "idle 200/500ms",
+                "inhibited by button 3",
+                "idle: waiting for the pointer to move",
+            ]
+        );
+    }
+
+    #[test]
+    fn drag_releases_after_resting() {
+        let mut d = Driver::new(DwellConfig::default());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(
+            d.at(600, 200, 200, 0),
+            vec![
+                Command::Press,
+                Command::PlaySound(Sound::Click(ClickType::Drag))
+            ]
+        );
+        assert!(d.sm.is_dragging());
+
+        // our own press shows up as a held button, that must not cancel it
+        d.at(700, 300, 300, 1 << 1);
+        d.at(800, 400, 400, 1 << 1);
+        assert!(d.at(1200, 400, 400, 1 << 1).is_empty());
+        assert_eq!(
+            d.at(1300, 400, 400, 1 << 1),
+            vec![Command::Release, Command::PlaySound(Sound::DragEnd)]
+        );
+        assert!(!d.sm.is_dragging());
+    }
+
+    #[test]
+    fn drag_direction_picks_scroll_or_drag() {
+        let config = DwellConfig {
+            drag_vertical: DragAction::Scroll,
+            scroll_step: 20,
+            ..DwellConfig::default()
+        };
+        let mut d = Driver::new(config.clone());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(
+            d.at(600, 200, 200, 0),
+            vec![Command::PlaySound(Sound::Click(ClickType::Drag))]
+        );
+        assert!(d.sm.is_dragging());
+
+        // mostly down: two wheel clicks so far, then one more
+        assert_eq!(
+            d.at(700, 205, 245, 0),
+            vec![Command::Scroll(ScrollDirection::Down); 2]
+        );
+        assert_eq!(
+            d.at(800, 205, 265, 0),
+            vec![Command::Scroll(ScrollDirection::Down)]
+        );
+        assert_eq!(
+            d.at(1300, 205, 265, 0),
+            vec![Command::PlaySound(Sound::DragEnd)]
+        );
+        assert!(!d.sm.is_dragging());
+
+        // sideways: pressed back where the dwell was
+        let mut d = Driver::new(config.clone());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.at(700, 230, 205, 0),
+            vec![
+                Command::MoveTo { x: 200, y: 200 },
+                Command::Press,
+                Command::MoveTo { x: 230, y: 205 }
+            ]
+        );
+        assert_eq!(
+            d.at(1300, 230, 205, 1 << 1),
+            vec![Command::Release, Command::PlaySound(Sound::DragEnd)]
+        );
+
+        // resting instead is still a click
+        let mut d = Driver::new(config);
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.at(1100, 200, 200, 0),
+            vec![
+                Command::Press,
+                Command::Release,
+                Command::PlaySound(Sound::DragEnd)
+            ]
+        );
+    }
+
+    #[test]
+    fn scroll_clicks_and_repeat() {
+        let config = DwellConfig {
+            drag_vertical: DragAction::Scroll,
+            scroll_step: 20,
+            scroll_clicks: 3,
+            scroll_repeat: Some(Duration::from_millis(200)),
+            ..DwellConfig::default()
+        };
+        let mut d = Driver::new(config.clone());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+
+        // three wheel clicks a step
+        assert_eq!(
+            d.at(700, 202, 225, 0),
+            vec![Command::Scroll(ScrollDirection::Down); 3]
+        );
+        // resting down there keeps scrolling, instead of ending the drag
+        assert!(d.at(800, 202, 225, 0).is_empty());
+        assert_eq!(
+            d.at(900, 202, 225, 0),
+            vec![Command::Scroll(ScrollDirection::Down); 3]
+        );
+        assert!(d.at(1000, 202, 225, 0).is_empty());
+        assert_eq!(
+            d.at(1300, 202, 225, 0),
+            vec![Command::Scroll(ScrollDirection::Down); 3]
+        );
+        assert!(d.sm.is_dragging());
+
+        // back near the start, resting ends it as usual
+        assert_eq!(
+            d.at(1400, 202, 200, 0),
+            vec![Command::Scroll(ScrollDirection::Up); 3]
+        );
+        assert!(d.at(1500, 202, 200, 0).is_empty());
+        assert_eq!(
+            d.at(1900, 202, 200, 0),
+            vec![Command::PlaySound(Sound::DragEnd)]
+        );
+        assert!(!d.sm.is_dragging());
+
+        // and natural_scroll turns both axes round
+        let mut d = Driver::new(DwellConfig {
+            drag_horizontal: DragAction::Scroll,
+            natural_scroll: true,
+            ..config.clone()
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.at(700, 202, 225, 0),
+            vec![Command::Scroll(ScrollDirection::Up); 3]
+        );
+        let mut d = Driver::new(DwellConfig {
+            drag_horizontal: DragAction::Scroll,
+            natural_scroll: true,
+            ..config
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.at(700, 175, 202, 0),
+            vec![Command::Scroll(ScrollDirection::Right); 3]
+        );
+    }
+
+    #[test]
+    fn gesture_dwell_draws_a_stroke() {
+        let mut d = Driver::new(no_drag());
+        d.sm.set_click_type(ClickType::Gesture);
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(
+            d.at(600, 200, 200, 0),
+            vec![Command::PlaySound(Sound::Click(ClickType::Gesture))]
+        );
+
+        // an L, down then right, with no clicks on the way
+        for (i, &(x, y)) in [(200, 250), (200, 300), (200, 350), (250, 350), (300, 350)]
+            .iter()
+            .enumerate()
+        {
+            assert!(d.at(700 + i as u64 * 100, x, y, 0).is_empty());
+        }
+        assert_eq!(
+            d.sm.dwell_time_left(d.start + Duration::from_millis(1200)),
+            None
+        );
+        assert_eq!(
+            d.at(1600, 300, 350, 0),
+            vec![
+                Command::Gesture {
+                    shape: Shape::L,
+                    x: 200,
+                    y: 200
+                },
+                Command::PlaySound(Sound::DragEnd)
+            ]
+        );
+        assert_eq!(d.sm.last_decision(), &Decision::Gestured(Some(Shape::L)));
+
+        // back to clicking, and a scribble that's nothing does nothing
+        d.sm.set_click_type(ClickType::Gesture);
+        d.at(1700, 400, 400, 0);
+        d.at(2200, 400, 400, 0);
+        d.at(2300, 420, 400, 0);
+        assert!(d.at(2800, 420, 400, 0).is_empty());
+        assert_eq!(d.sm.last_decision(), &Decision::Gestured(None));
+    }
+
+    #[test]
+    fn no_dwell_during_drag_cooldown() {
+        let mut d = Driver::new(DwellConfig {
+            drag_cooldown: Duration::from_millis(1000),
+            ..DwellConfig::default()
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        d.at(700, 300, 300, 1 << 1);
+        d.at(1200, 300, 300, 1 << 1);
+        assert!(!d.sm.is_dragging());
+
+        // moving off the drop and resting there does nothing
+        d.at(1300, 350, 350, 0);
+        assert_eq!(
+            d.sm.last_decision(),
+            &Decision::CoolingDown {
+                left: Duration::from_millis(900)
+            }
+        );
+        assert!(d.at(2000, 350, 350, 0).is_empty());
+
+        // after it, motion arms the dwell again
+        d.at(2300, 450, 450, 0);
+        assert_eq!(
+            d.at(2800, 450, 450, 0),
+            vec![
+                Command::Press,
+                Command::PlaySound(Sound::Click(ClickType::Drag))
+            ]
+        );
+    }
+
+    #[test]
+    fn right_clicks_can_take_longer() {
+        let mut d = Driver::new(DwellConfig {
+            right_dwell_time: Some(Duration::from_millis(1200)),
+            double_dwell_time: Some(Duration::from_millis(300)),
+            ..no_drag()
+        });
+        d.sm.set_click_type(ClickType::Right);
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert!(d.at(600, 200, 200, 0).is_empty());
+        assert_eq!(
+            d.sm.dwell_time_left(d.start + Duration::from_millis(600)),
+            Some(Duration::from_millis(700))
+        );
+        assert_eq!(
+            d.at(1300, 200, 200, 0),
+            vec![
+                Command::SecondaryClick,
+                Command::PlaySound(Sound::Click(ClickType::Right))
+            ]
+        );
+
+        // and a double click quicker than a left one
+        d.sm.set_click_type(ClickType::Double);
+        d.at(1400, 300, 300, 0);
+        assert_eq!(d.sm.dwell_time(), Duration::from_millis(300));
+        assert_eq!(
+            d.at(1700, 300, 300, 0).last(),
+            Some(&Command::PlaySound(Sound::Click(ClickType::Double)))
+        );
+        assert_eq!(d.sm.dwell_time(), Duration::from_millis(500));
+
+        // over an on-screen keyboard, keyboard_dwell_time goes for all of them
+        d.sm.set_keyboard_dwell(Some(Duration::from_millis(250)));
+        d.sm.set_click_type(ClickType::Right);
+        assert_eq!(d.sm.dwell_time(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn click_type_lasts_one_dwell() {
+        let mut d = Driver::new(no_drag());
+        d.sm.set_click_type(ClickType::Right);
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(
+            d.at(600, 200, 200, 0),
+            vec![
+                Command::SecondaryClick,
+                Command::PlaySound(Sound::Click(ClickType::Right))
+            ]
+        );
+        assert_eq!(d.sm.next_click_type(), ClickType::Left);
+
+        d.sm.set_click_type(ClickType::Drag);
+        d.at(700, 300, 300, 0);
+        assert_eq!(
+            d.at(1200, 300, 300, 0),
+            vec![
+                Command::Press,
+                Command::PlaySound(Sound::Click(ClickType::Drag))
+            ]
+        );
+        assert!(d.sm.is_dragging());
+    }
+
+    #[test]
+    fn moving_drag_ends_at_max_duration() {
+        let mut d = Driver::new(DwellConfig {
+            max_drag_duration: Duration::from_secs(2),
+            ..DwellConfig::default()
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert!(d.sm.is_dragging());
+
+        let mut released_at = None;
+        for i in 1..40 {
+            let ms = 600 + i * 100;
+            if d.at(ms, 200 + i as i32 * 20, 200, 1 << 1)[..]
+                == [Command::Release, Command::PlaySound(Sound::DragEnd)]
+            {
+                released_at = Some(ms);
+                break;
+            }
+        }
+        assert_eq!(released_at, Some(2600));
+        assert!(!d.sm.is_dragging());
+    }
+
+    #[test]
+    fn sleeps_until_a_large_movement() {
+        let mut d = Driver::new(DwellConfig {
+            auto_disable_after: Some(Duration::from_secs(60)),
+            ..no_drag()
+        });
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.at(60_100, 200, 200, 0),
+            vec![Command::WriteStatus(Status::Disabled)]
+        );
+        assert!(d.sm.is_asleep());
+
+        // jiggling the pointer neither wakes it nor clicks
+        d.at(61_000, 250, 200, 0);
+        assert!(d.at(62_000, 250, 200, 0).is_empty());
+
+        assert_eq!(
+            d.at(63_000, 500, 200, 0),
+            vec![Command::WriteStatus(Status::Enabled)]
+        );
+        d.at(63_100, 600, 200, 0);
+        assert_eq!(d.at(63_600, 600, 200, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn drag_time_left_counts_down() {
+        let mut d = Driver::new(DwellConfig::default());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(d.sm.drag_time_left(d.start), None);
+        d.at(600, 200, 200, 0);
+        d.at(800, 200, 200, 1 << 1);
+        assert_eq!(
+            d.sm.drag_time_left(d.start + Duration::from_millis(800)),
+            Some(Duration::from_millis(300))
+        );
+    }
+
+    #[test]
+    fn click_now_skips_the_dwell() {
+        let mut d = Driver::new(DwellConfig::default());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        let now = d.start + Duration::from_millis(150);
+        assert_eq!(
+            d.sm.click_now(now),
+            vec![
+                Command::Press,
+                Command::PlaySound(Sound::Click(ClickType::Drag))
+            ]
+        );
+        assert_eq!(
+            d.sm.click_now(now),
+            vec![Command::Release, Command::PlaySound(Sound::DragEnd)]
+        );
+    }
+
+    #[test]
+    fn leaving_the_screen_waits_for_motion() {
+        let mut d = Driver::new(DwellConfig::default());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert_eq!(d.at(600, 200, 200, 0)[0], Command::Press);
+        assert_eq!(d.sm.pointer_left(), vec![Command::Release]);
+        assert!(d.at(2000, 200, 200, 0).is_empty());
+        assert_eq!(d.sm.last_decision(), &Decision::WaitingForMotion);
+    }
+
+    #[test]
+    fn display_off_waits_for_motion() {
+        let mut d = Driver::new(no_drag());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        assert!(d.sm.display_off().is_empty());
+        assert_eq!(d.sm.last_decision(), &Decision::DisplayOff);
+        // the dwell that was going doesn't finish once it's back on
+        assert!(d.at(1000, 200, 200, 0).is_empty());
+        d.at(1100, 300, 300, 0);
+        assert_eq!(d.at(1600, 300, 300, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn macro_zones_replay_instead_of_clicking() {
+        let mut config = no_drag();
+        config.macro_zones = vec![crate::config::MacroZone::parse("ok 250 250 100 100").unwrap()];
+        let mut d = Driver::new(config);
+        d.at(0, 100, 100, 0);
+        d.at(100, 300, 300, 0);
+        assert_eq!(d.at(600, 300, 300, 0)[0], Command::RunMacro(0));
+        assert_eq!(d.sm.last_decision(), &Decision::Macro("ok".to_string()));
+        d.at(700, 200, 200, 0);
+        assert_eq!(d.at(1200, 200, 200, 0)[0], Command::Press);
+    }
+
+    #[test]
+    fn panel_buttons_pick_and_pause() {
+        let mut d = Driver::new(no_drag());
+        let rect = |x| Rect {
+            x,
+            y: 250,
+            width: 100,
+            height: 100,
+        };
+        d.sm.set_panel(vec![
+            (rect(250), PanelButton::Pause),
+            (rect(350), PanelButton::Click(ClickType::Double)),
+        ]);
+        d.at(0, 500, 500, 0);
+        d.at(100, 400, 300, 0);
+        assert_eq!(
+            d.at(600, 400, 300, 0),
+            vec![Command::PlaySound(Sound::Selected(ClickType::Double))]
+        );
+        assert_eq!(d.sm.next_click_type(), ClickType::Double);
+
+        d.at(700, 300, 300, 0);
+        assert_eq!(
+            d.at(1200, 300, 300, 0)[0],
+            Command::WriteStatus(Status::Disabled)
+        );
+        assert!(!d.sm.is_active());
+
+        // Still resting on it from the pause, which doesn't count
+        let start = d.start;
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(d.sm.update_paused(300, 300, at(2000)).is_empty());
+        d.sm.update_paused(320, 320, at(2100));
+        assert!(d.sm.update_paused(320, 320, at(2500)).is_empty());
+        assert_eq!(
+            d.sm.update_paused(320, 320, at(2600))[0],
+            Command::WriteStatus(Status::Enabled)
+        );
+        assert!(d.sm.is_active());
+    }
+
+    #[test]
+    fn disabling_releases_drag() {
+        let mut d = Driver::new(DwellConfig::default());
+        d.at(0, 100, 100, 0);
+        d.at(100, 200, 200, 0);
+        d.at(600, 200, 200, 0);
+        assert_eq!(
+            d.sm.set_active(false),
+            vec![Command::Release, Command::WriteStatus(Status::Disabled)]
+        );
+        assert!(d.at(2000, 500, 500, 0).is_empty());
+    }
+}
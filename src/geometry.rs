@@ -0,0 +1,124 @@
+// Where the monitors are. One X screen can span several monitors of
+// different sizes, with dead space in the root window that no monitor
+// shows, so inside the root isn't the same as visible. The frontend fills
+// the list from XRandR.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x
+            && y >= self.y
+            && ((x - self.x) as u32) < self.width
+            && ((y - self.y) as u32) < self.height
+    }
+
+    // Squared distance from (x, y) to the nearest point inside
+    fn distance_sq(&self, x: i32, y: i32) -> i64 {
+        let gap = |p: i32, start: i32, len: u32| {
+            let end = start as i64 + len as i64 - 1;
+            if (p as i64) < start as i64 {
+                start as i64 - p as i64
+            } else if p as i64 > end {
+                p as i64 - end
+            } else {
+                0
+            }
+        };
+        let dx = gap(x, self.x, self.width);
+        let dy = gap(y, self.y, self.height);
+        dx * dx + dy * dy
+    }
+}
+
+// The monitor (x, y) is on. In the dead space between monitors, the nearest
+// one.
+pub fn monitor_at(monitors: &[Rect], x: i32, y: i32) -> Option<Rect> {
+    monitor_index_at(monitors, x, y).map(|i| monitors[i])
+}
+
+// The same, as where it is in the list
+pub fn monitor_index_at(monitors: &[Rect], x: i32, y: i32) -> Option<usize> {
+    (0..monitors.len()).min_by_key(|&i| monitors[i].distance_sq(x, y))
+}
+
+// How many of the monitor's pixels make mm millimeters, given how big
+// XRandR says it is. Some report 0 by 0, projectors mostly, and some a
+// made up size; the diagonal makes a wrong aspect ratio matter less.
+pub fn mm_to_pixels(mm: u32, monitor: Rect, size_mm: (u32, u32)) -> Option<u32> {
+    if size_mm.0 == 0 || size_mm.1 == 0 {
+        return None;
+    }
+    let pixels = (monitor.width as f64).hypot(monitor.height as f64);
+    let physical = (size_mm.0 as f64).hypot(size_mm.1 as f64);
+    Some((mm as f64 * pixels / physical).round() as u32)
+}
+
+// Where to put the top left of a width by height box that wants to be at
+// (x, y), so that it's all inside bounds. Boxes bigger than bounds keep to
+// its top left.
+pub fn keep_inside(bounds: Rect, x: i32, y: i32, width: u32, height: u32) -> (i32, i32) {
+    let fit = |p: i32, start: i32, bound: u32, len: u32| {
+        let last = start + bound.saturating_sub(len) as i32;
+        p.min(last).max(start)
+    };
+    (
+        fit(x, bounds.x, bounds.width, width),
+        fit(y, bounds.y, bounds.height, height),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 1920x1080 laptop panel with a 2560x1440 monitor to its right, lined
+    // up at the top, so there's dead space under the laptop's right edge
+    const LAPTOP: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 1920,
+        height: 1080,
+    };
+    const EXTERNAL: Rect = Rect {
+        x: 1920,
+        y: 0,
+        width: 2560,
+        height: 1440,
+    };
+
+    #[test]
+    fn finds_the_monitor() {
+        let monitors = [LAPTOP, EXTERNAL];
+        assert_eq!(monitor_at(&monitors, 1919, 500), Some(LAPTOP));
+        assert_eq!(monitor_at(&monitors, 1920, 500), Some(EXTERNAL));
+        assert_eq!(monitor_at(&monitors, 100, 1300), Some(LAPTOP));
+        assert_eq!(monitor_at(&monitors, 1900, 1300), Some(EXTERNAL));
+        assert_eq!(monitor_at(&[], 0, 0), None);
+        assert!(!LAPTOP.contains(100, 1300));
+    }
+
+    #[test]
+    fn keeps_boxes_on_the_monitor() {
+        assert_eq!(keep_inside(LAPTOP, 1900, 10, 60, 20), (1860, 10));
+        assert_eq!(keep_inside(LAPTOP, -5, 1075, 60, 20), (0, 1060));
+        assert_eq!(keep_inside(EXTERNAL, 1900, 10, 60, 20), (1920, 10));
+        assert_eq!(keep_inside(LAPTOP, 500, 500, 4000, 20), (0, 500));
+    }
+
+    #[test]
+    fn converts_millimeters() {
+        // A 14 inch laptop is about 157 pixels an inch, a 27 inch
+        // monitor about 109
+        assert_eq!(mm_to_pixels(3, LAPTOP, (309, 174)), Some(19));
+        assert_eq!(mm_to_pixels(3, EXTERNAL, (597, 336)), Some(13));
+        assert_eq!(mm_to_pixels(3, EXTERNAL, (0, 0)), None);
+        assert_eq!(monitor_index_at(&[LAPTOP, EXTERNAL], 2000, 10), Some(1));
+    }
+}
@@ -0,0 +1,156 @@
+// Coarse strokes drawn after a gesture dwell, recognized from the points the
+// pointer went through on the way. Big and forgiving on purpose, they're for
+// people who can move a long way but not precisely.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    // Two legs at a corner, whichever way round
+    L,
+    // Ends back near where it started
+    Circle,
+    // Back and forth at least twice along one axis
+    ZigZag,
+}
+
+impl Shape {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shape::L => "l",
+            Shape::Circle => "circle",
+            Shape::ZigZag => "zigzag",
+        }
+    }
+}
+
+// Anything smaller is the pointer wandering, not a stroke
+const MIN_SIZE: i32 = 60;
+
+pub fn recognize(points: &[(i32, i32)]) -> Option<Shape> {
+    let (&first, &last) = (points.first()?, points.last()?);
+    let min_x = points.iter().map(|p| p.0).min()?;
+    let max_x = points.iter().map(|p| p.0).max()?;
+    let min_y = points.iter().map(|p| p.1).min()?;
+    let max_y = points.iter().map(|p| p.1).max()?;
+    let (width, height) = (max_x - min_x, max_y - min_y);
+    let size = width.max(height);
+    if size < MIN_SIZE {
+        return None;
+    }
+
+    if distance(first, last) < size as f64 / 4.0 {
+        // A line there and back isn't a circle
+        return (width.min(height) >= size / 2).then_some(Shape::Circle);
+    }
+
+    let threshold = size / 5;
+    let x_turns = reversals(points.iter().map(|p| p.0), threshold);
+    let y_turns = reversals(points.iter().map(|p| p.1), threshold);
+    if x_turns >= 2 || y_turns >= 2 {
+        return Some(Shape::ZigZag);
+    }
+
+    // Without turning back, it's an L if it went well out of the straight
+    // line between its ends: legs a and b put the corner ab/sqrt(a²+b²) out
+    let chord = distance(first, last);
+    let corner = points
+        .iter()
+        .map(|&p| distance_from_line(p, first, last))
+        .fold(0.0, f64::max);
+    (x_turns == 0 && y_turns == 0 && corner >= chord / 4.0).then_some(Shape::L)
+}
+
+fn distance(a: (i32, i32), b: (i32, i32)) -> f64 {
+    let (dx, dy) = ((b.0 - a.0) as f64, (b.1 - a.1) as f64);
+    (dx * dx + dy * dy).sqrt()
+}
+
+fn distance_from_line(p: (i32, i32), a: (i32, i32), b: (i32, i32)) -> f64 {
+    let (dx, dy) = ((b.0 - a.0) as f64, (b.1 - a.1) as f64);
+    let cross = dx * (p.1 - a.1) as f64 - dy * (p.0 - a.0) as f64;
+    cross.abs() / distance(a, b)
+}
+
+// How many times the values change direction by more than threshold, so
+// jitter on the way doesn't count
+fn reversals(mut values: impl Iterator<Item = i32>, threshold: i32) -> usize {
+    let start = match values.next() {
+        Some(v) => v,
+        None => return 0,
+    };
+    let mut turns = 0;
+    // 0 until it first gets threshold away from the start
+    let mut direction = 0;
+    let mut extreme = start;
+    for v in values {
+        if direction == 0 {
+            if (v - start).abs() >= threshold {
+                direction = (v - start).signum();
+                extreme = v;
+            }
+        } else if (v - extreme) * direction > 0 {
+            extreme = v;
+        } else if (extreme - v) * direction >= threshold {
+            turns += 1;
+            direction = -direction;
+            extreme = v;
+        }
+    }
+    turns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Straight lines between corners, a point every 10 pixels like a slow tick
+    fn stroke(corners: &[(i32, i32)]) -> Vec<(i32, i32)> {
+        let mut points = vec![corners[0]];
+        for pair in corners.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let steps = ((b.0 - a.0).abs().max((b.1 - a.1).abs()) / 10).max(1);
+            for i in 1..=steps {
+                points.push((a.0 + (b.0 - a.0) * i / steps, a.1 + (b.1 - a.1) * i / steps));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn recognizes_the_shapes() {
+        assert_eq!(
+            recognize(&stroke(&[(100, 100), (100, 300), (220, 300)])),
+            Some(Shape::L)
+        );
+        assert_eq!(
+            recognize(&stroke(&[(300, 100), (100, 100), (100, 300)])),
+            Some(Shape::L)
+        );
+        assert_eq!(
+            recognize(&stroke(&[(100, 100), (300, 100), (100, 200), (300, 200)])),
+            Some(Shape::ZigZag)
+        );
+
+        let circle: Vec<(i32, i32)> = (0..=32)
+            .map(|i| {
+                let a = i as f64 / 32.0 * std::f64::consts::TAU;
+                (
+                    200 + (a.cos() * 100.0) as i32,
+                    200 + (a.sin() * 100.0) as i32,
+                )
+            })
+            .collect();
+        assert_eq!(recognize(&circle), Some(Shape::Circle));
+    }
+
+    #[test]
+    fn ignores_wobbles_and_lines() {
+        assert_eq!(recognize(&stroke(&[(100, 100), (130, 120)])), None);
+        assert_eq!(recognize(&stroke(&[(100, 100), (300, 250)])), None);
+        // there and back
+        assert_eq!(
+            recognize(&stroke(&[(100, 100), (300, 100), (110, 100)])),
+            None
+        );
+        assert_eq!(recognize(&[]), None);
+    }
+}
@@ -0,0 +1,258 @@
+// `rtmouse-rs heatmap`: the history_log drawn over the screen, clicks in
+// green and abandoned dwells in red, so it's easy to see where pointing is
+// hard. Written as a PNG by hand, it's simple enough not to need a crate.
+
+use crate::history::Record;
+use crate::stats::Event;
+
+// Each event spreads over a disc this big, in pixels
+const RADIUS: i32 = 24;
+
+// 8 bit RGB, row by row
+pub fn render(records: &[Record], width: u32, height: u32) -> Vec<u8> {
+    let (w, h) = (width as i32, height as i32);
+    let mut clicks = vec![0f32; (width * height) as usize];
+    let mut aborted = vec![0f32; (width * height) as usize];
+
+    for r in records {
+        let grid = match r.event {
+            Event::Click(_) => &mut clicks,
+            Event::Aborted => &mut aborted,
+            Event::DragEnd => continue,
+        };
+        for y in (r.y - RADIUS).max(0)..(r.y + RADIUS + 1).min(h) {
+            for x in (r.x - RADIUS).max(0)..(r.x + RADIUS + 1).min(w) {
+                let d2 = ((x - r.x).pow(2) + (y - r.y).pow(2)) as f32;
+                let falloff = 1.0 - d2 / (RADIUS * RADIUS) as f32;
+                if falloff > 0.0 {
+                    grid[(y * w + x) as usize] += falloff * falloff;
+                }
+            }
+        }
+    }
+
+    // The square root keeps a few busy spots from washing out the rest
+    let scale = |grid: &[f32]| {
+        let max = grid
+            .iter()
+            .cloned()
+            .fold(0.0, f32::max)
+            .max(f32::MIN_POSITIVE);
+        move |v: f32| ((v / max).sqrt() * 255.0) as u8
+    };
+    let (click_level, aborted_level) = (scale(&clicks), scale(&aborted));
+
+    let mut rgb = Vec::with_capacity(clicks.len() * 3);
+    for (c, a) in clicks.iter().zip(&aborted) {
+        rgb.extend_from_slice(&[aborted_level(*a), click_level(*c), 0]);
+    }
+    rgb
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+struct Bits {
+    out: Vec<u8>,
+    acc: u32,
+    n: u32,
+}
+
+impl Bits {
+    // Least significant bit first, how deflate packs everything but the
+    // Huffman codes
+    fn put(&mut self, value: u32, n: u32) {
+        self.acc |= value << self.n;
+        self.n += n;
+        while self.n >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.n -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn put_code(&mut self, code: u32, n: u32) {
+        let reversed = code.reverse_bits() >> (32 - n);
+        self.put(reversed, n);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.n > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+// From RFC 1951 3.2.5
+const LENGTH_BASE: [u32; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u32; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// The fixed Huffman code for a literal/length symbol
+fn put_symbol(bits: &mut Bits, symbol: u32) {
+    match symbol {
+        0..=143 => bits.put_code(0x30 + symbol, 8),
+        144..=255 => bits.put_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.put_code(symbol - 256, 7),
+        _ => bits.put_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+// Index of the last base that's <= value
+fn bucket(bases: &[u32], value: u32) -> usize {
+    bases.iter().rposition(|&b| b <= value).unwrap_or(0)
+}
+
+// One fixed Huffman block. The only matches tried are repeats of the pixel
+// to the left and of the row above, which is all a heatmap needs.
+fn deflate(data: &[u8], distances: &[usize]) -> Vec<u8> {
+    let mut bits = Bits {
+        out: Vec::new(),
+        acc: 0,
+        n: 0,
+    };
+    // Last block, fixed codes
+    bits.put(1, 1);
+    bits.put(1, 2);
+
+    let mut i = 0;
+    while i < data.len() {
+        let (len, dist) = distances
+            .iter()
+            .filter(|&&d| d <= i && d <= 32768)
+            .map(|&d| {
+                let len = (0..258.min(data.len() - i))
+                    .take_while(|&k| data[i + k] == data[i + k - d])
+                    .count();
+                (len, d)
+            })
+            .max()
+            .unwrap_or((0, 0));
+
+        if len >= 3 {
+            let l = bucket(&LENGTH_BASE, len as u32);
+            put_symbol(&mut bits, 257 + l as u32);
+            bits.put(len as u32 - LENGTH_BASE[l], LENGTH_EXTRA[l]);
+            let d = bucket(&DIST_BASE, dist as u32);
+            bits.put_code(d as u32, 5);
+            bits.put(dist as u32 - DIST_BASE[d], DIST_EXTRA[d]);
+            i += len;
+        } else {
+            put_symbol(&mut bits, data[i] as u32);
+            i += 1;
+        }
+    }
+    put_symbol(&mut bits, 256);
+    bits.finish()
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3 + 1;
+    let mut raw = Vec::with_capacity(stride * height as usize);
+    for row in rgb.chunks(width as usize * 3) {
+        // No filter
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    zlib.extend(deflate(&raw, &[3, stride]));
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, deflate, no filter choice, not interlaced
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &ihdr);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwell::ClickType;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn clicks_green_aborts_red() {
+        let record = |event, x| Record {
+            time: UNIX_EPOCH,
+            event,
+            x,
+            y: 50,
+            class: String::new(),
+        };
+        let records = vec![
+            record(Event::Click(ClickType::Left), 20),
+            record(Event::Aborted, 80),
+        ];
+        let rgb = render(&records, 100, 100);
+        let at = |x: usize, y: usize| &rgb[(y * 100 + x) * 3..][..3];
+        assert_eq!(at(20, 50), &[0, 255, 0]);
+        assert_eq!(at(80, 50), &[255, 0, 0]);
+        assert_eq!(at(50, 5), &[0, 0, 0]);
+    }
+
+    #[test]
+    fn uniform_rows_compress() {
+        let rgb = vec![7u8; 300 * 200 * 3];
+        let png = png(300, 200, &rgb);
+        assert!(png.starts_with(b"\x89PNG"));
+        assert!(png.len() < 4000, "{} bytes", png.len());
+    }
+}
@@ -0,0 +1,198 @@
+// With history_log on, every click, drag and abandoned dwell is appended to
+// paths::history_file() with where it happened and the window class, for
+// `rtmouse-rs export`. One tab separated line per event:
+//
+//   unix time in ms, event, x, y, window class
+
+use crate::paths;
+use crate::stats::{self, Event};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub time: SystemTime,
+    pub event: Event,
+    pub x: i32,
+    pub y: i32,
+    // Empty when there's no window or it has no WM_CLASS
+    pub class: String,
+}
+
+// Then it's moved to old_history_file(), so together they hold between this
+// and twice this much
+const MAX_FILE_BYTES: u64 = 4 << 20;
+
+fn millis(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+pub fn to_line(record: &Record) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\n",
+        millis(record.time),
+        record.event.as_str(),
+        record.x,
+        record.y,
+        record.class.replace(|c: char| c.is_control(), " ")
+    )
+}
+
+pub fn parse_line(line: &str) -> Option<Record> {
+    let mut fields = line.splitn(5, '\t');
+    let ms: u64 = fields.next()?.parse().ok()?;
+    Some(Record {
+        time: UNIX_EPOCH + Duration::from_millis(ms),
+        event: Event::parse(fields.next()?)?,
+        x: fields.next()?.parse().ok()?,
+        y: fields.next()?.parse().ok()?,
+        class: fields.next()?.to_string(),
+    })
+}
+
+pub fn append_to(path: &Path, old_path: &Path, record: &Record) -> io::Result<()> {
+    if fs::metadata(path).is_ok_and(|m| m.len() >= MAX_FILE_BYTES) {
+        fs::rename(path, old_path)?;
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(to_line(record).as_bytes())
+}
+
+pub fn append(record: &Record) {
+    if let (Some(path), Some(old_path)) = (paths::history_file(), paths::old_history_file()) {
+        if let Err(e) = append_to(&path, &old_path, record) {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Oldest first, skipping lines that don't parse
+pub fn read_from(paths: &[&Path]) -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for path in paths {
+        match fs::read_to_string(path) {
+            Ok(text) => records.extend(text.lines().filter_map(parse_line)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(records)
+}
+
+pub fn read() -> io::Result<Vec<Record>> {
+    match (paths::old_history_file(), paths::history_file()) {
+        (Some(old_path), Some(path)) => read_from(&[&old_path, &path]),
+        _ => Ok(Vec::new()),
+    }
+}
+
+// 2026-10-14T09:30:00.250Z
+pub fn iso_time(t: SystemTime) -> String {
+    let ms = millis(t);
+    let secs = (ms / 1000) % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        stats::date(t),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ms % 1000
+    )
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn to_csv(records: &[Record]) -> String {
+    let mut out = "timestamp,type,x,y,window_class\n".to_string();
+    for r in records {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{}",
+            iso_time(r.time),
+            r.event.as_str(),
+            r.x,
+            r.y,
+            csv_field(&r.class)
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwell::ClickType;
+
+    fn record(ms: u64, event: Event, class: &str) -> Record {
+        Record {
+            time: UNIX_EPOCH + Duration::from_millis(ms),
+            event,
+            x: 10,
+            y: -20,
+            class: class.to_string(),
+        }
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let r = record(
+            1_760_434_200_250,
+            Event::Click(ClickType::Double),
+            "Gimp, 2",
+        );
+        assert_eq!(parse_line(to_line(&r).trim_end()), Some(r));
+        assert_eq!(parse_line("12\tleft\t1"), None);
+        assert_eq!(parse_line("12\tmiddle\t1\t2\t"), None);
+    }
+
+    #[test]
+    fn csv_quotes_classes() {
+        let records = vec![
+            record(1_760_434_200_250, Event::Aborted, ""),
+            record(
+                1_760_434_201_000,
+                Event::Click(ClickType::Left),
+                "say \"hi\", ok",
+            ),
+        ];
+        assert_eq!(
+            to_csv(&records),
+            "timestamp,type,x,y,window_class\n\
+             2025-10-14T09:30:00.250Z,aborted,10,-20,\n\
+             2025-10-14T09:30:01.000Z,left,10,-20,\"say \"\"hi\"\", ok\"\n"
+        );
+    }
+
+    #[test]
+    fn rotates_when_full() {
+        let dir = std::env::temp_dir().join(format!("rtmouse-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let (path, old_path) = (dir.join("history"), dir.join("history.old"));
+
+        let first = record(1, Event::DragEnd, "");
+        append_to(&path, &old_path, &first).unwrap();
+        let f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        f.set_len(MAX_FILE_BYTES).unwrap();
+        let second = record(2, Event::Click(ClickType::Right), "xterm");
+        append_to(&path, &old_path, &second).unwrap();
+
+        assert_eq!(read_from(&[&path]).unwrap(), vec![second.clone()]);
+        assert_eq!(read_from(&[&old_path, &path]).unwrap(), vec![first, second]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
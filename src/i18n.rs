@@ -0,0 +1,155 @@
+// Messages that reach people who may never look at a terminal, like desktop
+// notifications, in the user's language. Translations are `key = "text"`
+// files named after the language (de.toml, pt_BR.toml) in
+// $XDG_DATA_HOME/rtmouse/locale or /usr/share/rtmouse/locale. Anything a
+// translation doesn't have stays in English.
+
+use crate::config::{self, Value};
+use crate::paths;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    XwaylandWarning,
+    ClicksNotDelivered,
+}
+
+impl Message {
+    pub fn key(self) -> &'static str {
+        match self {
+            Message::XwaylandWarning => "xwayland_warning",
+            Message::ClicksNotDelivered => "clicks_not_delivered",
+        }
+    }
+
+    pub fn english(self) -> &'static str {
+        match self {
+            Message::XwaylandWarning => {
+                "rtmouse is running under Xwayland. Dwell clicks will only reach X11 \
+                 applications, native Wayland windows will not receive them."
+            }
+            Message::ClicksNotDelivered => {
+                "rtmouse's clicks are not being delivered. The X server refused them, \
+                 even after rtmouse set up its input simulation again."
+            }
+        }
+    }
+}
+
+// The languages to try, best first, the way gettext picks them: LANGUAGE is
+// a list, otherwise the first of LC_ALL, LC_MESSAGES and LANG that's set.
+// "pt_BR.UTF-8@euro" gives pt_BR, then pt.
+pub fn languages_from(language: Option<&str>, locale: Option<&str>) -> Vec<String> {
+    let locale = locale.filter(|l| !l.is_empty() && *l != "C" && *l != "POSIX");
+    let mut names: Vec<&str> = match language.filter(|l| !l.is_empty()) {
+        // LANGUAGE is ignored under the C locale
+        Some(list) if locale.is_some() => list.split(':').collect(),
+        _ => locale.into_iter().collect(),
+    };
+    names.retain(|n| !n.is_empty());
+
+    let mut out = Vec::new();
+    for name in names {
+        let name = name.split(['.', '@']).next().unwrap_or("");
+        for candidate in [name, name.split('_').next().unwrap_or("")] {
+            if !candidate.is_empty() && !out.iter().any(|o| o == candidate) {
+                out.push(candidate.to_string());
+            }
+        }
+    }
+    out
+}
+
+fn languages() -> Vec<String> {
+    let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+    let locale = var("LC_ALL")
+        .or_else(|| var("LC_MESSAGES"))
+        .or_else(|| var("LANG"));
+    languages_from(var("LANGUAGE").as_deref(), locale.as_deref())
+}
+
+fn locale_dirs() -> Vec<PathBuf> {
+    paths::data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("locale"))
+        .collect()
+}
+
+// Only the string values, a broken line just means that message stays in
+// English
+pub fn parse_catalog(text: &str) -> HashMap<String, String> {
+    let (entries, _) = config::parse(text);
+    entries
+        .into_iter()
+        .filter_map(|e| match e.value {
+            Value::Str(s) => Some((e.key, s)),
+            _ => None,
+        })
+        .collect()
+}
+
+// Best language first
+fn catalogs() -> &'static [HashMap<String, String>] {
+    static CATALOGS: OnceLock<Vec<HashMap<String, String>>> = OnceLock::new();
+    CATALOGS.get_or_init(|| {
+        let dirs = locale_dirs();
+        languages()
+            .iter()
+            .filter_map(|lang| {
+                dirs.iter().find_map(|dir| {
+                    std::fs::read_to_string(dir.join(format!("{}.toml", lang))).ok()
+                })
+            })
+            .map(|text| parse_catalog(&text))
+            .collect()
+    })
+}
+
+pub fn lookup(catalogs: &[HashMap<String, String>], msg: Message) -> String {
+    catalogs
+        .iter()
+        .find_map(|c| c.get(msg.key()))
+        .map_or(msg.english(), |s| s.as_str())
+        .to_string()
+}
+
+pub fn text(msg: Message) -> String {
+    lookup(catalogs(), msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_languages_like_gettext() {
+        assert_eq!(
+            languages_from(None, Some("pt_BR.UTF-8")),
+            vec!["pt_BR", "pt"]
+        );
+        assert_eq!(
+            languages_from(Some("de:fr_CA"), Some("en_US.UTF-8")),
+            vec!["de", "fr_CA", "fr"]
+        );
+        assert_eq!(languages_from(Some("de"), Some("C")), Vec::<String>::new());
+        assert_eq!(languages_from(None, None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn falls_back_to_english() {
+        let catalogs = vec![
+            parse_catalog("other = \"x\"\n"),
+            parse_catalog("xwayland_warning = \"unter Xwayland\"\n"),
+        ];
+        assert_eq!(
+            lookup(&catalogs, Message::XwaylandWarning),
+            "unter Xwayland"
+        );
+        assert_eq!(
+            lookup(&[], Message::XwaylandWarning),
+            Message::XwaylandWarning.english()
+        );
+    }
+}
@@ -0,0 +1,149 @@
+// Keyboard lights as feedback, for machines without speakers and people who
+// can't watch the screen: led_click blinks on every click, led_enabled stays
+// lit while dwell clicking is on. They're XKB named indicators, so "Scroll
+// Lock" rather than an LED number that differs between keyboards.
+//
+// An indicator that follows a lock, like Caps Lock, can't be lit without
+// locking it, XKB changes the keyboard state to match. That's fine for someone
+// who never types, and why both options are off by default.
+
+use crate::config::DwellConfig;
+use std::ffi::CString;
+use std::os::raw::{c_uint, c_void};
+use std::ptr::null_mut;
+use std::time::{Duration, Instant};
+use x11::xlib;
+
+// The x11 crate's declaration of this one is missing arguments. The map is
+// always null here, so it needn't be spelled out
+extern "C" {
+    fn XkbSetNamedIndicator(
+        display: *mut xlib::Display,
+        device_spec: c_uint,
+        name: xlib::Atom,
+        change_state: xlib::Bool,
+        state: xlib::Bool,
+        create_new: xlib::Bool,
+        change_map: xlib::Bool,
+        map: *mut c_void,
+    ) -> xlib::Bool;
+}
+
+// via X11/XKB.h
+const XKB_USE_CORE_KBD: c_uint = 0x100;
+
+// Long enough to see, short enough to stay apart on a double click. It's
+// ended by the first tick after it, so it lasts up to a tick longer.
+const BLINK: Duration = Duration::from_millis(150);
+
+struct Light {
+    name: xlib::Atom,
+    // How it was before rtmouse touched it, put back by restore
+    was_on: bool,
+}
+
+pub struct StateLeds {
+    click: Option<Light>,
+    enabled: Option<Light>,
+    // When to turn the click light off again
+    lit_until: Option<Instant>,
+}
+
+impl StateLeds {
+    pub fn new() -> StateLeds {
+        StateLeds {
+            click: None,
+            enabled: None,
+            lit_until: None,
+        }
+    }
+}
+
+impl Default for StateLeds {
+    fn default() -> StateLeds {
+        StateLeds::new()
+    }
+}
+
+// None for an empty name, or with a complaint for one the keyboard doesn't have
+fn find(display: *mut xlib::Display, option: &str, name: &str) -> Option<Light> {
+    if name.is_empty() {
+        return None;
+    }
+    let atom_name = CString::new(name).ok()?;
+    let atom = unsafe { xlib::XInternAtom(display, atom_name.as_ptr(), xlib::False) };
+    let mut state = 0;
+    let found = unsafe {
+        xlib::XkbGetNamedIndicator(
+            display,
+            atom,
+            null_mut(),
+            &mut state,
+            null_mut(),
+            null_mut(),
+        ) != 0
+    };
+    if !found {
+        eprintln!(
+            "Error: {}: the keyboard has no \"{}\" light, it's off",
+            option, name
+        );
+        return None;
+    }
+    Some(Light {
+        name: atom,
+        was_on: state != 0,
+    })
+}
+
+fn set(display: *mut xlib::Display, light: &Light, on: bool) {
+    unsafe {
+        XkbSetNamedIndicator(
+            display,
+            XKB_USE_CORE_KBD,
+            light.name,
+            xlib::True,
+            on as xlib::Bool,
+            xlib::False,
+            xlib::False,
+            null_mut(),
+        );
+        xlib::XFlush(display);
+    }
+}
+
+pub fn init(st: &mut StateLeds, display: *mut xlib::Display, config: &DwellConfig) {
+    st.click = find(display, "led_click", &config.led_click);
+    st.enabled = find(display, "led_enabled", &config.led_enabled);
+}
+
+pub fn blink(st: &mut StateLeds, display: *mut xlib::Display, now: Instant) {
+    if let Some(light) = &st.click {
+        set(display, light, !light.was_on);
+        st.lit_until = Some(now + BLINK);
+    }
+}
+
+// Call every tick, it ends the blinks
+pub fn update(st: &mut StateLeds, display: *mut xlib::Display, now: Instant) {
+    if st.lit_until.is_some_and(|t| now >= t) {
+        st.lit_until = None;
+        if let Some(light) = &st.click {
+            set(display, light, light.was_on);
+        }
+    }
+}
+
+pub fn show_enabled(st: &StateLeds, display: *mut xlib::Display, enabled: bool) {
+    if let Some(light) = &st.enabled {
+        set(display, light, enabled);
+    }
+}
+
+// Both lights back the way rtmouse found them
+pub fn restore(st: &mut StateLeds, display: *mut xlib::Display) {
+    st.lit_until = None;
+    for light in [&st.click, &st.enabled].iter().filter_map(|l| l.as_ref()) {
+        set(display, light, light.was_on);
+    }
+}
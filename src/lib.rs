@@ -0,0 +1,3192 @@
+extern crate x11;
+
+use std::ffi::{CStr, CString};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::{Command, Stdio};
+use std::ptr::null;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use x11::{dpms, xinput2, xlib, xrandr, xtest};
+
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod diagnose;
+pub mod doctor;
+pub mod dwell;
+pub mod geometry;
+pub mod gesture;
+pub mod heatmap;
+pub mod history;
+pub mod i18n;
+pub mod leds;
+pub mod macros;
+pub mod monitor;
+#[cfg(feature = "overlays")]
+pub mod overlay;
+pub mod panel;
+pub mod paths;
+pub mod power;
+pub mod setup;
+pub mod shake;
+#[cfg(feature = "sound")]
+pub mod sound;
+pub mod stats;
+pub mod supervise;
+
+use config::DwellConfig;
+use dwell::{Command as DwellCommand, DwellStateMachine, Sample, ScrollDirection, Status};
+
+pub const TIMER_INTERVAL_MS: u32 = 100;
+
+pub struct StateX11 {
+    pub display: *mut xlib::Display,
+    xi_extension_opcode: i32,
+    root: xlib::Window,
+    // The pointer mapping, physical button n at index n - 1 and the logical
+    // button it produces as the value. Refreshed on MappingNotify and XI2
+    // device changes rather than fetched for every click.
+    button_map: Vec<u8>,
+    // primary_button, which overrides it for left clicks
+    primary_button: Option<u8>,
+    // Keycode of the grabbed click_key, 0 for none
+    click_keycode: u32,
+    // Message type of control::Request client messages
+    control_atom: xlib::Atom,
+    state_atom: xlib::Atom,
+    utf8_string_atom: xlib::Atom,
+    wm_state_atom: xlib::Atom,
+    net_wm_name_atom: xlib::Atom,
+    net_close_window_atom: xlib::Atom,
+    accel_speed_atom: xlib::Atom,
+    float_atom: xlib::Atom,
+    // Roots of the screens we dwell on, see config screens
+    watched_roots: Vec<xlib::Window>,
+    // From the event reader thread, see spawn_event_reader
+    events: Option<mpsc::Receiver<InputEvent>>,
+    // Left, right, up and down for nudge_keys, 0 for missing ones. Grabbed
+    // on this connection, and only while the dwell timer runs.
+    nudge_keycodes: [u32; 4],
+    nudge_grabbed: bool,
+    // The default screen's monitors, see query_monitors
+    monitors: Vec<geometry::Rect>,
+    // And how big each one is in millimeters, 0 by 0 when it's unknown
+    monitor_sizes: Vec<(u32, u32)>,
+    // Tablets and touchscreens in absolute mode, see refresh_absolute_devices
+    absolute_devices: Vec<i32>,
+    // The server does DPMS, so display_powered_down can ask it
+    has_dpms: bool,
+}
+
+pub struct StateTiming {
+    enabled: bool,
+    tick_start: Instant,
+}
+
+pub struct StateDelivery {
+    // Clicks the X server refused in a row, even after setting XTest up again
+    failures: u32,
+}
+
+pub struct StateExplain {
+    enabled: bool,
+    last_line: String,
+    // Reused for formatting each tick's line
+    line: String,
+    // A copy of the trace for `rtmouse diagnose`
+    file: Option<fs::File>,
+    file_lines: usize,
+}
+
+pub struct StateMainLoop {
+    // What was loaded at startup, the dwell state machine has its own copy
+    pub config: DwellConfig,
+    pub dwell: DwellStateMachine,
+    pub st_explain: StateExplain,
+    pub st_x11: StateX11,
+    pub st_is_click_inhibited: StateIsClickInhibited,
+    st_timing: StateTiming,
+    st_delivery: StateDelivery,
+    st_leds: leds::StateLeds,
+    #[cfg(feature = "sound")]
+    st_sound: sound::StateSound,
+    send_event_target: Option<xlib::Window>,
+    // When the primary button was pressed, while we're holding it
+    pressed_at: Option<Instant>,
+    // Where the pointer was when we last asked the server
+    pointer: Option<(i32, i32)>,
+    // precision_mode slowed this device down from this accel speed, with
+    // the pointer here
+    precision: Option<(i32, f32, (i32, i32))>,
+    // When display_powered_down last asked the server, and what it said
+    dpms_checked: Option<Instant>,
+    // Last time keep_awake reset the idle timers
+    idle_reset: Option<Instant>,
+    display_off: bool,
+    // And the top-level window it was over, and the root of its screen
+    pointer_window: xlib::Window,
+    pointer_root: xlib::Window,
+    #[cfg(feature = "overlays")]
+    anchor_dot: Option<overlay::Overlay>,
+    #[cfg(feature = "overlays")]
+    anchor_dot_at: Option<(i32, i32)>,
+    #[cfg(feature = "overlays")]
+    drag_bar: Option<overlay::Overlay>,
+    // Where the drag bar is, and how much of it is filled
+    #[cfg(feature = "overlays")]
+    drag_bar_at: Option<(i32, i32, u32)>,
+    #[cfg(feature = "overlays")]
+    countdown: Option<overlay::Overlay>,
+    #[cfg(feature = "overlays")]
+    countdown_at: Option<(i32, i32, String)>,
+    overlay_theme: config::Theme,
+    #[cfg(feature = "overlays")]
+    overlay_style: overlay::Style,
+    pub control: Option<UnixListener>,
+    // Control socket connections that sent `subscribe`
+    subscribers: Vec<UnixStream>,
+    // Last value written to STATE_PROPERTY
+    published_state: String,
+    stats: stats::Stats,
+    stats_saved: Instant,
+    // The decision stats last counted from, and where the pointer was then
+    counted_decision: dwell::Decision,
+    counted_at: (i32, i32),
+    // The day of the last reset-clicks and how many clicks it had by then.
+    // Not saved, a restart counts the whole day again.
+    clicks_reset: Option<(String, u64)>,
+    monitor: monitor::Monitor,
+    // `macro record` in progress, and a macro being replayed
+    recording: Option<macros::Recording>,
+    replay: Option<macros::Replay>,
+    // The pause button and control panel, whichever are on
+    panels: Vec<panel::Panel>,
+    st_shake: shake::StateShake,
+    // When a shake last toggled dwell clicking, see shake_toggle
+    shaken_at: Option<Instant>,
+    // The last window checked against keyboard_classes, and whether it
+    // matched
+    keyboard_checked: Option<(xlib::Window, bool)>,
+    // device_profiles with their options, loaded at startup, and what the
+    // other devices get: the options and profile rtmouse started with
+    device_profiles: Vec<(config::DeviceProfile, DwellConfig)>,
+    startup_config: DwellConfig,
+    startup_profile: Option<String>,
+    // The device behind the last motion, and the entry in device_profiles
+    // it picked
+    moving_device: Option<i32>,
+    device_profile: Option<usize>,
+}
+
+#[cfg(feature = "sound")]
+fn play_click_sound(st: &mut StateMainLoop, which: dwell::Sound) {
+    let config = &st.config;
+    let (wanted, tone) = match which {
+        dwell::Sound::Click(dwell::ClickType::Drag) => (
+            config.drag_start_sound,
+            sound::Tone::Click(dwell::ClickType::Drag),
+        ),
+        dwell::Sound::Click(click) => (config.click_sound, sound::Tone::Click(click)),
+        dwell::Sound::DragEnd => (config.drag_end_sound, sound::Tone::DragEnd),
+        dwell::Sound::Selected(click) => (config.click_type_sound, sound::Tone::Selected(click)),
+    };
+    if config.sound_enabled && wanted {
+        sound::play_tone(&mut st.st_sound, tone, config);
+    }
+}
+
+// For the stuck drag watchdog, so it plays even with sound_enabled off
+#[cfg(feature = "sound")]
+fn play_warning_sound(st: &mut StateMainLoop) {
+    sound::play_tone(&mut st.st_sound, sound::Tone::Warning, &st.config);
+}
+
+#[cfg(feature = "sound")]
+fn choose_sound_theme(st: &mut StateMainLoop, name: &str) -> Result<(), String> {
+    sound::choose_theme(&mut st.st_sound, name)
+}
+
+#[cfg(feature = "sound")]
+fn sound_theme_name(st: &StateMainLoop) -> &str {
+    sound::theme_name(&st.st_sound, &st.config)
+}
+
+#[cfg(not(feature = "sound"))]
+fn choose_sound_theme(_st: &mut StateMainLoop, _name: &str) -> Result<(), String> {
+    Err("this rtmouse was built without sounds".to_string())
+}
+
+#[cfg(not(feature = "sound"))]
+fn sound_theme_name(_st: &StateMainLoop) -> &str {
+    "none"
+}
+
+#[cfg(not(feature = "sound"))]
+fn play_click_sound(_st: &mut StateMainLoop, _which: dwell::Sound) {}
+
+#[cfg(not(feature = "sound"))]
+fn play_warning_sound(_st: &mut StateMainLoop) {}
+
+// With RTMOUSE_DEBUG_TIMING set, each tick reports how long after its start
+// the state reacted and the synthetic button events were issued.
+fn log_timing(st: &StateTiming, what: &str) {
+    if st.enabled {
+        eprintln!(
+            "timing: {} +{}us",
+            what,
+            st.tick_start.elapsed().as_micros()
+        );
+    }
+}
+
+// --explain: one line per tick saying what the state machine decided and
+// why. Identical lines in a row, like a long stretch of waiting for motion,
+// are only printed once.
+fn log_decision(
+    st: &mut StateExplain,
+    x: i32,
+    y: i32,
+    decision: &dwell::Decision,
+    window: Option<&str>,
+) {
+    if !st.enabled {
+        return;
+    }
+    st.line.clear();
+    let _ = write!(st.line, "{} ({}, {})", decision, x, y);
+    if let Some(window) = window {
+        let _ = write!(st.line, " in {}", window);
+    }
+    if st.line == st.last_line {
+        return;
+    }
+    std::mem::swap(&mut st.line, &mut st.last_line);
+    let line = &st.last_line;
+
+    eprintln!("explain: {}", line);
+
+    if let Some(file) = &mut st.file {
+        // Start over now and then so a long session can't fill the disk
+        if st.file_lines >= TRACE_FILE_MAX_LINES {
+            let _ = file.set_len(0);
+            st.file_lines = 0;
+        }
+        let _ = writeln!(file, "{}", line);
+        st.file_lines += 1;
+    }
+}
+
+const TRACE_FILE_MAX_LINES: usize = 10_000;
+
+// Also keeps a copy of the trace in path, see log_file
+pub fn enable_explain(st: &mut StateExplain, path: &std::path::Path) {
+    st.enabled = true;
+    // It names the windows clicked on
+    let file = paths::ensure_dir_for(path).and_then(|_| {
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+    });
+    match file {
+        Ok(file) => {
+            let _ = file.set_len(0);
+            st.file = Some(file);
+        }
+        Err(e) => eprintln!("Error: could not open {}: {}", path.display(), e),
+    }
+}
+
+// via XI2.h: #define XIMaskLen(event) (((event) >> 3) + 1)
+fn xi_mask_len(event: i32) -> i32 {
+    (event >> 3) + 1
+}
+
+pub fn initialize_x11_state(st_x11: &mut StateX11, config: &DwellConfig) {
+    // The event reader has a connection of its own, but Xlib still shares
+    // some state between connections
+    unsafe {
+        xlib::XInitThreads();
+        xlib::XSetErrorHandler(Some(log_x_error));
+    }
+    let display = unsafe { xlib::XOpenDisplay(null()) };
+    if display.is_null() {
+        panic!("Error: Failed to open default display");
+    }
+
+    let mut opcode = 0;
+    let mut evt = 0;
+    let mut err = 0;
+    unsafe {
+        let ext = CString::new("XInputExtension").unwrap();
+        if xlib::XQueryExtension(display, ext.as_ptr(), &mut opcode, &mut evt, &mut err) == 0 {
+            panic!("Error: initialize_x11_state: could not query XInputExtension.");
+        }
+    }
+
+    st_x11.display = display;
+    st_x11.xi_extension_opcode = opcode;
+    st_x11.root = unsafe { xlib::XDefaultRootWindow(display) };
+    refresh_button_map(st_x11);
+    refresh_absolute_devices(st_x11);
+    st_x11.primary_button = config.primary_button;
+
+    unsafe {
+        let intern = |name: &str| {
+            let name = CString::new(name).unwrap();
+            xlib::XInternAtom(display, name.as_ptr(), xlib::False)
+        };
+        st_x11.control_atom = intern(control::CLIENT_MESSAGE_ATOM);
+        st_x11.state_atom = intern(STATE_PROPERTY);
+        st_x11.utf8_string_atom = intern("UTF8_STRING");
+        st_x11.wm_state_atom = intern("WM_STATE");
+        st_x11.net_wm_name_atom = intern("_NET_WM_NAME");
+        st_x11.net_close_window_atom = intern("_NET_CLOSE_WINDOW");
+        st_x11.accel_speed_atom = intern(ACCEL_SPEED_PROPERTY);
+        st_x11.float_atom = intern("FLOAT");
+    }
+
+    st_x11.has_dpms = unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        dpms::DPMSQueryExtension(display, &mut event_base, &mut error_base) != 0
+            && dpms::DPMSCapable(display) != 0
+    };
+
+    watch_screens(st_x11, &config.screens);
+    query_monitors(st_x11);
+    if config.nudge_keys {
+        for (keycode, name) in st_x11.nudge_keycodes.iter_mut().zip(NUDGE_KEYS) {
+            *keycode = keycode_of(display, name);
+        }
+    }
+    spawn_event_reader(st_x11, &config.click_key);
+}
+
+// Everything we listen for is selected on a second connection, and a thread
+// blocks in XNextEvent on it and passes the events on as they come, so the
+// tick loop only has to empty a channel.
+fn spawn_event_reader(st_x11: &mut StateX11, click_key: &str) {
+    let display = unsafe { xlib::XOpenDisplay(null()) };
+    if display.is_null() {
+        panic!("Error: Failed to open default display");
+    }
+    let root = unsafe { xlib::XDefaultRootWindow(display) };
+
+    let mask_len = xi_mask_len(xinput2::XI_LASTEVENT);
+    let mut mask_buf = vec![0u8; mask_len as usize];
+    let mut m = xinput2::XIEventMask {
+        deviceid: xinput2::XIAllDevices,
+        mask_len,
+        mask: mask_buf.as_mut_ptr(),
+    };
+    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_RawButtonPress);
+    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_RawButtonRelease);
+    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_RawMotion);
+    // A device appearing or changing can come with a new pointer mapping
+    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_DeviceChanged);
+    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_HierarchyChanged);
+
+    unsafe {
+        // Raw events go to the root of the screen the pointer is on, so
+        // listen on all of them to notice it leaving the watched ones
+        for screen in 0..xlib::XScreenCount(display) {
+            xinput2::XISelectEvents(display, xlib::XRootWindow(display, screen), &mut m, 1);
+        }
+        // Client messages sent to the root window only reach clients that
+        // selected the mask they were sent with. wmctrl style senders use
+        // the substructure masks, plain XSendEvent callers often
+        // StructureNotifyMask.
+        xlib::XSelectInput(
+            display,
+            root,
+            xlib::StructureNotifyMask | xlib::SubstructureNotifyMask,
+        );
+        xlib::XSync(display, 0);
+    }
+
+    // Key grabs belong to the connection that made them, so this one
+    st_x11.click_keycode = grab_click_key(display, root, click_key);
+
+    // Monitors plugged in or out, or changing resolution
+    let mut rr_event_base = None;
+    unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        if xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) != 0 {
+            xrandr::XRRSelectInput(
+                display,
+                root,
+                xrandr::RRScreenChangeNotifyMask
+                    | xrandr::RRCrtcChangeNotifyMask
+                    | xrandr::RROutputChangeNotifyMask,
+            );
+            rr_event_base = Some(event_base);
+        }
+    }
+
+    let reader = EventReader {
+        display,
+        xi_extension_opcode: st_x11.xi_extension_opcode,
+        click_keycode: st_x11.click_keycode,
+        control_atom: st_x11.control_atom,
+        rr_event_base,
+        xtest_devices: unsafe { xtest_devices(display) },
+    };
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("x events".to_string())
+        .spawn(move || read_events(reader, tx))
+        .expect("Error: could not start the X event thread");
+    st_x11.events = Some(rx);
+}
+
+// What the event reader passes on
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    // Physical button numbers
+    ButtonPress(u32),
+    ButtonRelease(u32),
+    // From this slave device
+    Motion(i32),
+    MappingChanged,
+    ClickKeyPress,
+    ClickKeyRelease,
+    Request(control::Request),
+    // The monitor layout changed
+    ScreenChanged,
+}
+
+struct EventReader {
+    display: *mut xlib::Display,
+    xi_extension_opcode: i32,
+    click_keycode: u32,
+    control_atom: xlib::Atom,
+    // None without XRandR
+    rr_event_base: Option<i32>,
+    // The XTEST slave pointers, whose buttons are ours and not held by
+    // anyone, see xtest_devices
+    xtest_devices: Vec<i32>,
+}
+
+// Only ever used by the reader thread once it's been handed over
+unsafe impl Send for EventReader {}
+
+fn read_events(mut reader: EventReader, tx: mpsc::Sender<InputEvent>) {
+    loop {
+        let event = unsafe { next_input_event(&mut reader) };
+        if let Some(event) = event {
+            // The tick loop is gone, so are we
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Our fake button events come back as raw events from the server's XTEST
+// device, and counting them as held buttons would have rtmouse inhibit
+// itself. Other programs faking clicks through XTest go unseen too, which
+// is no loss: nobody is holding those buttons down.
+unsafe fn xtest_devices(display: *mut xlib::Display) -> Vec<i32> {
+    let mut n = 0;
+    let devices = xinput2::XIQueryDevice(display, xinput2::XIAllDevices, &mut n);
+    if devices.is_null() {
+        return Vec::new();
+    }
+    let found = std::slice::from_raw_parts(devices, n.max(0) as usize)
+        .iter()
+        .filter(|d| d._use == xinput2::XISlavePointer)
+        .filter(|d| is_xtest_pointer(CStr::from_ptr(d.name).to_bytes()))
+        .map(|d| d.deviceid)
+        .collect();
+    xinput2::XIFreeDeviceInfo(devices);
+    found
+}
+
+// The server names them "Virtual core XTEST pointer", or "<master> XTEST
+// pointer" for other master pointers
+fn is_xtest_pointer(name: &[u8]) -> bool {
+    name.ends_with(b"XTEST pointer")
+}
+
+unsafe fn next_input_event(reader: &mut EventReader) -> Option<InputEvent> {
+    let display = reader.display;
+    let mut ev = std::mem::MaybeUninit::uninit();
+    xlib::XNextEvent(display, ev.as_mut_ptr());
+    let mut ev = ev.assume_init();
+    if let Some(base) = reader.rr_event_base {
+        let kind = ev.get_type() - base;
+        if kind == xrandr::RRScreenChangeNotify {
+            xrandr::XRRUpdateConfiguration(&mut ev);
+        }
+        if kind == xrandr::RRScreenChangeNotify || kind == xrandr::RRNotify {
+            return Some(InputEvent::ScreenChanged);
+        }
+    }
+    match ev.get_type() {
+        xlib::MappingNotify => {
+            return (ev.mapping.request == xlib::MappingPointer)
+                .then_some(InputEvent::MappingChanged);
+        }
+        xlib::KeyPress if ev.key.keycode == reader.click_keycode => {
+            return Some(InputEvent::ClickKeyPress);
+        }
+        xlib::KeyRelease if ev.key.keycode == reader.click_keycode => {
+            return Some(InputEvent::ClickKeyRelease);
+        }
+        xlib::ClientMessage => {
+            let msg = ev.client_message;
+            if msg.message_type != reader.control_atom || msg.format != 32 {
+                return None;
+            }
+            let request = control::Request::from_client_message(msg.data.as_longs());
+            if request.is_none() {
+                eprintln!(
+                    "Error: ignoring a {} message with unknown data {:?}",
+                    control::CLIENT_MESSAGE_ATOM,
+                    msg.data.as_longs()
+                );
+            }
+            return request.map(InputEvent::Request);
+        }
+        _ => {}
+    }
+
+    let mut cookie = ev.generic_event_cookie;
+    if xlib::XGetEventData(display, &mut cookie) == 0 {
+        return None;
+    }
+    let mut event = None;
+    if cookie.type_ == xlib::GenericEvent && cookie.extension == reader.xi_extension_opcode {
+        let data: *mut xinput2::XIRawEvent = cookie.data.cast();
+        event = match cookie.evtype {
+            xinput2::XI_RawButtonPress | xinput2::XI_RawButtonRelease
+                if reader.xtest_devices.contains(&(*data).sourceid) =>
+            {
+                None
+            }
+            xinput2::XI_RawButtonPress => Some(InputEvent::ButtonPress((*data).detail as u32)),
+            xinput2::XI_RawButtonRelease => Some(InputEvent::ButtonRelease((*data).detail as u32)),
+            xinput2::XI_RawMotion => Some(InputEvent::Motion((*data).sourceid)),
+            xinput2::XI_HierarchyChanged => {
+                reader.xtest_devices = xtest_devices(display);
+                Some(InputEvent::MappingChanged)
+            }
+            xinput2::XI_DeviceChanged => Some(InputEvent::MappingChanged),
+            _ => None,
+        };
+    }
+    xlib::XFreeEventData(display, &mut cookie);
+    event
+}
+
+fn watch_screens(st_x11: &mut StateX11, screens: &[u32]) {
+    let display = st_x11.display;
+    let count = unsafe { xlib::XScreenCount(display) };
+    st_x11.watched_roots = screens
+        .iter()
+        .filter_map(|&screen| {
+            if screen as i32 >= count {
+                eprintln!(
+                    "Error: screens: the display has no screen {}, it has {}",
+                    screen, count
+                );
+                return None;
+            }
+            Some(unsafe { xlib::XRootWindow(display, screen as i32) })
+        })
+        .collect();
+    if st_x11.watched_roots.is_empty() {
+        st_x11.watched_roots.push(st_x11.root);
+    }
+}
+
+// The default screen's monitors from XRandR. Servers older than RandR 1.5
+// can't list them, so there it's the whole screen as one. Called again
+// whenever the event reader sees the layout change.
+fn query_monitors(st_x11: &mut StateX11) {
+    let display = st_x11.display;
+    let (mut major, mut minor) = (0, 0);
+    let mut monitors = Vec::new();
+    let mut sizes = Vec::new();
+    unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        if xrandr::XRRQueryExtension(display, &mut event_base, &mut error_base) != 0
+            && xrandr::XRRQueryVersion(display, &mut major, &mut minor) != 0
+            && (major, minor) >= (1, 5)
+        {
+            let mut count = 0;
+            let list = xrandr::XRRGetMonitors(display, st_x11.root, xlib::True, &mut count);
+            if !list.is_null() {
+                for m in std::slice::from_raw_parts(list, count.max(0) as usize) {
+                    monitors.push(geometry::Rect {
+                        x: m.x,
+                        y: m.y,
+                        width: m.width.max(0) as u32,
+                        height: m.height.max(0) as u32,
+                    });
+                    sizes.push((m.mwidth.max(0) as u32, m.mheight.max(0) as u32));
+                }
+                xrandr::XRRFreeMonitors(list);
+            }
+        }
+        // Not XDisplayWidth, this connection never hears about resizes so
+        // its idea of the size stays what it was at startup
+        let (mut root, mut x, mut y, mut width, mut height, mut border, mut depth) =
+            (0, 0, 0, 0, 0, 0, 0);
+        if monitors.is_empty()
+            && xlib::XGetGeometry(
+                display,
+                st_x11.root,
+                &mut root,
+                &mut x,
+                &mut y,
+                &mut width,
+                &mut height,
+                &mut border,
+                &mut depth,
+            ) != 0
+        {
+            monitors.push(geometry::Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+            sizes.push((0, 0));
+        }
+    }
+    st_x11.monitors = monitors;
+    st_x11.monitor_sizes = sizes;
+}
+
+// How many X errors reached log_x_error
+static X_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+// Installed for the whole process instead of Xlib's default, which prints
+// the error and exits. Almost every error rtmouse can get is a window going
+// away between finding it and asking about it, and taking someone's clicking
+// away over that is far worse than the one request that failed.
+// Losing the connection goes through the IO error handler instead and is
+// still fatal, there's nothing to carry on with then.
+unsafe extern "C" fn log_x_error(
+    display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> i32 {
+    let n = X_ERRORS.fetch_add(1, Ordering::Relaxed) + 1;
+    // Something failing every tick shouldn't fill the log
+    if n > 20 && !n.is_multiple_of(1000) {
+        return 0;
+    }
+    let event = &*event;
+    let mut text = [0 as std::os::raw::c_char; 256];
+    xlib::XGetErrorText(
+        display,
+        event.error_code as i32,
+        text.as_mut_ptr(),
+        text.len() as i32,
+    );
+    eprintln!(
+        "Error: X error {} on request {}.{}, resource 0x{:x} (#{} so far, carrying on)",
+        CStr::from_ptr(text.as_ptr()).to_string_lossy(),
+        event.request_code,
+        event.minor_code,
+        event.resourceid,
+        n
+    );
+    0
+}
+
+static X_ERROR_SEEN: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn note_x_error(
+    _display: *mut xlib::Display,
+    _event: *mut xlib::XErrorEvent,
+) -> i32 {
+    X_ERROR_SEEN.store(true, Ordering::SeqCst);
+    0
+}
+
+// Runs f with errors noted instead of logged by log_x_error, and says
+// whether any of its requests failed. For requests that can fail in normal
+// use, like grabbing a key someone else has or looking at a window that was
+// just destroyed.
+fn catch_x_errors<T>(display: *mut xlib::Display, f: impl FnOnce() -> T) -> (T, bool) {
+    X_ERROR_SEEN.store(false, Ordering::SeqCst);
+    unsafe {
+        let old_handler = xlib::XSetErrorHandler(Some(note_x_error));
+        let result = f();
+        xlib::XSync(display, xlib::False);
+        xlib::XSetErrorHandler(old_handler);
+        (result, X_ERROR_SEEN.load(Ordering::SeqCst))
+    }
+}
+
+// The keycode for an X key name like "F12", 0 if the keyboard has none
+fn keycode_of(display: *mut xlib::Display, name: &str) -> u32 {
+    CString::new(name)
+        .map(|name| unsafe { xlib::XStringToKeysym(name.as_ptr()) })
+        .ok()
+        .filter(|&keysym| keysym != 0)
+        .map(|keysym| unsafe { xlib::XKeysymToKeycode(display, keysym) as u32 })
+        .unwrap_or(0)
+}
+
+// Grabs keycode on the root window. Caps Lock and Num Lock count as
+// modifiers to X, so the key is grabbed once for each combination of them.
+// Returns whether another client had it already.
+fn grab_key(display: *mut xlib::Display, root: xlib::Window, keycode: u32) -> bool {
+    let ((), failed) = catch_x_errors(display, || {
+        for modifiers in [
+            0,
+            xlib::LockMask,
+            xlib::Mod2Mask,
+            xlib::LockMask | xlib::Mod2Mask,
+        ] {
+            unsafe {
+                xlib::XGrabKey(
+                    display,
+                    keycode as i32,
+                    modifiers,
+                    root,
+                    xlib::False,
+                    xlib::GrabModeAsync,
+                    xlib::GrabModeAsync,
+                );
+            }
+        }
+    });
+    if failed {
+        unsafe {
+            xlib::XUngrabKey(display, keycode as i32, xlib::AnyModifier, root);
+        }
+    }
+    failed
+}
+
+// Grabs click_key, see grab_key. The keycode grabbed, 0 for none
+fn grab_click_key(display: *mut xlib::Display, root: xlib::Window, name: &str) -> u32 {
+    if name.is_empty() {
+        return 0;
+    }
+
+    let keycode = keycode_of(display, name);
+    if keycode == 0 {
+        eprintln!(
+            "Error: click_key: no key called \"{}\" on this keyboard, the click key is off",
+            name
+        );
+        return 0;
+    }
+
+    let failed = grab_key(display, root, keycode);
+
+    // Holding the key down then only repeats KeyPress, without the
+    // KeyRelease in between
+    unsafe {
+        xlib::XkbSetDetectableAutoRepeat(display, xlib::True, std::ptr::null_mut());
+    }
+
+    if failed {
+        eprintln!(
+            "Error: click_key: another program already grabbed \"{}\", the click key is off",
+            name
+        );
+        return 0;
+    }
+
+    keycode
+}
+
+// Size of the default screen, for commands that don't otherwise need the
+// display. None without one.
+pub fn screen_size() -> Option<(u32, u32)> {
+    unsafe {
+        let display = xlib::XOpenDisplay(null());
+        if display.is_null() {
+            return None;
+        }
+        let screen = xlib::XDefaultScreen(display);
+        let size = (
+            xlib::XDisplayWidth(display, screen) as u32,
+            xlib::XDisplayHeight(display, screen) as u32,
+        );
+        xlib::XCloseDisplay(display);
+        Some(size)
+    }
+}
+
+// For --setup: the furthest the pointer gets from where it was while it's
+// held still for 5 seconds. None without a display.
+pub fn measure_wander() -> Option<u32> {
+    unsafe {
+        let display = xlib::XOpenDisplay(null());
+        if display.is_null() {
+            return None;
+        }
+        let root = xlib::XDefaultRootWindow(display);
+        let position = || {
+            let (mut x, mut y, mut child_x, mut child_y) = (0, 0, 0, 0);
+            let (mut root_win, mut child_win, mut mask) = (0, 0, 0);
+            xlib::XQueryPointer(
+                display,
+                root,
+                &mut root_win,
+                &mut child_win,
+                &mut x,
+                &mut y,
+                &mut child_x,
+                &mut child_y,
+                &mut mask,
+            );
+            (x, y)
+        };
+
+        let start = position();
+        let mut wander = 0;
+        let until = Instant::now() + std::time::Duration::from_secs(5);
+        while Instant::now() < until {
+            thread::sleep(std::time::Duration::from_millis(TIMER_INTERVAL_MS as u64));
+            let (x, y) = position();
+            let distance = (((x - start.0).pow(2) + (y - start.1).pow(2)) as f64).sqrt();
+            wander = wander.max(distance.round() as u32);
+        }
+        xlib::XCloseDisplay(display);
+        Some(wander)
+    }
+}
+
+// Xwayland advertises an XWAYLAND extension. Older versions don't, so also
+// look at the session environment.
+fn is_xwayland(st_x11: &StateX11) -> bool {
+    let mut opcode = 0;
+    let mut evt = 0;
+    let mut err = 0;
+    let ext = CString::new("XWAYLAND").unwrap();
+    let has_ext = unsafe {
+        xlib::XQueryExtension(
+            st_x11.display,
+            ext.as_ptr(),
+            &mut opcode,
+            &mut evt,
+            &mut err,
+        ) != 0
+    };
+
+    has_ext
+        || std::env::var_os("WAYLAND_DISPLAY").is_some()
+        || std::env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland")
+}
+
+// Under Xwayland, XTest clicks only reach X11 clients. There is no Wayland
+// capable backend to switch to yet, so all we can do is make noise about it.
+pub fn warn_if_xwayland(st_x11: &StateX11) {
+    if !is_xwayland(st_x11) {
+        return;
+    }
+
+    let msg = i18n::text(i18n::Message::XwaylandWarning);
+    eprintln!("**********************************************************************");
+    eprintln!("WARNING: {}", msg);
+    eprintln!("**********************************************************************");
+    notify(&msg);
+}
+
+// Best effort, most users never see stderr of a session daemon.
+fn notify(msg: &str) {
+    let _ = Command::new("notify-send")
+        .args(["--urgency=critical", "rtmouse", msg])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .and_then(|mut child| child.wait());
+}
+
+pub struct StateIsClickInhibited {
+    inhibit_mask: u64,
+    uninhibit_mask: u64,
+    // Any raw motion since the last take_motion_seen()
+    motion_seen: bool,
+    // The device behind the latest, until take_moved_by()
+    moved_by: Option<i32>,
+    // The latest came from one of absolute_devices
+    absolute_motion: bool,
+    // The device behind the latest, kept
+    pointer_device: Option<i32>,
+    // A panel window moved, or was mapped or unmapped, since the last
+    // take_panels_moved()
+    panels_moved: bool,
+    // The pointer mapping changed since the last take_mapping_changed()
+    mapping_changed: bool,
+    // click_key is held down, so further presses are autorepeat
+    click_key_down: bool,
+    // click_key went down since the last take_click_key_pressed()
+    click_key_pressed: bool,
+    // Client messages since the last take_client_requests()
+    client_requests: Vec<control::Request>,
+    // nudge_keys presses since the last take_nudge(), in nudge_steps
+    nudge: (i32, i32),
+    // The monitor layout changed since the last take_screen_changed()
+    screen_changed: bool,
+}
+
+// Takes what the event reader sent since last time and returns the mask of
+// physical buttons currently held. Raw motion is noted for
+// take_motion_seen().
+pub fn poll_inhibit_mask(st: &mut StateIsClickInhibited, st_x11: &StateX11) -> u64 {
+    st.inhibit_mask &= !st.uninhibit_mask;
+    st.uninhibit_mask = 0;
+
+    for event in st_x11.events.iter().flat_map(|rx| rx.try_iter()) {
+        match event {
+            InputEvent::ButtonPress(button) => st.inhibit_mask |= 1 << button,
+            InputEvent::ButtonRelease(button) => st.uninhibit_mask |= 1 << button,
+            InputEvent::Motion(device) => {
+                st.motion_seen = true;
+                st.moved_by = Some(device);
+                st.absolute_motion = st_x11.absolute_devices.contains(&device);
+                st.pointer_device = Some(device);
+            }
+            InputEvent::MappingChanged => st.mapping_changed = true,
+            InputEvent::ClickKeyPress => {
+                if !st.click_key_down {
+                    st.click_key_pressed = true;
+                }
+                st.click_key_down = true;
+            }
+            InputEvent::ClickKeyRelease => st.click_key_down = false,
+            InputEvent::Request(request) => st.client_requests.push(request),
+            InputEvent::ScreenChanged => st.screen_changed = true,
+        }
+    }
+
+    // Only the panels' structure events are selected on this connection,
+    // and the nudge keys grabbed on it. MappingNotify goes to every client
+    // though, so don't let it pile up.
+    let display = st_x11.display;
+    unsafe {
+        while xlib::XPending(display) > 0 {
+            let mut ev = std::mem::MaybeUninit::uninit();
+            xlib::XNextEvent(display, ev.as_mut_ptr());
+            let ev = ev.assume_init();
+            match ev.get_type() {
+                xlib::KeyPress => {}
+                xlib::ConfigureNotify
+                | xlib::MapNotify
+                | xlib::UnmapNotify
+                | xlib::ReparentNotify => {
+                    st.panels_moved = true;
+                    continue;
+                }
+                _ => continue,
+            }
+            let keycode = ev.key.keycode;
+            if let Some(i) = st_x11
+                .nudge_keycodes
+                .iter()
+                .position(|&k| k != 0 && k == keycode)
+            {
+                let (dx, dy) = NUDGE_DIRECTIONS[i];
+                st.nudge.0 += dx;
+                st.nudge.1 += dy;
+            }
+        }
+    }
+
+    st.inhibit_mask
+}
+
+pub fn take_motion_seen(st: &mut StateIsClickInhibited) -> bool {
+    std::mem::replace(&mut st.motion_seen, false)
+}
+
+pub fn take_moved_by(st: &mut StateIsClickInhibited) -> Option<i32> {
+    st.moved_by.take()
+}
+
+pub fn take_panels_moved(st: &mut StateIsClickInhibited) -> bool {
+    std::mem::replace(&mut st.panels_moved, false)
+}
+
+pub fn take_mapping_changed(st: &mut StateIsClickInhibited) -> bool {
+    std::mem::replace(&mut st.mapping_changed, false)
+}
+
+pub fn take_click_key_pressed(st: &mut StateIsClickInhibited) -> bool {
+    std::mem::replace(&mut st.click_key_pressed, false)
+}
+
+pub fn take_client_requests(st: &mut StateIsClickInhibited) -> Vec<control::Request> {
+    std::mem::take(&mut st.client_requests)
+}
+
+pub fn take_nudge(st: &mut StateIsClickInhibited) -> (i32, i32) {
+    std::mem::take(&mut st.nudge)
+}
+
+pub fn take_screen_changed(st: &mut StateIsClickInhibited) -> bool {
+    std::mem::replace(&mut st.screen_changed, false)
+}
+
+const NUDGE_KEYS: [&str; 4] = ["Left", "Right", "Up", "Down"];
+const NUDGE_DIRECTIONS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+// The arrow keys are only taken from applications while the dwell timer
+// runs. Another client having them just means they don't nudge.
+fn update_nudge_grab(st: &mut StateMainLoop) {
+    let want = st.dwell.armed_anchor().is_some() && st.st_x11.nudge_keycodes != [0; 4];
+    if want == st.st_x11.nudge_grabbed {
+        return;
+    }
+    let display = st.st_x11.display;
+    let root = st.st_x11.root;
+    for &keycode in st.st_x11.nudge_keycodes.iter().filter(|&&k| k != 0) {
+        if want {
+            grab_key(display, root, keycode);
+        } else {
+            unsafe {
+                xlib::XUngrabKey(display, keycode as i32, xlib::AnyModifier, root);
+            }
+        }
+    }
+    st.st_x11.nudge_grabbed = want;
+}
+
+// Moves the pointer by nudge, in nudge_steps, if the dwell timer is running,
+// and returns where it ended up. The server keeps it on the screen.
+fn nudge_pointer(st: &mut StateMainLoop, nudge: (i32, i32), x: i32, y: i32) -> (i32, i32) {
+    if nudge == (0, 0) || st.dwell.armed_anchor().is_none() {
+        return (x, y);
+    }
+    let step = st.config.nudge_step as i32;
+    unsafe {
+        xlib::XWarpPointer(
+            st.st_x11.display,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            nudge.0 * step,
+            nudge.1 * step,
+        );
+    }
+    let pointer = query_pointer(&st.st_x11);
+    st.pointer = Some((pointer.x, pointer.y));
+    st.pointer_window = pointer.window;
+    st.dwell.nudged(pointer.x, pointer.y);
+    (pointer.x, pointer.y)
+}
+
+pub struct Pointer {
+    pub x: i32,
+    pub y: i32,
+    // Root window of the screen it's on, x and y are relative to it
+    pub root: xlib::Window,
+    // The top-level window under it, 0 over the root
+    pub window: xlib::Window,
+    // Button1Mask and so on, for logical buttons held
+    pub buttons: u32,
+}
+
+pub fn query_pointer(st_x11: &StateX11) -> Pointer {
+    let display = st_x11.display;
+
+    let mut root_x = 0;
+    let mut root_y = 0;
+    let mut root_win = st_x11.root;
+
+    let mut child_x = 0;
+    let mut child_y = 0;
+    let mut child_win = 0;
+
+    let mut button_mask = 0;
+
+    unsafe {
+        xlib::XQueryPointer(
+            display,
+            root_win,
+            &mut root_win,
+            &mut child_win,
+            &mut root_x,
+            &mut root_y,
+            &mut child_x,
+            &mut child_y,
+            &mut button_mask,
+        );
+    }
+
+    Pointer {
+        x: root_x,
+        y: root_y,
+        root: root_win,
+        window: child_win,
+        buttons: button_mask,
+    }
+}
+
+// Raw events only say when buttons go down or up, so ones already down
+// when rtmouse starts have to be asked for. Those XTest holds, left by an
+// rtmouse that died mid drag, are let go first with release_stale_buttons;
+// whatever is still held after that is held by hand and inhibits dwelling
+// until its release comes in. Call it after initialize_x11_state.
+pub fn initialize_held_buttons(st: &mut StateMainLoop) {
+    let masks = [
+        xlib::Button1Mask,
+        xlib::Button2Mask,
+        xlib::Button3Mask,
+        xlib::Button4Mask,
+        xlib::Button5Mask,
+    ];
+    let held = |st_x11: &StateX11| {
+        let buttons = query_pointer(st_x11).buttons;
+        (1..)
+            .zip(masks)
+            .filter(move |&(_, mask)| buttons & mask != 0)
+    };
+    if st.config.release_stale_buttons {
+        for (logical, _) in held(&st.st_x11) {
+            send_button_event(&st.st_x11, physical_button(&st.st_x11, logical), false, 0);
+        }
+    }
+    let mut mask = 0;
+    for (logical, _) in held(&st.st_x11) {
+        mask |= 1 << physical_button(&st.st_x11, logical);
+    }
+    if mask != 0 {
+        eprintln!("note: buttons are held down, waiting for them to be let go");
+    }
+    st.st_is_click_inhibited.inhibit_mask = mask;
+}
+
+// libinput's pointer speed, a float from -1 to 1 on each slave pointer it
+// drives. Devices other drivers run don't have it.
+const ACCEL_SPEED_PROPERTY: &str = "libinput Accel Speed";
+
+// How far from where precision_mode slowed the pointer it can go before it's
+// moving somewhere else, and back to full speed
+const PRECISION_RADIUS: i32 = 100;
+
+fn accel_speed(st_x11: &StateX11, device: i32) -> Option<f32> {
+    let (speed, failed) = catch_x_errors(st_x11.display, || unsafe {
+        let (mut type_, mut format, mut n, mut after) = (0, 0, 0, 0);
+        let mut data = std::ptr::null_mut();
+        if xinput2::XIGetProperty(
+            st_x11.display,
+            device,
+            st_x11.accel_speed_atom,
+            0,
+            1,
+            xlib::False,
+            st_x11.float_atom,
+            &mut type_,
+            &mut format,
+            &mut n,
+            &mut after,
+            &mut data,
+        ) != 0
+        {
+            return None;
+        }
+        let speed =
+            (type_ == st_x11.float_atom && format == 32 && n == 1).then(|| *(data as *const f32));
+        if !data.is_null() {
+            xlib::XFree(data.cast());
+        }
+        speed
+    });
+    speed.filter(|_| !failed)
+}
+
+fn set_accel_speed(st_x11: &StateX11, device: i32, speed: f32) {
+    let mut speed = speed;
+    catch_x_errors(st_x11.display, || unsafe {
+        xinput2::XIChangeProperty(
+            st_x11.display,
+            device,
+            st_x11.accel_speed_atom,
+            st_x11.float_atom,
+            32,
+            xlib::PropModeReplace,
+            (&mut speed as *mut f32).cast(),
+            1,
+        );
+    });
+}
+
+// The speed to put back and the device's name, in case rtmouse is killed
+// while the pointer is slowed down
+fn precision_file() -> std::path::PathBuf {
+    paths::runtime_dir().join("precision")
+}
+
+// With precision_mode, slows down the device moving the pointer once the
+// dwell timer arms. It speeds back up after the click, when dwelling stops
+// for any other reason, or once the pointer goes further than
+// PRECISION_RADIUS.
+fn precision_mode(st: &mut StateMainLoop) {
+    if let Some((device, original, (ax, ay))) = st.precision {
+        let (x, y) = st.pointer.unwrap_or((ax, ay));
+        let far = (x - ax).pow(2) + (y - ay).pow(2) > PRECISION_RADIUS.pow(2);
+        let done = !st.dwell.is_active()
+            || st.dwell.is_dragging()
+            || (st.dwell.armed_anchor().is_none() && !st.dwell.is_moving());
+        if far || done || !st.config.precision_mode {
+            set_accel_speed(&st.st_x11, device, original);
+            let _ = fs::remove_file(precision_file());
+            st.precision = None;
+        }
+        return;
+    }
+    if !st.config.precision_mode || st.dwell.is_dragging() {
+        return;
+    }
+    let (anchor, device) = match (
+        st.dwell.armed_anchor(),
+        st.st_is_click_inhibited.pointer_device,
+    ) {
+        (Some(anchor), Some(device)) => (anchor, device),
+        _ => return,
+    };
+    let original = match accel_speed(&st.st_x11, device) {
+        Some(speed) => speed,
+        None => return,
+    };
+    let slow = st.config.precision_speed as f32 / 100.0;
+    if original <= slow {
+        return;
+    }
+    let name = device_name(&st.st_x11, device).unwrap_or_default();
+    let path = precision_file();
+    let _ = paths::ensure_dir_for(&path)
+        .and_then(|()| fs::write(&path, format!("{}\n{}\n", original, name)));
+    set_accel_speed(&st.st_x11, device, slow);
+    st.precision = Some((device, original, anchor));
+}
+
+// Puts back a speed precision_mode left slowed down, by a previous rtmouse
+// that didn't get to shut down
+pub fn restore_pointer_speed(st: &mut StateMainLoop) {
+    let text = match fs::read_to_string(precision_file()) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let _ = fs::remove_file(precision_file());
+    let mut lines = text.lines();
+    let (speed, name) = match (
+        lines.next().and_then(|s| s.parse::<f32>().ok()),
+        lines.next(),
+    ) {
+        (Some(speed), Some(name)) => (speed, name),
+        _ => return,
+    };
+    let devices = unsafe {
+        let mut n = 0;
+        let devices = xinput2::XIQueryDevice(st.st_x11.display, xinput2::XIAllDevices, &mut n);
+        if devices.is_null() {
+            return;
+        }
+        let found: Vec<i32> = std::slice::from_raw_parts(devices, n.max(0) as usize)
+            .iter()
+            .filter(|d| d._use == xinput2::XISlavePointer)
+            .filter(|d| CStr::from_ptr(d.name).to_string_lossy() == name)
+            .map(|d| d.deviceid)
+            .collect();
+        xinput2::XIFreeDeviceInfo(devices);
+        found
+    };
+    for device in devices {
+        set_accel_speed(&st.st_x11, device, speed);
+    }
+}
+
+// The top-level window that has the input focus, or None when focus follows
+// the pointer anyway
+fn focused_top_level(st_x11: &StateX11) -> Option<xlib::Window> {
+    let display = st_x11.display;
+    let (top_level, failed) = catch_x_errors(display, || unsafe {
+        let mut window = 0;
+        let mut revert_to = 0;
+        xlib::XGetInputFocus(display, &mut window, &mut revert_to);
+        if window == xlib::PointerRoot as xlib::Window {
+            return None;
+        }
+
+        // Walk up to the child of the root
+        loop {
+            let mut root = 0;
+            let mut parent = 0;
+            let mut children = std::ptr::null_mut();
+            let mut n = 0;
+            if window == 0
+                || window == st_x11.root
+                || xlib::XQueryTree(
+                    display,
+                    window,
+                    &mut root,
+                    &mut parent,
+                    &mut children,
+                    &mut n,
+                ) == 0
+            {
+                return Some(0);
+            }
+            if !children.is_null() {
+                xlib::XFree(children.cast());
+            }
+            if parent == root {
+                return Some(window);
+            }
+            window = parent;
+        }
+    });
+    if failed {
+        return Some(0);
+    }
+    top_level
+}
+
+// Only worth round trips to the server when a click is due. A trigger that
+// isn't a timer could be due any time the pointer rests.
+fn click_due(st: &StateMainLoop) -> bool {
+    match st.dwell.dwell_time_left(st.st_timing.tick_start) {
+        Some(left) => left.is_zero(),
+        None => !st.dwell.is_dragging() && st.dwell.armed_anchor().is_some(),
+    }
+}
+
+fn pointer_unfocused(st: &StateMainLoop) -> bool {
+    if !st.config.focused_window_only || !click_due(st) {
+        return false;
+    }
+    focused_top_level(&st.st_x11).is_some_and(|focused| focused != st.pointer_window)
+}
+
+// There's no asking X who holds a grab, but trying for one says whether
+// anyone does. Ours is let go straight away.
+fn pointer_grabbed(st: &StateMainLoop) -> bool {
+    if st.config.grab_delay.is_zero() || !click_due(st) {
+        return false;
+    }
+    let display = st.st_x11.display;
+    unsafe {
+        let result = xlib::XGrabPointer(
+            display,
+            st.st_x11.root,
+            xlib::False,
+            0,
+            xlib::GrabModeAsync,
+            xlib::GrabModeAsync,
+            0,
+            0,
+            xlib::CurrentTime,
+        );
+        if result == xlib::GrabSuccess {
+            xlib::XUngrabPointer(display, xlib::CurrentTime);
+            xlib::XFlush(display);
+        }
+        result == xlib::AlreadyGrabbed || result == xlib::GrabFrozen
+    }
+}
+
+// For --explain and status, "class \"title\"" of the application window
+// under the pointer
+fn window_description(st_x11: &StateX11, top_level: xlib::Window) -> Option<String> {
+    if top_level == 0 {
+        return None;
+    }
+    let (description, failed) = catch_x_errors(st_x11.display, || {
+        let window = client_window(st_x11, top_level)?;
+        let class = window_class(st_x11, window).unwrap_or_default();
+        let name = window_name(st_x11, window).unwrap_or_default();
+        Some(format!("{} \"{}\"", class, name))
+    });
+    // Gone while we were looking
+    if failed {
+        return None;
+    }
+    description.map(|d| d.replace(|c: char| c.is_control(), " "))
+}
+
+fn pointer_window_class(st_x11: &StateX11, top_level: xlib::Window) -> Option<String> {
+    if top_level == 0 {
+        return None;
+    }
+    let (class, failed) = catch_x_errors(st_x11.display, || {
+        window_class(st_x11, client_window(st_x11, top_level)?)
+    });
+    class.filter(|_| !failed)
+}
+
+// Window managers reparent application windows into frames, so the top-level
+// window is often a frame. The application's own window is the one with
+// WM_STATE on it.
+fn client_window(st_x11: &StateX11, window: xlib::Window) -> Option<xlib::Window> {
+    if get_property(
+        st_x11,
+        window,
+        st_x11.wm_state_atom,
+        xlib::AnyPropertyType as u64,
+    )
+    .is_some()
+    {
+        return Some(window);
+    }
+
+    let mut root = 0;
+    let mut parent = 0;
+    let mut children = std::ptr::null_mut();
+    let mut n = 0;
+    let children = unsafe {
+        if xlib::XQueryTree(
+            st_x11.display,
+            window,
+            &mut root,
+            &mut parent,
+            &mut children,
+            &mut n,
+        ) == 0
+        {
+            return None;
+        }
+        if children.is_null() {
+            return None;
+        }
+        let list = std::slice::from_raw_parts(children, n as usize).to_vec();
+        xlib::XFree(children.cast());
+        list
+    };
+    // Topmost first
+    children
+        .into_iter()
+        .rev()
+        .find_map(|child| client_window(st_x11, child))
+}
+
+fn get_property(
+    st_x11: &StateX11,
+    window: xlib::Window,
+    property: xlib::Atom,
+    type_: xlib::Atom,
+) -> Option<Vec<u8>> {
+    let mut actual_type = 0;
+    let mut format = 0;
+    let mut n = 0;
+    let mut after = 0;
+    let mut data = std::ptr::null_mut();
+    unsafe {
+        let status = xlib::XGetWindowProperty(
+            st_x11.display,
+            window,
+            property,
+            0,
+            1024,
+            xlib::False,
+            type_,
+            &mut actual_type,
+            &mut format,
+            &mut n,
+            &mut after,
+            &mut data,
+        );
+        if status != xlib::Success as i32 || actual_type == 0 {
+            return None;
+        }
+        let bytes = if data.is_null() || format != 8 {
+            Vec::new()
+        } else {
+            std::slice::from_raw_parts(data, n as usize).to_vec()
+        };
+        if !data.is_null() {
+            xlib::XFree(data.cast());
+        }
+        Some(bytes)
+    }
+}
+
+fn window_class(st_x11: &StateX11, window: xlib::Window) -> Option<String> {
+    unsafe {
+        let mut hint = xlib::XClassHint {
+            res_name: std::ptr::null_mut(),
+            res_class: std::ptr::null_mut(),
+        };
+        if xlib::XGetClassHint(st_x11.display, window, &mut hint) == 0 {
+            return None;
+        }
+        let class = (!hint.res_class.is_null()).then(|| {
+            CStr::from_ptr(hint.res_class)
+                .to_string_lossy()
+                .into_owned()
+        });
+        for p in [hint.res_name, hint.res_class] {
+            if !p.is_null() {
+                xlib::XFree(p.cast());
+            }
+        }
+        class
+    }
+}
+
+fn window_name(st_x11: &StateX11, window: xlib::Window) -> Option<String> {
+    if let Some(name) = get_property(
+        st_x11,
+        window,
+        st_x11.net_wm_name_atom,
+        st_x11.utf8_string_atom,
+    ) {
+        return Some(String::from_utf8_lossy(&name).into_owned());
+    }
+    // Old clients only set WM_NAME
+    unsafe {
+        let mut name = std::ptr::null_mut();
+        if xlib::XFetchName(st_x11.display, window, &mut name) == 0 || name.is_null() {
+            return None;
+        }
+        let s = CStr::from_ptr(name).to_string_lossy().into_owned();
+        xlib::XFree(name.cast());
+        Some(s)
+    }
+}
+
+// Slave pointers whose x or y valuator is absolute. Their motion is the pen
+// or finger being put down somewhere, which the state machine treats as a
+// jump when it's far, see jump_pixels.
+fn refresh_absolute_devices(st_x11: &mut StateX11) {
+    st_x11.absolute_devices.clear();
+    unsafe {
+        let mut n = 0;
+        let devices = xinput2::XIQueryDevice(st_x11.display, xinput2::XIAllDevices, &mut n);
+        if devices.is_null() {
+            return;
+        }
+        for device in std::slice::from_raw_parts(devices, n.max(0) as usize) {
+            if device._use != xinput2::XISlavePointer {
+                continue;
+            }
+            let classes =
+                std::slice::from_raw_parts(device.classes, device.num_classes.max(0) as usize);
+            let absolute = classes.iter().any(|&class| {
+                if (*class)._type != xinput2::XIValuatorClass {
+                    return false;
+                }
+                let valuator = &*(class as *const xinput2::XIValuatorClassInfo);
+                valuator.number <= 1 && valuator.mode == xinput2::XIModeAbsolute
+            });
+            if absolute {
+                st_x11.absolute_devices.push(device.deviceid);
+            }
+        }
+        xinput2::XIFreeDeviceInfo(devices);
+    }
+}
+
+pub fn refresh_button_map(st_x11: &mut StateX11) {
+    let mut map = vec![0; 256];
+    let n = unsafe { xlib::XGetPointerMapping(st_x11.display, map.as_mut_ptr(), 256) };
+    map.truncate(n.max(0) as usize);
+    st_x11.button_map = map;
+}
+
+// XTest presses physical buttons, which then go through the pointer
+// mapping. To get a logical button out, press whichever physical button
+// maps to it. Unless primary_button says the mapping is wrong.
+fn physical_button(st_x11: &StateX11, logical: u8) -> u8 {
+    if let (1, Some(button)) = (logical, st_x11.primary_button) {
+        return button;
+    }
+    match st_x11.button_map.iter().position(|&b| b == logical) {
+        Some(i) => i as u8 + 1,
+        None => logical,
+    }
+}
+
+// The wheel is buttons 4 to 7 to X
+fn wheel_button(direction: ScrollDirection) -> u32 {
+    match direction {
+        ScrollDirection::Up => xlib::Button4,
+        ScrollDirection::Down => xlib::Button5,
+        ScrollDirection::Left => 6,
+        ScrollDirection::Right => 7,
+    }
+}
+
+pub fn get_primary_button_code(st_x11: &StateX11) -> u8 {
+    physical_button(st_x11, 1)
+}
+
+pub fn get_secondary_button_code(st_x11: &StateX11) -> u8 {
+    physical_button(st_x11, 3)
+}
+
+// Whether the server took it. That needs a round trip, but a click that
+// silently went nowhere is worse than a slower one.
+pub fn send_button_event(st_x11: &StateX11, btn: u8, state: bool, delay: u32) -> bool {
+    let (sent, failed) = catch_x_errors(st_x11.display, || unsafe {
+        xtest::XTestFakeButtonEvent(st_x11.display, btn.into(), state.into(), delay.into()) != 0
+    });
+    sent && !failed
+}
+
+// Deepest window under the pointer, found by walking XQueryPointer down
+// through the child windows starting at the root.
+fn get_window_under_cursor(st_x11: &StateX11) -> xlib::Window {
+    let display = st_x11.display;
+    let mut window = st_x11.root;
+
+    loop {
+        let mut root_win = 0;
+        let mut child_win = 0;
+        let mut root_x = 0;
+        let mut root_y = 0;
+        let mut win_x = 0;
+        let mut win_y = 0;
+        let mut button_mask = 0;
+
+        let same_screen = unsafe {
+            xlib::XQueryPointer(
+                display,
+                window,
+                &mut root_win,
+                &mut child_win,
+                &mut root_x,
+                &mut root_y,
+                &mut win_x,
+                &mut win_y,
+                &mut button_mask,
+            )
+        };
+
+        if same_screen == 0 || child_win == 0 {
+            return window;
+        }
+        window = child_win;
+    }
+}
+
+// WM_CLASS of the window or of its nearest ancestor that has one. Toolkit
+// subwindows usually don't carry a class hint but their client window does.
+fn get_window_class(st_x11: &StateX11, window: xlib::Window) -> Option<(String, String)> {
+    let display = st_x11.display;
+    let root = st_x11.root;
+    let mut window = window;
+
+    while window != 0 && window != root {
+        unsafe {
+            let mut hint = xlib::XClassHint {
+                res_name: std::ptr::null_mut(),
+                res_class: std::ptr::null_mut(),
+            };
+            if xlib::XGetClassHint(display, window, &mut hint) != 0 {
+                let name = CStr::from_ptr(hint.res_name).to_string_lossy().into_owned();
+                let class = CStr::from_ptr(hint.res_class)
+                    .to_string_lossy()
+                    .into_owned();
+                xlib::XFree(hint.res_name.cast());
+                xlib::XFree(hint.res_class.cast());
+                return Some((name, class));
+            }
+
+            let mut root_win = 0;
+            let mut parent = 0;
+            let mut children = std::ptr::null_mut();
+            let mut n_children = 0;
+            if xlib::XQueryTree(
+                display,
+                window,
+                &mut root_win,
+                &mut parent,
+                &mut children,
+                &mut n_children,
+            ) == 0
+            {
+                return None;
+            }
+            if !children.is_null() {
+                xlib::XFree(children.cast());
+            }
+            window = parent;
+        }
+    }
+
+    None
+}
+
+// Returns the window to deliver clicks to with XSendEvent, if the window
+// under the cursor belongs to one of the configured send_event_classes.
+fn find_send_event_target(st_x11: &StateX11, classes: &[String]) -> Option<xlib::Window> {
+    if classes.is_empty() {
+        return None;
+    }
+
+    let window = get_window_under_cursor(st_x11);
+    let (name, class) = get_window_class(st_x11, window)?;
+
+    let matches = classes
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(&name) || c.eq_ignore_ascii_case(&class));
+
+    if matches {
+        Some(window)
+    } else {
+        None
+    }
+}
+
+// Deliver a ButtonPress/ButtonRelease straight to a window. Unlike XTest
+// this skips the pointer mapping, so btn is a logical button number.
+// Release events carry the state from before the release, which includes
+// the button being released. Only buttons 1 to 5 have a mask, the bits
+// after Button5Mask are the keyboard group.
+fn button_event_state(mask: u32, btn: u32, press: bool) -> u32 {
+    if !press && (1..=5).contains(&btn) {
+        mask | xlib::Button1Mask << (btn - 1)
+    } else {
+        mask
+    }
+}
+
+fn send_button_event_to_window(
+    st_x11: &StateX11,
+    window: xlib::Window,
+    btn: u32,
+    state: bool,
+) -> bool {
+    let display = st_x11.display;
+
+    let mut root_win = 0;
+    let mut child_win = 0;
+    let mut root_x = 0;
+    let mut root_y = 0;
+    let mut win_x = 0;
+    let mut win_y = 0;
+    let mut button_mask = 0;
+
+    unsafe {
+        xlib::XQueryPointer(
+            display,
+            window,
+            &mut root_win,
+            &mut child_win,
+            &mut root_x,
+            &mut root_y,
+            &mut win_x,
+            &mut win_y,
+            &mut button_mask,
+        );
+    }
+
+    let (type_, event_mask) = if state {
+        (xlib::ButtonPress, xlib::ButtonPressMask)
+    } else {
+        (xlib::ButtonRelease, xlib::ButtonReleaseMask)
+    };
+
+    let button_mask = button_event_state(button_mask, btn, state);
+
+    let mut ev = xlib::XEvent {
+        button: xlib::XButtonEvent {
+            type_,
+            serial: 0,
+            send_event: xlib::True,
+            display,
+            window,
+            root: root_win,
+            subwindow: 0,
+            time: xlib::CurrentTime,
+            x: win_x,
+            y: win_y,
+            x_root: root_x,
+            y_root: root_y,
+            state: button_mask,
+            button: btn,
+            same_screen: xlib::True,
+        },
+    };
+
+    // The window can be gone by now
+    let (sent, failed) = catch_x_errors(display, || unsafe {
+        xlib::XSendEvent(display, window, xlib::True, event_mask, &mut ev) != 0
+    });
+    sent && !failed
+}
+
+// logical is what the target window should see, XTest gets whichever
+// physical button the pointer mapping turns into it
+fn send_click_event(
+    st_x11: &StateX11,
+    target: Option<xlib::Window>,
+    logical: u32,
+    state: bool,
+) -> bool {
+    match target {
+        Some(window) => send_button_event_to_window(st_x11, window, logical, state),
+        None => send_button_event(st_x11, physical_button(st_x11, logical as u8), state, 0),
+    }
+}
+
+// The X server refusing a fake button most likely means what we know about
+// it went stale, a pointer mapping that shrank or XTest going away, so look
+// again before giving up on the click
+fn reinitialize_xtest(st_x11: &mut StateX11) {
+    refresh_button_map(st_x11);
+    let (mut event_base, mut error_base, mut major, mut minor) = (0, 0, 0, 0);
+    let has_xtest = unsafe {
+        xtest::XTestQueryExtension(
+            st_x11.display,
+            &mut event_base,
+            &mut error_base,
+            &mut major,
+            &mut minor,
+        ) != 0
+    };
+    if !has_xtest {
+        eprintln!("Error: the X server no longer has the XTEST extension");
+    }
+}
+
+// send_click_event, tried once more after reinitialize_xtest, and reported
+// on the first click that still doesn't go through
+fn deliver_click_event(
+    st: &mut StateMainLoop,
+    target: Option<xlib::Window>,
+    logical: u32,
+    state: bool,
+) {
+    if send_click_event(&st.st_x11, target, logical, state) {
+        if st.st_delivery.failures > 0 {
+            eprintln!("clicks are being delivered again");
+        }
+        st.st_delivery.failures = 0;
+        return;
+    }
+
+    reinitialize_xtest(&mut st.st_x11);
+    if send_click_event(&st.st_x11, target, logical, state) {
+        eprintln!("a button event was refused, it went through after setting XTest up again");
+        st.st_delivery.failures = 0;
+        return;
+    }
+
+    st.st_delivery.failures += 1;
+    if st.st_delivery.failures == 1 {
+        eprintln!("Error: clicks are not being delivered, the X server refuses the button events");
+        notify(&i18n::text(i18n::Message::ClicksNotDelivered));
+        monitor::send(&st.config.monitor_command, monitor::Alert::NotDelivering);
+    }
+}
+
+fn write_status_file(config: &DwellConfig, status: Status) {
+    if !config.write_status_file {
+        return;
+    }
+    let path = config.status_path();
+    if let Err(e) = fs::write(&path, format!("{}\n", status.as_str())) {
+        eprintln!("Error: could not write {}: {}", path.display(), e);
+    }
+}
+
+pub fn run_commands(st: &mut StateMainLoop, cmds: &[DwellCommand]) {
+    for cmd in cmds {
+        match *cmd {
+            DwellCommand::Press => {
+                st.send_event_target =
+                    find_send_event_target(&st.st_x11, &st.config.send_event_classes);
+                st.pressed_at = Some(Instant::now());
+                deliver_click_event(st, st.send_event_target, xlib::Button1, true);
+                log_timing(&st.st_timing, "button press issued");
+            }
+            DwellCommand::Release => {
+                deliver_click_event(st, st.send_event_target, xlib::Button1, false);
+                st.send_event_target = None;
+                st.pressed_at = None;
+                log_timing(&st.st_timing, "button release issued");
+            }
+            DwellCommand::SecondaryClick => {
+                let target = find_send_event_target(&st.st_x11, &st.config.send_event_classes);
+                for state in &[true, false] {
+                    deliver_click_event(st, target, xlib::Button3, *state);
+                }
+                log_timing(&st.st_timing, "secondary click issued");
+            }
+            DwellCommand::Scroll(direction) => {
+                let target = find_send_event_target(&st.st_x11, &st.config.send_event_classes);
+                for state in &[true, false] {
+                    deliver_click_event(st, target, wheel_button(direction), *state);
+                }
+            }
+            DwellCommand::MoveTo { x, y } => unsafe {
+                xtest::XTestFakeMotionEvent(st.st_x11.display, -1, x, y, 0);
+            },
+            DwellCommand::Gesture { shape, x, y } => run_gesture(st, shape, x, y),
+            DwellCommand::PlaySound(which) => {
+                if let dwell::Sound::Click(_) = which {
+                    leds::blink(&mut st.st_leds, st.st_x11.display, Instant::now());
+                }
+                play_click_sound(st, which);
+            }
+            DwellCommand::RunMacro(zone) => {
+                let name = st.config.macro_zones[zone].name.clone();
+                if let Err(e) = play_macro(st, &name) {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            DwellCommand::WriteStatus(status) => {
+                match status {
+                    Status::Terminated => leds::restore(&mut st.st_leds, st.st_x11.display),
+                    status => leds::show_enabled(
+                        &st.st_leds,
+                        st.st_x11.display,
+                        status == Status::Enabled,
+                    ),
+                }
+                write_status_file(&st.config, status);
+            }
+        }
+    }
+}
+
+// A stroke recognized after a gesture dwell, done where the dwell was
+fn run_gesture(st: &mut StateMainLoop, shape: gesture::Shape, x: i32, y: i32) {
+    let action = match shape {
+        gesture::Shape::L => st.config.gesture_l.clone(),
+        gesture::Shape::Circle => st.config.gesture_circle.clone(),
+        gesture::Shape::ZigZag => st.config.gesture_zigzag.clone(),
+    };
+    match action.as_str() {
+        "" => {}
+        "close-window" => close_window_at(&st.st_x11, x, y),
+        command if command.starts_with("macro ") => {
+            if let Err(e) = play_macro(st, command["macro ".len()..].trim()) {
+                eprintln!("Error: the {} gesture: {}", shape.as_str(), e);
+            }
+        }
+        "middle-click" => {
+            unsafe {
+                xtest::XTestFakeMotionEvent(st.st_x11.display, -1, x, y, 0);
+            }
+            let target = find_send_event_target(&st.st_x11, &st.config.send_event_classes);
+            for state in &[true, false] {
+                deliver_click_event(st, target, xlib::Button2, *state);
+            }
+        }
+        command => {
+            let child = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .env("RTMOUSE_GESTURE", shape.as_str())
+                .env("RTMOUSE_X", x.to_string())
+                .env("RTMOUSE_Y", y.to_string())
+                .stdin(Stdio::null())
+                .spawn();
+            match child {
+                Ok(mut child) => {
+                    thread::spawn(move || child.wait());
+                }
+                Err(e) => eprintln!("Error: could not run the {} gesture: {}", shape.as_str(), e),
+            }
+        }
+    }
+}
+
+// Starts replaying a recorded macro. The pointer goes back to where it is
+// now afterwards.
+fn play_macro(st: &mut StateMainLoop, name: &str) -> Result<(), String> {
+    let steps = macros::load(name).map_err(|e| format!("can't load macro {}: {}", name, e))?;
+    let home = st.pointer.unwrap_or(st.counted_at);
+    st.replay = Some(macros::replay(name, steps, home, Instant::now()));
+    Ok(())
+}
+
+// Carries out the replay's next step once it's due, then puts the pointer back
+fn run_replay(st: &mut StateMainLoop, now: Instant) {
+    let replay = match &mut st.replay {
+        Some(replay) => replay,
+        None => return,
+    };
+    if let Some(step) = macros::next_step(replay, now) {
+        let mut cmds = vec![DwellCommand::MoveTo {
+            x: step.x,
+            y: step.y,
+        }];
+        cmds.extend_from_slice(match step.click {
+            dwell::ClickType::Right => &[DwellCommand::SecondaryClick][..],
+            dwell::ClickType::Double => &[
+                DwellCommand::Press,
+                DwellCommand::Release,
+                DwellCommand::Press,
+                DwellCommand::Release,
+            ],
+            _ => &[DwellCommand::Press, DwellCommand::Release],
+        });
+        cmds.push(DwellCommand::PlaySound(dwell::Sound::Click(step.click)));
+        run_commands(st, &cmds);
+    } else if macros::finished(replay) {
+        let (x, y) = replay.home;
+        st.replay = None;
+        run_commands(st, &[DwellCommand::MoveTo { x, y }]);
+    }
+}
+
+fn macro_state(st: &StateMainLoop) -> String {
+    match (&st.recording, &st.replay) {
+        (_, Some(replay)) => format!("playing {}", replay.name),
+        (Some(recording), None) => format!("recording {}", recording.name),
+        (None, None) => "none".to_string(),
+    }
+}
+
+// Asks the window manager to close the application window at x, y, like
+// its close button would
+fn close_window_at(st_x11: &StateX11, x: i32, y: i32) {
+    let display = st_x11.display;
+    let (window, failed) = catch_x_errors(display, || {
+        let (mut win_x, mut win_y, mut top_level) = (0, 0, 0);
+        unsafe {
+            xlib::XTranslateCoordinates(
+                display,
+                st_x11.root,
+                st_x11.root,
+                x,
+                y,
+                &mut win_x,
+                &mut win_y,
+                &mut top_level,
+            );
+        }
+        if top_level == 0 {
+            return None;
+        }
+        client_window(st_x11, top_level)
+    });
+    let window = match window {
+        Some(window) if !failed => window,
+        // The desktop, or gone already
+        _ => return,
+    };
+
+    let mut data = xlib::ClientMessageData::new();
+    // A user action, as opposed to the application asking for it
+    data.set_long(1, 2);
+    let mut ev = xlib::XEvent {
+        client_message: xlib::XClientMessageEvent {
+            type_: xlib::ClientMessage,
+            serial: 0,
+            send_event: xlib::True,
+            display,
+            window,
+            message_type: st_x11.net_close_window_atom,
+            format: 32,
+            data,
+        },
+    };
+    unsafe {
+        xlib::XSendEvent(
+            display,
+            st_x11.root,
+            xlib::False,
+            xlib::SubstructureRedirectMask | xlib::SubstructureNotifyMask,
+            &mut ev,
+        );
+        xlib::XFlush(display);
+    }
+}
+
+// The reply to `status` on the control socket
+fn state_name(st: &StateMainLoop) -> &'static str {
+    if st.dwell.is_asleep() {
+        "asleep"
+    } else if st.dwell.is_active() {
+        Status::Enabled.as_str()
+    } else {
+        Status::Disabled.as_str()
+    }
+}
+
+// Today's clicks from the stats, less the ones before a reset-clicks
+fn clicks_today(st: &StateMainLoop) -> u64 {
+    let now = std::time::SystemTime::now();
+    let clicks = stats::today(&st.stats, now).clicks();
+    match &st.clicks_reset {
+        Some((day, before)) if *day == stats::local_date(now) => clicks.saturating_sub(*before),
+        _ => clicks,
+    }
+}
+
+fn status_reply(st: &StateMainLoop) -> String {
+    let window = window_description(&st.st_x11, st.pointer_window);
+    format!(
+        "state {}\ndragging {}\ndelivering {}\nnext_click {}\ntheme {}\nsound_theme {}\nprofile {}\nmacro {}\ndecision {}\nwindow {}\nclicks_today {}\npid {}\n",
+        state_name(st),
+        st.dwell.is_dragging(),
+        st.st_delivery.failures == 0,
+        st.dwell.next_click_type().as_str(),
+        st.overlay_theme.as_str(),
+        sound_theme_name(st),
+        profile_name(st),
+        macro_state(st),
+        st.dwell.last_decision(),
+        window.as_deref().unwrap_or("none"),
+        clicks_today(st),
+        std::process::id()
+    )
+}
+
+// Status bars watch this root window property for PropertyNotify instead of
+// polling. It holds the parts of status_reply that don't change every tick,
+// as `key value` lines.
+const STATE_PROPERTY: &str = "_RTMOUSE_STATE";
+
+fn publish_state(st: &mut StateMainLoop) {
+    let value = format!(
+        "state {}\ndragging {}\ndelivering {}\nnext_click {}\npid {}\n",
+        state_name(st),
+        st.dwell.is_dragging(),
+        st.st_delivery.failures == 0,
+        st.dwell.next_click_type().as_str(),
+        std::process::id()
+    );
+    if value == st.published_state {
+        return;
+    }
+    // Subscribers get the lines that changed
+    if !st.subscribers.is_empty() {
+        let before: Vec<&str> = st.published_state.lines().collect();
+        for line in value.lines().filter(|l| !before.contains(l)) {
+            control::broadcast(&mut st.subscribers, &format!("{}\n", line));
+        }
+    }
+
+    let st_x11 = &st.st_x11;
+    unsafe {
+        xlib::XChangeProperty(
+            st_x11.display,
+            st_x11.root,
+            st_x11.state_atom,
+            st_x11.utf8_string_atom,
+            8,
+            xlib::PropModeReplace,
+            value.as_ptr(),
+            value.len() as i32,
+        );
+        xlib::XFlush(st_x11.display);
+    }
+    st.published_state = value;
+}
+
+// Carries out a request from the control socket or a client message and
+// returns the status reply
+fn handle_request(st: &mut StateMainLoop, request: control::Request) -> String {
+    let active = match request {
+        control::Request::Status | control::Request::Subscribe => return status_reply(st),
+        control::Request::ClickType(click) => {
+            let cmds = st.dwell.set_click_type(click);
+            run_commands(st, &cmds);
+            return status_reply(st);
+        }
+        control::Request::Theme(theme) => {
+            set_overlay_theme(st, theme);
+            return status_reply(st);
+        }
+        control::Request::SoundTheme(name) => {
+            if let Err(e) = choose_sound_theme(st, &name) {
+                return format!("error {}\n", e);
+            }
+            return status_reply(st);
+        }
+        control::Request::ResetClicks => {
+            let now = std::time::SystemTime::now();
+            let before = stats::today(&st.stats, now).clicks();
+            st.clicks_reset = Some((stats::local_date(now), before));
+            return status_reply(st);
+        }
+        control::Request::RecordMacro(name) => {
+            st.recording = Some(macros::start_recording(&name, Instant::now()));
+            return status_reply(st);
+        }
+        control::Request::StopMacro => {
+            // Stops a replay that's going wrong too
+            st.replay = None;
+            match st.recording.take() {
+                Some(r) if r.steps.is_empty() => {
+                    return format!("error no clicks were recorded, {} wasn't saved\n", r.name)
+                }
+                Some(r) => {
+                    if let Err(e) = macros::save(&r.name, &r.steps) {
+                        return format!("error can't save macro {}: {}\n", r.name, e);
+                    }
+                }
+                None => {}
+            }
+            return status_reply(st);
+        }
+        control::Request::PlayMacro(name) => {
+            return match play_macro(st, &name) {
+                Ok(()) => status_reply(st),
+                Err(e) => format!("error {}\n", e),
+            };
+        }
+        control::Request::Enable => true,
+        control::Request::Disable => false,
+        control::Request::Toggle => !st.dwell.is_active(),
+    };
+    let cmds = st.dwell.set_active(active);
+    run_commands(st, &cmds);
+    status_reply(st)
+}
+
+fn poll_control(st: &mut StateMainLoop) {
+    if let Some(listener) = st.control.take() {
+        let subscribed = control::poll(&listener, |request| handle_request(st, request));
+        st.subscribers.extend(subscribed);
+        st.control = Some(listener);
+    }
+}
+
+fn handle_client_requests(st: &mut StateMainLoop) {
+    for request in take_client_requests(&mut st.st_is_click_inhibited) {
+        handle_request(st, request);
+    }
+}
+
+// Whatever the state machine thinks, we never hold the button down longer
+// than stuck_drag_limit.
+fn check_stuck_drag(st: &mut StateMainLoop) {
+    let pressed_at = match st.pressed_at {
+        Some(t) => t,
+        None => return,
+    };
+    if pressed_at.elapsed() < st.config.stuck_drag_limit {
+        return;
+    }
+
+    eprintln!(
+        "Error: the button has been held for {}s, releasing it",
+        pressed_at.elapsed().as_secs()
+    );
+    run_commands(st, &[DwellCommand::Release]);
+    st.dwell.drag_released();
+    play_warning_sound(st);
+    monitor::send(&st.config.monitor_command, monitor::Alert::StuckDrag);
+}
+
+fn update_monitor(st: &mut StateMainLoop) {
+    if st.config.monitor_command.is_empty() {
+        return;
+    }
+    let clicks = stats::today(&st.stats, std::time::SystemTime::now()).clicks();
+    let alerts = monitor::update(
+        &mut st.monitor,
+        st.dwell.is_active(),
+        st.dwell.is_asleep(),
+        clicks,
+        Instant::now(),
+        st.config.monitor_no_clicks_after,
+    );
+    for alert in alerts {
+        monitor::send(&st.config.monitor_command, alert);
+    }
+}
+
+// Needs the display, so call it after initialize_x11_state
+#[cfg(feature = "overlays")]
+pub fn initialize_overlays(st: &mut StateMainLoop) {
+    let style = &st.overlay_style;
+    let outline = style.outline * 2;
+
+    if st.config.anchor_dot {
+        let size = style.dot_radius * 2 + 1 + outline;
+        st.anchor_dot = Some(overlay::create(&st.st_x11, size, size));
+    }
+    if st.config.drag_bar {
+        st.drag_bar = Some(overlay::create(
+            &st.st_x11,
+            style.bar_width + outline,
+            style.bar_height + outline,
+        ));
+    }
+    if st.config.countdown != config::Countdown::Off {
+        let mut countdown = overlay::create(&st.st_x11, 1, 1);
+        overlay::set_font(&st.st_x11, &mut countdown, style.countdown_font);
+        // Room for "60000", the longest dwell_time
+        let (w, h) = overlay::text_size(&countdown, "60000");
+        overlay::resize(
+            &st.st_x11,
+            &mut countdown,
+            w.max(1) + outline,
+            h.max(1) + outline,
+        );
+        st.countdown = Some(countdown);
+    }
+}
+
+#[cfg(not(feature = "overlays"))]
+pub fn initialize_overlays(_st: &mut StateMainLoop) {}
+
+// Needs the display too, and has to come before the first set_active
+pub fn initialize_leds(st: &mut StateMainLoop) {
+    leds::init(&mut st.st_leds, st.st_x11.display, &st.config);
+}
+
+pub fn initialize_panels(st: &mut StateMainLoop) {
+    if st.config.pause_button {
+        let button = panel::create_pause_button(&st.st_x11, st.config.pause_button_size);
+        st.panels.push(button);
+    }
+    if st.config.panel {
+        let strip = panel::create_control_panel(&st.st_x11, &st.config);
+        st.panels.push(strip);
+    }
+}
+
+// Tells the state machine where the panel buttons are, after a panel
+// moved. Finding out is two round trips a panel, so only then.
+fn locate_panels(st: &mut StateMainLoop) {
+    if !take_panels_moved(&mut st.st_is_click_inhibited) || st.panels.is_empty() {
+        return;
+    }
+    let buttons = st
+        .panels
+        .iter()
+        .flat_map(|p| panel::buttons(&st.st_x11, p))
+        .collect();
+    st.dwell.set_panel(buttons);
+}
+
+fn update_panels(st: &mut StateMainLoop) {
+    let dwell_time = st.dwell.dwell_time().as_millis().max(1) as u32;
+    let progress = st.dwell.dwell_time_left(Instant::now()).map_or(0, |left| {
+        let done = dwell_time.saturating_sub(left.as_millis() as u32);
+        done * panel::PROGRESS_STEPS / dwell_time
+    });
+    let look = panel::Look {
+        paused: !st.dwell.is_active(),
+        next_click: st.dwell.next_click_type(),
+        progress,
+    };
+    for p in &mut st.panels {
+        panel::show(&st.st_x11, p, look);
+    }
+}
+
+// While dwell clicking is off, resting on a pause button turns it on
+fn watch_pause_button(st: &mut StateMainLoop, x: i32, y: i32) {
+    let cmds = st.dwell.update_paused(x, y, Instant::now());
+    if !cmds.is_empty() {
+        explain_decision(st, x, y);
+        run_commands(st, &cmds);
+    }
+}
+
+// Where the pointer is while dwell clicking is off, for the pause buttons
+// and shakes. Only asked when it moved.
+fn pointer_while_off(st: &mut StateMainLoop, moved: bool) -> Option<(i32, i32)> {
+    if moved || st.pointer.is_none() {
+        let pointer = query_pointer(&st.st_x11);
+        if pointer.root != st.st_x11.root {
+            st.pointer = None;
+            return None;
+        }
+        st.pointer = Some((pointer.x, pointer.y));
+    }
+    st.pointer
+}
+
+// Shaking goes on for a moment after it toggled, that mustn't start a dwell
+// or count towards the next shake
+const SHAKE_SETTLE: std::time::Duration = std::time::Duration::from_secs(1);
+
+fn settling_after_shake(st: &StateMainLoop) -> bool {
+    st.shaken_at.is_some_and(|t| t.elapsed() < SHAKE_SETTLE)
+}
+
+// Feeds the shake detector, and toggles dwell clicking on a shake.
+// Returns whether it did.
+fn toggle_on_shake(st: &mut StateMainLoop, x: i32, y: i32) -> bool {
+    if !st.config.shake_toggle || settling_after_shake(st) {
+        return false;
+    }
+    let now = Instant::now();
+    if !shake::feed(&mut st.st_shake, &st.config, x, y, now) {
+        return false;
+    }
+    st.shaken_at = Some(now);
+    let cmds = st.dwell.set_active(!st.dwell.is_active());
+    run_commands(st, &cmds);
+    true
+}
+
+// Rebuilds every overlay in the new theme
+#[cfg(feature = "overlays")]
+pub fn set_overlay_theme(st: &mut StateMainLoop, theme: config::Theme) {
+    if theme == st.overlay_theme {
+        return;
+    }
+    for overlay in vec![
+        st.anchor_dot.take(),
+        st.drag_bar.take(),
+        st.countdown.take(),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        overlay::destroy(&st.st_x11, overlay);
+    }
+    st.anchor_dot_at = None;
+    st.drag_bar_at = None;
+    st.countdown_at = None;
+
+    st.overlay_theme = theme;
+    st.overlay_style = overlay::style(&st.config, theme);
+    initialize_overlays(st);
+}
+
+#[cfg(not(feature = "overlays"))]
+pub fn set_overlay_theme(st: &mut StateMainLoop, theme: config::Theme) {
+    st.overlay_theme = theme;
+}
+
+// Docking, undocking or a new resolution, no restart needed
+fn screen_changed(st: &mut StateMainLoop) {
+    query_monitors(&mut st.st_x11);
+    for p in &st.panels {
+        panel::reposition(&st.st_x11, p);
+    }
+    reset_overlays(st);
+}
+
+// Hides them all, to be drawn again from scratch wherever they belong now
+#[cfg(feature = "overlays")]
+fn reset_overlays(st: &mut StateMainLoop) {
+    for overlay in [&mut st.anchor_dot, &mut st.drag_bar, &mut st.countdown]
+        .iter_mut()
+        .filter_map(|o| o.as_mut())
+    {
+        overlay::hide(&st.st_x11, overlay);
+    }
+    st.anchor_dot_at = None;
+    st.drag_bar_at = None;
+    st.countdown_at = None;
+}
+
+#[cfg(not(feature = "overlays"))]
+fn reset_overlays(_st: &mut StateMainLoop) {}
+
+#[cfg(feature = "overlays")]
+fn update_overlays(st: &mut StateMainLoop) {
+    update_panels(st);
+    update_anchor_dot(st);
+    update_drag_bar(st);
+    update_countdown(st);
+}
+
+#[cfg(not(feature = "overlays"))]
+fn update_overlays(st: &mut StateMainLoop) {
+    update_panels(st);
+}
+
+// The monitor the pointer is on. The countdown and drag bar sit next to
+// the pointer, and are kept on its monitor rather than spilling onto the
+// next one or into the dead space between them.
+#[cfg(feature = "overlays")]
+fn pointer_monitor(st_x11: &StateX11, pointer: Option<(i32, i32)>) -> Option<geometry::Rect> {
+    let (x, y) = pointer?;
+    geometry::monitor_at(&st_x11.monitors, x, y)
+}
+
+// min_movement_mm on the monitor the pointer is on, None when that one
+// doesn't say how big it is
+fn movement_pixels(st_x11: &StateX11, mm: u32, x: i32, y: i32) -> Option<u32> {
+    let i = geometry::monitor_index_at(&st_x11.monitors, x, y)?;
+    let size = *st_x11.monitor_sizes.get(i)?;
+    geometry::mm_to_pixels(mm, st_x11.monitors[i], size)
+}
+
+// Above and to the right of the pointer, opposite the drag bar
+#[cfg(feature = "overlays")]
+const COUNTDOWN_OFFSET: (i32, i32) = (24, -16);
+
+#[cfg(feature = "overlays")]
+fn update_countdown(st: &mut StateMainLoop) {
+    let overlay = match &mut st.countdown {
+        Some(overlay) => overlay,
+        None => return,
+    };
+    let config = &st.config;
+    let style = &st.overlay_style;
+
+    let on_screen = st.pointer_root == st.st_x11.root;
+    let pointer = st.pointer.filter(|_| on_screen);
+    let shown = match (st.dwell.dwell_time_left(Instant::now()), pointer) {
+        (Some(left), Some((x, y))) => Some((
+            x + COUNTDOWN_OFFSET.0,
+            y + COUNTDOWN_OFFSET.1,
+            overlay::countdown_text(config.countdown, left, st.dwell.dwell_time()),
+        )),
+        _ => None,
+    };
+    if shown == st.countdown_at {
+        return;
+    }
+
+    match &shown {
+        Some((x, y, text)) => {
+            let o = style.outline;
+            let mut shapes = Vec::new();
+            if o > 0 {
+                let (w, h) = overlay::text_size(overlay, text);
+                shapes.push(overlay::Shape::Rect {
+                    x: 0,
+                    y: 0,
+                    w: w + 2 * o,
+                    h: h + 2 * o,
+                    color: overlay::OUTLINE_COLOR,
+                });
+            }
+            shapes.push(overlay::Shape::Text {
+                x: o as i32,
+                y: o as i32,
+                text: text.clone(),
+                color: style.countdown_color,
+            });
+            let within = pointer_monitor(&st.st_x11, pointer);
+            overlay::show_at(&st.st_x11, overlay, *x, *y, within);
+            overlay::draw(&st.st_x11, overlay, &shapes);
+        }
+        None => overlay::hide(&st.st_x11, overlay),
+    }
+    st.countdown_at = shown;
+}
+
+// Below and to the right of the pointer, clear of the cursor image
+#[cfg(feature = "overlays")]
+const DRAG_BAR_OFFSET: (i32, i32) = (24, 24);
+
+#[cfg(feature = "overlays")]
+fn update_drag_bar(st: &mut StateMainLoop) {
+    let bar = match &mut st.drag_bar {
+        Some(bar) => bar,
+        None => return,
+    };
+    let config = &st.config;
+    let style = &st.overlay_style;
+
+    let on_screen = st.pointer_root == st.st_x11.root;
+    let pointer = st.pointer.filter(|_| on_screen);
+    let shown = match (st.dwell.drag_time_left(Instant::now()), pointer) {
+        (Some(left), Some((x, y))) => {
+            let total = config.drag_time.min(config.max_drag_duration);
+            let filled = (style.bar_width as f64 * left.as_secs_f64()
+                / total.as_secs_f64().max(0.001))
+            .ceil()
+            .min(style.bar_width as f64) as u32;
+            Some((x + DRAG_BAR_OFFSET.0, y + DRAG_BAR_OFFSET.1, filled))
+        }
+        _ => None,
+    };
+    if shown == st.drag_bar_at {
+        return;
+    }
+    st.drag_bar_at = shown;
+
+    match shown {
+        Some((x, y, filled)) => {
+            let o = style.outline;
+            let mut shapes = Vec::new();
+            if o > 0 {
+                shapes.push(overlay::Shape::Rect {
+                    x: 0,
+                    y: 0,
+                    w: filled + 2 * o,
+                    h: style.bar_height + 2 * o,
+                    color: overlay::OUTLINE_COLOR,
+                });
+            }
+            shapes.push(overlay::Shape::Rect {
+                x: o as i32,
+                y: o as i32,
+                w: filled,
+                h: style.bar_height,
+                color: style.bar_color,
+            });
+            let within = pointer_monitor(&st.st_x11, pointer);
+            overlay::show_at(&st.st_x11, bar, x, y, within);
+            overlay::draw(&st.st_x11, bar, &shapes);
+        }
+        None => overlay::hide(&st.st_x11, bar),
+    }
+}
+
+#[cfg(feature = "overlays")]
+fn update_anchor_dot(st: &mut StateMainLoop) {
+    let dot = match &mut st.anchor_dot {
+        Some(dot) => dot,
+        None => return,
+    };
+    // The overlays live on the default screen
+    let on_screen = st.pointer_root == st.st_x11.root;
+    let anchor = st.dwell.armed_anchor().filter(|_| on_screen);
+    if anchor == st.anchor_dot_at {
+        return;
+    }
+    st.anchor_dot_at = anchor;
+
+    match anchor {
+        Some((x, y)) => {
+            let style = &st.overlay_style;
+            let r = style.dot_radius as i32;
+            let o = style.outline as i32;
+            let mut shapes = Vec::new();
+            if o > 0 {
+                shapes.push(overlay::Shape::Circle {
+                    x: r + o,
+                    y: r + o,
+                    r: r + o,
+                    color: overlay::OUTLINE_COLOR,
+                });
+            }
+            shapes.push(overlay::Shape::Circle {
+                x: r + o,
+                y: r + o,
+                r,
+                color: style.dot_color,
+            });
+            // Centered on the spot it marks, even at the edge
+            overlay::show_at(&st.st_x11, dot, x, y, None);
+            overlay::draw(&st.st_x11, dot, &shapes);
+        }
+        None => overlay::hide(&st.st_x11, dot),
+    }
+}
+
+// Counts the decision for the usage stats and logs it for --explain.
+fn explain_decision(st: &mut StateMainLoop, x: i32, y: i32) {
+    let decision = st.dwell.last_decision();
+    let event = stats::event(&st.counted_decision, decision);
+    if let (Some(stats::Event::Click(click)), Some(recording)) = (event, &mut st.recording) {
+        macros::record(recording, click, x, y, Instant::now());
+    }
+    if let Some(event) = event {
+        let now = std::time::SystemTime::now();
+        stats::count(&mut st.stats, event, now);
+        // Where the dwell was given up, not where the pointer went
+        let (x, y) = if event == stats::Event::Aborted {
+            st.counted_at
+        } else {
+            (x, y)
+        };
+        if !st.subscribers.is_empty() {
+            let line = format!("event {} {} {}\n", event.as_str(), x, y);
+            control::broadcast(&mut st.subscribers, &line);
+        }
+        if st.config.history_log {
+            history::append(&history::Record {
+                time: now,
+                event,
+                x,
+                y,
+                class: pointer_window_class(&st.st_x11, st.pointer_window).unwrap_or_default(),
+            });
+        }
+    }
+    st.counted_decision.clone_from(decision);
+    st.counted_at = (x, y);
+
+    // Clicks name the window they went to, since "clicks vanish in app X"
+    // is a common report. Looking it up costs round trips, so only then.
+    let clicked = matches!(
+        decision,
+        dwell::Decision::Clicked(_) | dwell::Decision::DragStarted | dwell::Decision::DragReleased
+    );
+    let window = if st.st_explain.enabled && clicked {
+        window_description(&st.st_x11, st.pointer_window)
+    } else {
+        None
+    };
+    log_decision(&mut st.st_explain, x, y, decision, window.as_deref());
+}
+
+// Saved now and then as well as on shutdown, so a crash or a SIGKILL loses
+// a minute at most
+const STATS_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+fn save_stats_now_and_then(st: &mut StateMainLoop) {
+    if st.stats.dirty && st.stats_saved.elapsed() >= STATS_SAVE_INTERVAL {
+        stats::save(&mut st.stats);
+        st.stats_saved = Instant::now();
+    }
+}
+
+// How often display_powered_down asks, it's a round trip and the monitors
+// take longer than this to come back on anyway
+const DPMS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Whether DPMS has the monitors in standby, suspend or off. Servers with
+// DPMS disabled, or without it, always have them on.
+fn display_powered_down(st: &mut StateMainLoop) -> bool {
+    if !st.st_x11.has_dpms {
+        return false;
+    }
+    if st
+        .dpms_checked
+        .is_some_and(|t| t.elapsed() < DPMS_CHECK_INTERVAL)
+    {
+        return st.display_off;
+    }
+    let (mut level, mut enabled) = (dpms::DPMSModeOn, 0);
+    unsafe {
+        dpms::DPMSInfo(st.st_x11.display, &mut level, &mut enabled);
+    }
+    st.display_off = enabled != 0 && level != dpms::DPMSModeOn;
+    st.dpms_checked = Some(Instant::now());
+    st.display_off
+}
+
+// Well under the shortest screen saver timeout anyone sets
+const IDLE_RESET_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
+
+// With idle_inhibit, resets the idle timers while a drag is held or a dwell
+// is armed. XResetScreenSaver covers the X server's own screen saver and
+// DPMS, xdg-screensaver the desktop's, on a thread since it can be slow.
+fn keep_awake(st: &mut StateMainLoop) {
+    if !st.config.idle_inhibit
+        || !(st.dwell.is_dragging() || st.dwell.armed_anchor().is_some())
+        || st
+            .idle_reset
+            .is_some_and(|t| t.elapsed() < IDLE_RESET_INTERVAL)
+    {
+        return;
+    }
+    st.idle_reset = Some(Instant::now());
+    unsafe {
+        xlib::XResetScreenSaver(st.st_x11.display);
+    }
+    thread::spawn(|| {
+        let _ = Command::new("xdg-screensaver")
+            .arg("reset")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    });
+}
+
+// keyboard_dwell, if the pointer is over one of keyboard_classes. Only
+// looked up again once it's over a different window. Quick dwells are
+// handed to the state machine from here.
+fn keyboard_dwell(st: &mut StateMainLoop) -> Option<config::KeyboardDwell> {
+    if st.config.keyboard_dwell == config::KeyboardDwell::Normal {
+        st.dwell.set_keyboard_dwell(None);
+        return None;
+    }
+    let window = st.pointer_window;
+    let over = match st.keyboard_checked {
+        Some((checked, over)) if checked == window => over,
+        _ => {
+            // Some keyboards are override-redirect, with no WM_STATE on them
+            let (class, failed) = catch_x_errors(st.st_x11.display, || {
+                pointer_window_class(&st.st_x11, window)
+                    .or_else(|| window_class(&st.st_x11, window).filter(|_| window != 0))
+            });
+            let classes = &st.config.keyboard_classes;
+            let over = !failed
+                && class
+                    .is_some_and(|class| classes.iter().any(|c| c.eq_ignore_ascii_case(&class)));
+            st.keyboard_checked = Some((window, over));
+            over
+        }
+    };
+    let quick = over && st.config.keyboard_dwell == config::KeyboardDwell::Quick;
+    st.dwell
+        .set_keyboard_dwell(Some(st.config.keyboard_dwell_time).filter(|_| quick));
+    Some(st.config.keyboard_dwell).filter(|_| over)
+}
+
+// The XInput name of a device, None if it's gone already
+fn device_name(st_x11: &StateX11, device: i32) -> Option<String> {
+    unsafe {
+        let mut n = 0;
+        let info = xinput2::XIQueryDevice(st_x11.display, device, &mut n);
+        if info.is_null() {
+            return None;
+        }
+        let name = (n > 0).then(|| CStr::from_ptr((*info).name).to_string_lossy().into_owned());
+        xinput2::XIFreeDeviceInfo(info);
+        name
+    }
+}
+
+// With device_profiles, a different device moving the pointer switches to
+// its profile, or back to the startup one when none is for it. Only the
+// options the state machine and the tick loop read switch over, the
+// windows and overlays stay as they started.
+fn switch_device_profile(st: &mut StateMainLoop) {
+    let device = match take_moved_by(&mut st.st_is_click_inhibited) {
+        Some(device) if !st.device_profiles.is_empty() && st.moving_device != Some(device) => {
+            device
+        }
+        _ => return,
+    };
+    st.moving_device = Some(device);
+    let name = match device_name(&st.st_x11, device) {
+        Some(name) => name,
+        None => return,
+    };
+    if is_xtest_pointer(name.as_bytes()) {
+        // Our own clicks, and other programs'
+        return;
+    }
+    let picked = st
+        .device_profiles
+        .iter()
+        .position(|(p, _)| p.matches(&name));
+    if picked == st.device_profile {
+        return;
+    }
+    st.device_profile = picked;
+    let config = match picked {
+        Some(i) => st.device_profiles[i].1.clone(),
+        None => st.startup_config.clone(),
+    };
+    st.dwell.set_config(config.clone());
+    st.config = config;
+    eprintln!(
+        "note: {} is moving the pointer, switched to profile {}",
+        name,
+        profile_name(st)
+    );
+}
+
+// The profile running now, for the status
+fn profile_name(st: &StateMainLoop) -> &str {
+    match st.device_profile {
+        Some(i) => &st.device_profiles[i].0.profile,
+        None => st.startup_profile.as_deref().unwrap_or("none"),
+    }
+}
+
+// A tick that doesn't dwell. The motion seen meanwhile is dropped, and the
+// pointer has to move again afterwards before a dwell starts.
+fn hold_still(st: &mut StateMainLoop, pause: fn(&mut DwellStateMachine) -> Vec<DwellCommand>) {
+    take_motion_seen(&mut st.st_is_click_inhibited);
+    take_nudge(&mut st.st_is_click_inhibited);
+    take_click_key_pressed(&mut st.st_is_click_inhibited);
+    let (x, y) = st.pointer.take().unwrap_or(st.counted_at);
+    let cmds = pause(&mut st.dwell);
+    explain_decision(st, x, y);
+    run_commands(st, &cmds);
+    precision_mode(st);
+    update_overlays(st);
+    publish_state(st);
+}
+
+// Leaving on SIGINT, SIGTERM or SIGHUP. The state machine lets go of
+// anything held and says Terminated, which puts the keyboard lights back.
+pub fn shutdown(st: &mut StateMainLoop) {
+    let cmds = st.dwell.shutdown();
+    run_commands(st, &cmds);
+    // Like check_stuck_drag, whatever the state machine thinks, the XTest
+    // button doesn't stay down after we're gone
+    if st.pressed_at.is_some() {
+        run_commands(st, &[DwellCommand::Release]);
+    }
+    if let Some((device, original, _)) = st.precision.take() {
+        set_accel_speed(&st.st_x11, device, original);
+        let _ = fs::remove_file(precision_file());
+    }
+    if st.stats.dirty {
+        stats::save(&mut st.stats);
+    }
+    unsafe {
+        xlib::XSync(st.st_x11.display, xlib::False);
+    }
+}
+
+pub fn main_loop(st: &mut StateMainLoop) {
+    leds::update(&mut st.st_leds, st.st_x11.display, Instant::now());
+    poll_control(st);
+    check_stuck_drag(st);
+    update_monitor(st);
+    update_nudge_grab(st);
+    save_stats_now_and_then(st);
+
+    if !st.dwell.is_active() {
+        // Keep the queue drained, so nothing stale is acted on once enabled
+        poll_inhibit_mask(&mut st.st_is_click_inhibited, &st.st_x11);
+        locate_panels(st);
+        take_click_key_pressed(&mut st.st_is_click_inhibited);
+        let moved = take_motion_seen(&mut st.st_is_click_inhibited);
+        take_nudge(&mut st.st_is_click_inhibited);
+        if take_mapping_changed(&mut st.st_is_click_inhibited) {
+            refresh_button_map(&mut st.st_x11);
+            refresh_absolute_devices(&mut st.st_x11);
+        }
+        if take_screen_changed(&mut st.st_is_click_inhibited) {
+            screen_changed(st);
+        }
+        if !st.panels.is_empty() || st.config.shake_toggle {
+            if let Some((x, y)) = pointer_while_off(st, moved) {
+                if !toggle_on_shake(st, x, y) {
+                    watch_pause_button(st, x, y);
+                }
+            }
+        } else {
+            st.pointer = None;
+        }
+        st.replay = None;
+        handle_client_requests(st);
+        precision_mode(st);
+
+        update_overlays(st);
+        publish_state(st);
+        return;
+    }
+
+    st.st_timing.tick_start = Instant::now();
+
+    let buttons = poll_inhibit_mask(&mut st.st_is_click_inhibited, &st.st_x11);
+    locate_panels(st);
+    if take_mapping_changed(&mut st.st_is_click_inhibited) {
+        refresh_button_map(&mut st.st_x11);
+        refresh_absolute_devices(&mut st.st_x11);
+    }
+    if take_screen_changed(&mut st.st_is_click_inhibited) {
+        screen_changed(st);
+    }
+    handle_client_requests(st);
+    switch_device_profile(st);
+
+    if display_powered_down(st) {
+        // The motion that wakes the monitors doesn't get to start a dwell,
+        // and neither does any from before
+        hold_still(st, DwellStateMachine::display_off);
+        return;
+    }
+    if st.replay.is_some() {
+        run_replay(st, st.st_timing.tick_start);
+        hold_still(st, DwellStateMachine::replaying);
+        return;
+    }
+    if settling_after_shake(st) {
+        hold_still(st, DwellStateMachine::shaken);
+        return;
+    }
+
+    // Without raw motion the pointer is where it was, so skip the round
+    // trip. Only warps by other clients slip past this, and the next real
+    // motion catches up with them.
+    let moved = take_motion_seen(&mut st.st_is_click_inhibited);
+    let nudge = take_nudge(&mut st.st_is_click_inhibited);
+    let (x, y) = match st.pointer {
+        Some(pointer) if !moved => pointer,
+        _ => {
+            let pointer = query_pointer(&st.st_x11);
+            st.pointer_window = pointer.window;
+            st.pointer_root = pointer.root;
+            (pointer.x, pointer.y)
+        }
+    };
+    st.pointer = Some((x, y));
+
+    if !st.st_x11.watched_roots.contains(&st.pointer_root) {
+        let cmds = st.dwell.pointer_left();
+        explain_decision(st, x, y);
+        run_commands(st, &cmds);
+        update_overlays(st);
+        publish_state(st);
+        return;
+    }
+    if toggle_on_shake(st, x, y) {
+        // It was on, so that switched it off
+        explain_decision(st, x, y);
+        update_overlays(st);
+        publish_state(st);
+        return;
+    }
+
+    if keyboard_dwell(st) == Some(config::KeyboardDwell::Off) {
+        hold_still(st, DwellStateMachine::over_keyboard);
+        return;
+    }
+
+    let (x, y) = nudge_pointer(st, nudge, x, y);
+    if let Some(mm) = st.config.min_movement_mm {
+        let pixels = movement_pixels(&st.st_x11, mm, x, y);
+        st.dwell
+            .set_min_movement(pixels.unwrap_or(st.config.min_movement_pixels));
+    }
+    st.dwell
+        .set_absolute(st.st_is_click_inhibited.absolute_motion);
+    let cmds = st.dwell.update(Sample {
+        x,
+        y,
+        buttons,
+        now: st.st_timing.tick_start,
+        unfocused: pointer_unfocused(st),
+        grabbed: pointer_grabbed(st),
+    });
+
+    explain_decision(st, x, y);
+    keep_awake(st);
+    precision_mode(st);
+
+    if st.dwell.is_moving() {
+        log_timing(&st.st_timing, "motion detected");
+    }
+    if buttons != 0 {
+        log_timing(&st.st_timing, "click inhibited");
+    }
+
+    run_commands(st, &cmds);
+
+    if take_click_key_pressed(&mut st.st_is_click_inhibited) {
+        let cmds = st.dwell.click_now(st.st_timing.tick_start);
+        explain_decision(st, x, y);
+        run_commands(st, &cmds);
+    }
+
+    update_overlays(st);
+    publish_state(st);
+}
+
+pub fn initialize_main_loop_state(config: DwellConfig) -> StateMainLoop {
+    StateMainLoop {
+        dwell: DwellStateMachine::new(config.clone()),
+        st_explain: StateExplain {
+            enabled: false,
+            last_line: String::new(),
+            line: String::new(),
+            file: None,
+            file_lines: 0,
+        },
+        st_is_click_inhibited: StateIsClickInhibited {
+            inhibit_mask: 0,
+            uninhibit_mask: 0,
+            motion_seen: false,
+            moved_by: None,
+            absolute_motion: false,
+            pointer_device: None,
+            panels_moved: false,
+            mapping_changed: false,
+            click_key_down: false,
+            click_key_pressed: false,
+            client_requests: Vec::new(),
+            nudge: (0, 0),
+            screen_changed: false,
+        },
+        st_x11: StateX11 {
+            display: std::ptr::null_mut(),
+            xi_extension_opcode: 0,
+            root: 0,
+            button_map: Vec::new(),
+            primary_button: None,
+            click_keycode: 0,
+            control_atom: 0,
+            state_atom: 0,
+            utf8_string_atom: 0,
+            wm_state_atom: 0,
+            net_wm_name_atom: 0,
+            net_close_window_atom: 0,
+            accel_speed_atom: 0,
+            float_atom: 0,
+            watched_roots: Vec::new(),
+            events: None,
+            nudge_keycodes: [0; 4],
+            nudge_grabbed: false,
+            monitors: Vec::new(),
+            monitor_sizes: Vec::new(),
+            absolute_devices: Vec::new(),
+            has_dpms: false,
+        },
+        st_timing: StateTiming {
+            enabled: std::env::var_os("RTMOUSE_DEBUG_TIMING").is_some(),
+            tick_start: Instant::now(),
+        },
+        st_delivery: StateDelivery { failures: 0 },
+        st_leds: leds::StateLeds::new(),
+        #[cfg(feature = "sound")]
+        st_sound: sound::StateSound::new(),
+        send_event_target: None,
+        pressed_at: None,
+        dpms_checked: None,
+        display_off: false,
+        idle_reset: None,
+        pointer: None,
+        precision: None,
+        pointer_window: 0,
+        pointer_root: 0,
+        #[cfg(feature = "overlays")]
+        anchor_dot: None,
+        #[cfg(feature = "overlays")]
+        anchor_dot_at: None,
+        #[cfg(feature = "overlays")]
+        drag_bar: None,
+        #[cfg(feature = "overlays")]
+        drag_bar_at: None,
+        #[cfg(feature = "overlays")]
+        countdown: None,
+        #[cfg(feature = "overlays")]
+        countdown_at: None,
+        overlay_theme: config.overlay_theme,
+        #[cfg(feature = "overlays")]
+        overlay_style: overlay::style(&config, config.overlay_theme),
+        control: None,
+        subscribers: Vec::new(),
+        published_state: String::new(),
+        stats: stats::load(),
+        stats_saved: Instant::now(),
+        counted_decision: dwell::Decision::Disabled,
+        counted_at: (0, 0),
+        clicks_reset: None,
+        monitor: monitor::Monitor::new(Instant::now()),
+        recording: None,
+        replay: None,
+        panels: Vec::new(),
+        st_shake: shake::StateShake::new(),
+        shaken_at: None,
+        keyboard_checked: None,
+        device_profiles: Vec::new(),
+        startup_config: config.clone(),
+        startup_profile: None,
+        moving_device: None,
+        device_profile: None,
+        config,
+    }
+}
+
+// For device_profiles, each entry with its profile's options.
+// startup_profile is the --profile rtmouse runs, for the status.
+pub fn set_device_profiles(
+    st: &mut StateMainLoop,
+    startup_profile: Option<String>,
+    profiles: Vec<(config::DeviceProfile, DwellConfig)>,
+) {
+    st.startup_profile = startup_profile;
+    st.device_profiles = profiles;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_real_buttons_get_a_mask() {
+        assert_eq!(button_event_state(0, 1, true), 0);
+        assert_eq!(button_event_state(0, 1, false), xlib::Button1Mask);
+        assert_eq!(button_event_state(0, 5, false), xlib::Button5Mask);
+        assert_eq!(
+            button_event_state(xlib::ShiftMask, 3, false),
+            xlib::ShiftMask | xlib::Button3Mask
+        );
+        // Horizontal scrolling doesn't claim a keyboard group change
+        assert_eq!(button_event_state(0, 6, false), 0);
+        assert_eq!(button_event_state(0, 7, false), 0);
+    }
+}
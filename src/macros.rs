@@ -0,0 +1,247 @@
+// Recorded click macros, for the same few clicks over and over (a dialog that
+// keeps coming back) that are exhausting to dwell through every time.
+// `rtmouse-rs macro record NAME` notes every left, right and double click
+// until `macro stop`, and the macro is replayed by dwelling in one of the
+// config's macro_zones, a gesture set to "macro NAME", or `macro play NAME`.
+//
+// Each is a file in paths::macros_dir() named after it, one step per line:
+//
+//   ms since the step before, click type, x, y
+
+use crate::dwell::ClickType;
+use crate::paths;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub delay: Duration,
+    pub click: ClickType,
+    pub x: i32,
+    pub y: i32,
+}
+
+// Replays keep the recorded rhythm, but never rush an application and
+// never leave someone waiting on a pause while recording
+const MIN_GAP: Duration = Duration::from_millis(100);
+const MAX_GAP: Duration = Duration::from_secs(2);
+
+// Names end up as file names, so nothing that could leave the directory
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn to_text(steps: &[Step]) -> String {
+    steps
+        .iter()
+        .map(|s| {
+            format!(
+                "{} {} {} {}\n",
+                s.delay.as_millis(),
+                s.click.as_str(),
+                s.x,
+                s.y
+            )
+        })
+        .collect()
+}
+
+pub fn parse(text: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let step = match fields[..] {
+            [delay, click, x, y] => (|| {
+                Some(Step {
+                    delay: Duration::from_millis(delay.parse().ok()?),
+                    click: ClickType::parse(click).filter(|c| recordable(*c))?,
+                    x: x.parse().ok()?,
+                    y: y.parse().ok()?,
+                })
+            })(),
+            _ => None,
+        };
+        match step {
+            Some(step) => steps.push(step),
+            None => return Err(format!("line {}: expected `ms click x y`", i + 1)),
+        }
+    }
+    Ok(steps)
+}
+
+// Drags and gestures depend on what happens after the dwell, so there's no
+// single spot to replay them at
+pub fn recordable(click: ClickType) -> bool {
+    matches!(
+        click,
+        ClickType::Left | ClickType::Right | ClickType::Double
+    )
+}
+
+pub fn path(name: &str) -> Option<PathBuf> {
+    Some(paths::macros_dir()?.join(name))
+}
+
+fn no_home() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "neither XDG_STATE_HOME nor HOME is set",
+    )
+}
+
+pub fn save(name: &str, steps: &[Step]) -> io::Result<()> {
+    let path = path(name).ok_or_else(no_home)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, to_text(steps))
+}
+
+pub fn load(name: &str) -> io::Result<Vec<Step>> {
+    let text = fs::read_to_string(path(name).ok_or_else(no_home)?)?;
+    parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub struct Recording {
+    pub name: String,
+    pub steps: Vec<Step>,
+    // The last step, or when recording started
+    last: Instant,
+}
+
+pub fn start_recording(name: &str, now: Instant) -> Recording {
+    Recording {
+        name: name.to_string(),
+        steps: Vec::new(),
+        last: now,
+    }
+}
+
+pub fn record(r: &mut Recording, click: ClickType, x: i32, y: i32, now: Instant) {
+    if !recordable(click) {
+        return;
+    }
+    r.steps.push(Step {
+        delay: now.duration_since(r.last),
+        click,
+        x,
+        y,
+    });
+    r.last = now;
+}
+
+pub struct Replay {
+    pub name: String,
+    steps: Vec<Step>,
+    next: usize,
+    due: Instant,
+    // Where the pointer goes back to afterwards
+    pub home: (i32, i32),
+}
+
+pub fn replay(name: &str, steps: Vec<Step>, home: (i32, i32), now: Instant) -> Replay {
+    Replay {
+        name: name.to_string(),
+        steps,
+        next: 0,
+        due: now + MIN_GAP,
+        home,
+    }
+}
+
+// The step that's due by now, if any
+pub fn next_step(r: &mut Replay, now: Instant) -> Option<Step> {
+    let step = *r.steps.get(r.next)?;
+    if now < r.due {
+        return None;
+    }
+    r.next += 1;
+    if let Some(after) = r.steps.get(r.next) {
+        r.due = now + after.delay.clamp(MIN_GAP, MAX_GAP);
+    }
+    Some(step)
+}
+
+pub fn finished(r: &Replay) -> bool {
+    r.next >= r.steps.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_round_trip() {
+        let start = Instant::now();
+        let mut r = start_recording("dismiss", start);
+        record(
+            &mut r,
+            ClickType::Left,
+            10,
+            20,
+            start + Duration::from_millis(700),
+        );
+        record(
+            &mut r,
+            ClickType::Drag,
+            0,
+            0,
+            start + Duration::from_millis(900),
+        );
+        record(
+            &mut r,
+            ClickType::Double,
+            -5,
+            40,
+            start + Duration::from_millis(1500),
+        );
+        assert_eq!(r.steps.len(), 2);
+
+        let text = to_text(&r.steps);
+        assert_eq!(text, "700 left 10 20\n800 double -5 40\n");
+        assert_eq!(parse(&text).unwrap(), r.steps);
+        assert!(parse("700 drag 1 2\n").is_err());
+        assert!(parse("700 left 1\n").is_err());
+
+        assert!(valid_name("close-dialog_2"));
+        assert!(!valid_name("../config"));
+        assert!(!valid_name(""));
+    }
+
+    #[test]
+    fn replay_keeps_to_the_gaps() {
+        let step = |ms, x| Step {
+            delay: Duration::from_millis(ms),
+            click: ClickType::Left,
+            x,
+            y: 0,
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut r = replay(
+            "m",
+            vec![step(5000, 1), step(10, 2), step(10000, 3)],
+            (0, 0),
+            start,
+        );
+
+        assert_eq!(next_step(&mut r, at(50)), None);
+        assert_eq!(next_step(&mut r, at(100)).map(|s| s.x), Some(1));
+        // too quick gets slowed down, too slow sped up
+        assert_eq!(next_step(&mut r, at(150)), None);
+        assert_eq!(next_step(&mut r, at(200)).map(|s| s.x), Some(2));
+        assert_eq!(next_step(&mut r, at(2100)), None);
+        assert_eq!(next_step(&mut r, at(2200)).map(|s| s.x), Some(3));
+        assert!(finished(&r));
+        assert_eq!(next_step(&mut r, at(9000)), None);
+    }
+}
@@ -1,307 +1,430 @@
-extern crate x11;
-
-use std::cmp::max;
-use std::ffi::{CStr, CString};
-use std::fs;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::os::raw::{c_char, c_int, c_uint};
-use std::ptr::null;
-use std::sync::mpsc;
-use std::thread;
+use rtmouse_rs::cli::{self, Action};
+use rtmouse_rs::config;
+use rtmouse_rs::control::{self, Request};
+use rtmouse_rs::diagnose;
+use rtmouse_rs::doctor;
+use rtmouse_rs::heatmap;
+use rtmouse_rs::history;
+use rtmouse_rs::paths;
+use rtmouse_rs::power;
+use rtmouse_rs::setup;
+use rtmouse_rs::supervise;
+use rtmouse_rs::*;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::path::Path;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::sleep;
-use std::time::*;
 use std::time::{Duration, Instant};
-use x11::{xinput2, xlib, xtest};
-
-struct DwellConfig {
-    min_movement_pixels: u32,
-    dwell_time: u32,
-    drag_time: u32,
-    drag_enabled: bool,
-    sound_enabled: bool,
-    write_status_file: bool,
-    status_file: &'static str,
-}
-
-const TIMER_INTERVAL_MS: u32 = 100;
-
-// Default config, may make mutable later
-static CONFIG: DwellConfig = DwellConfig {
-    // Minimum movement before a mouse motion activates the dwell timer
-    min_movement_pixels: 10,
-
-    // rtmouse will wait this long after mouse movement ends before clicking.
-    // default 500ms. you may want to make it longer
-    dwell_time: 500 / TIMER_INTERVAL_MS,
-
-    // rtmouse will drag-click if you move the mouse within this timeframe
-    // after a click occurs.
-    drag_time: 500 / TIMER_INTERVAL_MS,
 
-    // dragging only happens when this is on
-    drag_enabled: true,
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
-    // sound plays on click when this is on
-    sound_enabled: true,
-
-    // status_file will be modified with enabled/disabled/terminated statuses
-    // when this is on
-    write_status_file: true,
-
-    status_file: "/tmp/rtmouse-status.txt",
-};
-
-struct StateActive {
-    active: bool,
-    just_became_active: bool,
+// The file --config names, or the one rtmouse would read by default
+fn config_file(opts: &cli::Options) -> std::path::PathBuf {
+    match opts.config_path.clone().or_else(config::default_path) {
+        Some(path) => path,
+        None => {
+            eprintln!("Error: neither XDG_CONFIG_HOME nor HOME is set, pass --config");
+            exit(1);
+        }
+    }
 }
 
-struct StateX11 {
-    display: *mut xlib::Display,
-    xi_extension_opcode: i32,
+fn init_config(opts: &cli::Options, force: bool) {
+    let path = config_file(opts);
+    match config::write_template(&path, force) {
+        Ok(()) => println!("wrote {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            eprintln!(
+                "Error: {} already exists, pass --force to overwrite it",
+                path.display()
+            );
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
 }
 
-struct StateIsCursorMoving {
-    old_x: i32,
-    old_y: i32,
-    moving: bool,
-}
+fn setup(opts: &cli::Options, force: bool) {
+    let path = config_file(opts);
+    // Before the questions, not after them
+    if path.exists() && !force {
+        eprintln!(
+            "Error: {} already exists, pass --force to overwrite it",
+            path.display()
+        );
+        exit(1);
+    }
 
-struct StateMainLoop {
-    we_are_dragging_mouse: bool,
-    idle_timer: u32,
-    st_active: StateActive,
-    st_x11: StateX11,
-    st_is_click_inhibited: StateIsClickInhibited,
-    st_is_cursor_moving: StateIsCursorMoving,
+    let measure: &dyn Fn() -> Option<u32> = &measure_wander;
+    let calibrate = std::env::var_os("DISPLAY").map(|_| measure);
+    let answers = setup::run(
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+        calibrate,
+        setup::read_mousetweaks().as_ref(),
+    );
+    let written =
+        answers.and_then(|config| config::write_config(&path, &setup::config_text(&config), force));
+    match written {
+        Ok(()) => println!("wrote {}", path.display()),
+        Err(e) => {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
 }
 
-fn play_click_sound() {}
-
-// via XI2.h: #define XIMaskLen(event) (((event) >> 3) + 1)
-fn XIMaskLen(event: i32) -> i32 {
-    (event >> 3) + 1
+fn print_config(opts: &cli::Options, config: &config::DwellConfig) {
+    let source = opts
+        .config_path
+        .clone()
+        .or_else(config::default_path)
+        .filter(|p| p.exists());
+    match (source, &opts.profile) {
+        (Some(path), Some(name)) => println!(
+            "# defaults merged with {}, profile {}",
+            path.display(),
+            name
+        ),
+        (Some(path), None) => println!("# defaults merged with {}", path.display()),
+        (None, _) => println!("# defaults, no config file found"),
+    }
+    print!("{}", config::to_toml(config, false));
 }
 
-fn initialize_x11_state(st_x11: &mut StateX11) {
-    let display = unsafe { xlib::XOpenDisplay(null()) };
-    if display.is_null() {
-        panic!("Error: Failed to open default display");
+fn send(opts: &cli::Options, request: Request) -> Vec<(String, String)> {
+    // Only for control_socket, so a config the daemon would refuse doesn't
+    // stop `rtmouse-rs disable`
+    let socket = config::load_from(opts.config_path.as_deref(), opts.profile.as_deref())
+        .unwrap_or_default()
+        .control_socket_path();
+    match control::send_to(&socket, request) {
+        Ok(fields) => fields,
+        Err(e) => {
+            use std::io::ErrorKind::{ConnectionRefused, NotFound};
+            match e.kind() {
+                NotFound | ConnectionRefused => eprintln!(
+                    "Error: rtmouse isn't running, nothing is listening on {}",
+                    socket.display()
+                ),
+                _ => eprintln!("Error: can't reach the running rtmouse: {}", e),
+            }
+            exit(1);
+        }
     }
+}
 
-    let mut opcode = 0;
-    let mut evt = 0;
-    let mut err = 0;
-    unsafe {
-        let ext = CString::new("XInputExtension").unwrap();
-        if xlib::XQueryExtension(display, ext.as_ptr(), &mut opcode, &mut evt, &mut err) == 0 {
-            panic!("Error: initialize_x11_state: could not query XInputExtension.");
+fn status(opts: &cli::Options) {
+    let fields = send(opts, Request::Status);
+    if opts.json {
+        println!("{}", control::to_json(&fields));
+    } else {
+        for (key, value) in &fields {
+            println!("{:9} {}", format!("{}:", key), value);
         }
     }
+}
 
-    st_x11.display = display;
-    st_x11.xi_extension_opcode = opcode;
-
-    let root = unsafe { xlib::XDefaultRootWindow(display) };
-
-    let mask_len = XIMaskLen(xinput2::XI_LASTEVENT);
-    let mut mask_buf = vec![0u8; mask_len as usize];
-    let mut m = xinput2::XIEventMask {
-        deviceid: xinput2::XIAllDevices,
-        mask_len,
-        mask: mask_buf.as_mut_ptr(),
+fn read_history(config: &config::DwellConfig, since: Option<Duration>) -> Vec<history::Record> {
+    let mut records = match history::read() {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Error: could not read the history log: {}", e);
+            exit(1);
+        }
     };
-    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_RawButtonPress);
-    xinput2::XISetMask(&mut mask_buf[..], xinput2::XI_RawButtonRelease);
-
-    unsafe {
-        xinput2::XISelectEvents(display, root, &mut m, 1);
-        xlib::XSync(display, 0);
+    if let Some(since) = since {
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(since)
+            .unwrap_or(std::time::UNIX_EPOCH);
+        records.retain(|r| r.time >= cutoff);
     }
+    if records.is_empty() && !config.history_log {
+        eprintln!("note: history_log is off in the config, so nothing new is being recorded");
+    }
+    records
 }
 
-struct StateIsClickInhibited {
-    inhibit_mask: u64,
-    uninhibit_mask: u64,
-}
-
-fn is_click_inhibited(st: &mut StateIsClickInhibited, st_x11: &StateX11) -> bool {
-    st.inhibit_mask &= !st.uninhibit_mask;
-    st.uninhibit_mask = 0;
+fn write_heatmap(config: &config::DwellConfig, since: Option<Duration>) {
+    use std::io::{IsTerminal, Write};
+    if std::io::stdout().is_terminal() {
+        eprintln!("Error: heatmap writes a PNG, redirect it to a file");
+        exit(1);
+    }
 
-    let display = st_x11.display;
+    let records = read_history(config, since);
+    // Without a display, just big enough for every event
+    let (width, height) = screen_size().unwrap_or_else(|| {
+        let max = |f: fn(&history::Record) -> i32| {
+            records.iter().map(f).max().unwrap_or(0).clamp(0, 16383) as u32 + 1
+        };
+        (max(|r| r.x), max(|r| r.y))
+    });
+    let rgb = heatmap::render(&records, width, height);
+    if let Err(e) = std::io::stdout().write_all(&heatmap::png(width, height, &rgb)) {
+        eprintln!("Error: could not write the heatmap: {}", e);
+        exit(1);
+    }
+}
 
-    unsafe {
-        while xlib::XPending(display) > 0 {
-            let mut ev = std::mem::MaybeUninit::uninit();
-            xlib::XNextEvent(display, ev.as_mut_ptr());
-            let ev = ev.assume_init();
-            let mut cookie = ev.generic_event_cookie;
+fn export_profile(opts: &cli::Options, name: &str) {
+    match config::load_from(opts.config_path.as_deref(), Some(name)) {
+        Ok(config) => print!("{}", config::profile_to_toml(&config, name)),
+        Err(e) => {
+            eprint!("{}", e);
+            exit(1);
+        }
+    }
+}
 
-            if xlib::XGetEventData(display, &mut cookie) != 0
-                && cookie.type_ == xlib::GenericEvent
-                && cookie.extension == st_x11.xi_extension_opcode
-            {
-                let data: *mut xinput2::XIRawEvent = cookie.data.cast();
+fn import_profile(opts: &cli::Options, file: &Path, force: bool) {
+    let fail = |what: &Path, e: &dyn std::fmt::Display| -> ! {
+        eprintln!("Error: {}: {}", what.display(), e);
+        exit(1);
+    };
+    let profile = std::fs::read_to_string(file).unwrap_or_else(|e| fail(file, &e));
+    let path = config_file(opts);
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => fail(&path, &e),
+    };
 
-                match cookie.evtype {
-                    xinput2::XI_RawButtonPress => {
-                        st.inhibit_mask |= 1 << (*data).detail;
-                    }
-                    xinput2::XI_RawButtonRelease => {
-                        st.uninhibit_mask |= 1 << (*data).detail;
-                    }
-                    _ => {}
+    let (text, name) = match config::import_profile(&existing, &profile, force) {
+        Ok(imported) => imported,
+        Err(errors) => {
+            eprint!(
+                "{}",
+                config::ConfigErrors {
+                    path: file.to_path_buf(),
+                    errors,
                 }
-            }
+            );
+            exit(1);
         }
+    };
+    let written = match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+    .and_then(|_| std::fs::write(&path, text));
+    match written {
+        Ok(()) => println!(
+            "added profile {} to {}, run it with --profile {}",
+            name,
+            path.display(),
+            name
+        ),
+        Err(e) => fail(&path, &e),
     }
-
-    st.inhibit_mask != 0
 }
 
-fn is_cursor_moving(st: &mut StateIsCursorMoving, st_x11: &StateX11) -> bool {
-    let display = st_x11.display;
-
-    let mut root_x = 0;
-    let mut root_y = 0;
-    let mut root_win = unsafe { xlib::XDefaultRootWindow(display) };
-
-    let mut child_x = 0;
-    let mut child_y = 0;
-    let mut child_win = std::mem::MaybeUninit::uninit();
-
-    let mut button_mask = 0;
+// Every device_profiles entry with its profile's options, up front so a
+// missing or broken profile stops rtmouse now rather than when the device
+// first moves
+fn load_device_profiles(
+    opts: &cli::Options,
+    config: &config::DwellConfig,
+) -> Vec<(config::DeviceProfile, config::DwellConfig)> {
+    let load = |d: &config::DeviceProfile| match config::load_from(
+        opts.config_path.as_deref(),
+        Some(&d.profile),
+    ) {
+        Ok(profile) => (d.clone(), profile),
+        Err(e) => {
+            eprint!("{}", e);
+            exit(1);
+        }
+    };
+    config.device_profiles.iter().map(load).collect()
+}
 
-    unsafe {
-        xlib::XQueryPointer(
-            display,
-            root_win,
-            &mut root_win,
-            child_win.as_mut_ptr(),
-            &mut root_x,
-            &mut root_y,
-            &mut child_x,
-            &mut child_y,
-            &mut button_mask,
-        );
+// Left behind when rtmouse is killed, like the status file, so check the
+// process is alive before trusting it
+fn write_pid_file(config: &config::DwellConfig) {
+    let path = config.pid_path();
+    let written = paths::ensure_dir_for(&path)
+        .and_then(|_| std::fs::write(&path, format!("{}\n", std::process::id())));
+    if let Err(e) = written {
+        eprintln!("Error: could not write {}: {}", path.display(), e);
     }
+}
 
-    let dx = root_x - st.old_x;
-    let dy = root_y - st.old_y;
-
-    let movement_threshold = if st.moving {
-        1
-    } else {
-        CONFIG.min_movement_pixels
+fn main() {
+    let opts = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, cli::usage());
+            exit(2);
+        }
     };
 
-    st.moving = (dx * dx + dy * dy) as u32 > movement_threshold * movement_threshold;
-
-    if st.moving {
-        st.old_x = root_x;
-        st.old_y = root_y;
+    if let Some(dir) = &opts.runtime_dir {
+        paths::set_runtime_dir(dir.clone());
     }
 
-    st.moving
-}
-
-fn get_primary_button_code(st_x11: &StateX11) -> u8 {
-    let mut primary_button = 0;
-    if unsafe { xlib::XGetPointerMapping(st_x11.display, &mut primary_button, 1) } < 1 {
-        primary_button = 1
+    match opts.action {
+        Action::Run => {
+            use std::io::IsTerminal;
+            let first_run = opts.config_path.is_none()
+                && opts.profile.is_none()
+                && config::default_path().is_some_and(|p| !p.exists());
+            if first_run && std::io::stdin().is_terminal() {
+                println!("there's no config yet, so a few questions first (ctrl-c to stop)\n");
+                setup(&opts, false);
+            }
+            if opts.supervise {
+                let args = std::env::args_os().skip(1).filter(|a| a != "--supervise");
+                supervise::run(args.collect());
+            }
+        }
+        Action::Help => {
+            print!("{}", cli::usage());
+            return;
+        }
+        Action::InitConfig { force } => {
+            init_config(&opts, force);
+            return;
+        }
+        Action::Setup { force } => {
+            setup(&opts, force);
+            return;
+        }
+        Action::Doctor => {
+            let checks = doctor::checks(opts.config_path.as_deref(), opts.profile.as_deref());
+            print!("{}", doctor::report(&checks));
+            if checks.iter().any(|c| !c.ok) {
+                exit(1);
+            }
+            return;
+        }
+        Action::Diagnose => {
+            match diagnose::run(opts.config_path.as_deref(), opts.profile.as_deref()) {
+                Ok(path) => println!(
+                    "wrote {}. look it over, then attach it to your bug report",
+                    path.display()
+                ),
+                Err(e) => {
+                    eprintln!("Error: could not write the diagnostics: {}", e);
+                    exit(1);
+                }
+            }
+            return;
+        }
+        Action::Status => {
+            status(&opts);
+            return;
+        }
+        Action::Send(ref request) => {
+            let fields = send(&opts, request.clone());
+            let key = match request {
+                Request::ClickType(_) => "next_click",
+                Request::Theme(_) => "theme",
+                Request::SoundTheme(_) => "sound_theme",
+                Request::ResetClicks => "clicks_today",
+                Request::RecordMacro(_) | Request::StopMacro | Request::PlayMacro(_) => "macro",
+                _ => "state",
+            };
+            if let Some((_, value)) = fields.iter().find(|(k, _)| k == key) {
+                println!("{}", value);
+            }
+            return;
+        }
+        Action::Completions(shell) => {
+            print!("{}", cli::completions(shell));
+            return;
+        }
+        Action::ExportProfile { ref name } => {
+            export_profile(&opts, name);
+            return;
+        }
+        Action::ImportProfile { ref path, force } => {
+            import_profile(&opts, path, force);
+            return;
+        }
+        Action::PrintConfig | Action::Export { .. } | Action::Heatmap { .. } => {}
     }
-    primary_button
-}
 
-fn send_button_event(st_x11: &StateX11, btn: u8, state: bool, delay: u32) {
-    unsafe {
-        xtest::XTestFakeButtonEvent(st_x11.display, btn.into(), state.into(), delay.into());
-    }
-}
+    let config = match config::load_from(opts.config_path.as_deref(), opts.profile.as_deref()) {
+        Ok(c) => c,
+        Err(e) => {
+            eprint!("{}", e);
+            exit(1);
+        }
+    };
 
-fn main_loop(st: &mut StateMainLoop) {
-    if !st.st_active.active {
+    if opts.action == Action::PrintConfig {
+        print_config(&opts, &config);
         return;
     }
-
-    let max_time = max(CONFIG.dwell_time, CONFIG.drag_time) + 1;
-
-    if is_cursor_moving(&mut st.st_is_cursor_moving, &st.st_x11) {
-        if st.st_active.just_became_active {
-            st.st_active.just_became_active = false;
-            st.idle_timer = max_time + 1;
-        } else {
-            st.idle_timer = 0;
+    match opts.action {
+        Action::Export { since } => {
+            print!("{}", history::to_csv(&read_history(&config, since)));
+            return;
         }
-        return;
+        Action::Heatmap { since } => {
+            write_heatmap(&config, since);
+            return;
+        }
+        _ => {}
     }
 
-    if st.idle_timer < max_time {
-        st.idle_timer += 1;
+    let device_profiles = load_device_profiles(&opts, &config);
+    let mut st = initialize_main_loop_state(config);
+    set_device_profiles(&mut st, opts.profile.clone(), device_profiles);
+    if opts.explain {
+        enable_explain(&mut st.st_explain, &st.config.log_path());
     }
 
-    if is_click_inhibited(&mut st.st_is_click_inhibited, &st.st_x11) {
-        if !CONFIG.drag_enabled || !st.we_are_dragging_mouse {
-            st.idle_timer = max_time;
+    match control::listen(&st.config.control_socket_path()) {
+        Ok(listener) => st.control = Some(listener),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            eprintln!("Error: {}", e);
+            exit(1);
         }
+        Err(e) => eprintln!("Error: no control socket, status won't work: {}", e),
     }
-
-    if st.idle_timer == CONFIG.dwell_time && !st.we_are_dragging_mouse {
-        let primary_button = get_primary_button_code(&st.st_x11);
-        if CONFIG.drag_enabled {
-            send_button_event(&st.st_x11, primary_button, true, 0);
-
-            st.we_are_dragging_mouse = true;
-            st.idle_timer = 0;
-        } else {
-            send_button_event(&st.st_x11, primary_button, true, 0);
-            send_button_event(&st.st_x11, primary_button, false, 0);
-
-            st.idle_timer = max_time;
+    write_pid_file(&st.config);
+
+    initialize_x11_state(&mut st.st_x11, &st.config);
+    initialize_held_buttons(&mut st);
+    restore_pointer_speed(&mut st);
+    warn_if_xwayland(&st.st_x11);
+    initialize_overlays(&mut st);
+    initialize_leds(&mut st);
+    initialize_panels(&mut st);
+
+    let cmds = st.dwell.set_active(!st.config.disabled);
+    run_commands(&mut st, &cmds);
+
+    // Checked every tick, so a drag isn't left held down behind us
+    let stop = Arc::new(AtomicBool::new(false));
+    for &signal in &[SIGHUP, SIGINT, SIGTERM] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&stop)) {
+            eprintln!("Error: can't catch signal {}: {}", signal, e);
         }
-        play_click_sound();
-    }
-
-    if st.idle_timer == CONFIG.drag_time && st.we_are_dragging_mouse {
-        let primary_button = get_primary_button_code(&st.st_x11);
-        send_button_event(&st.st_x11, primary_button, false, 0);
-
-        st.we_are_dragging_mouse = false;
-        st.idle_timer = max_time;
     }
-}
-
-fn main() {
-    let mut st = StateMainLoop {
-        idle_timer: 0,
-        we_are_dragging_mouse: false,
-        st_active: StateActive {
-            active: true,
-            just_became_active: true,
-        },
-        st_is_click_inhibited: StateIsClickInhibited {
-            inhibit_mask: 0,
-            uninhibit_mask: 0,
-        },
-        st_is_cursor_moving: StateIsCursorMoving {
-            old_x: 0,
-            old_y: 0,
-            moving: false,
-        },
-        st_x11: StateX11 {
-            display: std::ptr::null_mut(),
-            xi_extension_opcode: 0,
-        },
-    };
-
-    initialize_x11_state(&mut st.st_x11);
 
     let mut next_tick = Instant::now();
-    let tick_duration = Duration::from_millis(TIMER_INTERVAL_MS as u64);
+    let mut tick_duration = Duration::from_millis(TIMER_INTERVAL_MS as u64);
+    let mut power_checked: Option<Instant> = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        // Plugging in or out doesn't need noticing right away
+        if power_checked.is_none_or(|t| t.elapsed() >= POWER_CHECK_INTERVAL) {
+            tick_duration = if power::on_battery() {
+                st.config.battery_tick_interval
+            } else {
+                Duration::from_millis(TIMER_INTERVAL_MS as u64)
+            };
+            power_checked = Some(Instant::now());
+        }
 
-    loop {
         main_loop(&mut st);
         let now = Instant::now();
         while next_tick <= now {
@@ -309,4 +432,5 @@ fn main() {
         }
         sleep(next_tick - now);
     }
+    shutdown(&mut st);
 }
@@ -0,0 +1,184 @@
+// Opt-in alerts for a carer who isn't in the room. monitor_command runs
+// through sh -c when dwell clicking is turned on or off, when the stuck drag
+// watchdog has to step in, when clicks stop being delivered, and when nothing
+// has been clicked for monitor_no_clicks_after, with RTMOUSE_EVENT and
+// RTMOUSE_DETAIL set. Whether that goes on to curl, mail or a pager is up to
+// the command.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alert {
+    Enabled,
+    Disabled,
+    StuckDrag,
+    NoClicks(Duration),
+    // The first click after a NoClicks
+    ClickingAgain,
+    // The X server refused a click, even after setting XTest up again
+    NotDelivering,
+}
+
+impl Alert {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Alert::Enabled => "enabled",
+            Alert::Disabled => "disabled",
+            Alert::StuckDrag => "stuck-drag",
+            Alert::NoClicks(_) => "no-clicks",
+            Alert::ClickingAgain => "clicking-again",
+            Alert::NotDelivering => "not-delivering",
+        }
+    }
+
+    pub fn detail(self) -> String {
+        match self {
+            Alert::Enabled => "rtmouse dwell clicking was turned on".to_string(),
+            Alert::Disabled => "rtmouse dwell clicking was turned off".to_string(),
+            Alert::StuckDrag => {
+                "rtmouse had to let go of a mouse button that was held down too long".to_string()
+            }
+            Alert::NoClicks(after) => format!(
+                "rtmouse hasn't clicked anything in {} minutes",
+                after.as_secs() / 60
+            ),
+            Alert::ClickingAgain => "rtmouse is clicking again".to_string(),
+            Alert::NotDelivering => {
+                "rtmouse's clicks are not being delivered to the desktop".to_string()
+            }
+        }
+    }
+}
+
+pub struct Monitor {
+    // Whether dwell clicking was on at the last update, None before the first
+    enabled: Option<bool>,
+    // Today's clicks at the last update
+    clicks: u64,
+    // Since the last click, or since clicking last could have started
+    quiet_since: Instant,
+    no_clicks_sent: bool,
+}
+
+impl Monitor {
+    pub fn new(now: Instant) -> Monitor {
+        Monitor {
+            enabled: None,
+            clicks: 0,
+            quiet_since: now,
+            no_clicks_sent: false,
+        }
+    }
+}
+
+// What's worth telling the carer since the last update. clicks is today's
+// count, so it only ever goes up except at midnight.
+pub fn update(
+    m: &mut Monitor,
+    enabled: bool,
+    asleep: bool,
+    clicks: u64,
+    now: Instant,
+    no_clicks_after: Option<Duration>,
+) -> Vec<Alert> {
+    let mut out = Vec::new();
+    if m.enabled != Some(enabled) {
+        out.push(if enabled {
+            Alert::Enabled
+        } else {
+            Alert::Disabled
+        });
+        m.enabled = Some(enabled);
+        m.quiet_since = now;
+        m.no_clicks_sent = false;
+    }
+
+    if clicks > m.clicks {
+        m.quiet_since = now;
+        if m.no_clicks_sent {
+            out.push(Alert::ClickingAgain);
+            m.no_clicks_sent = false;
+        }
+    }
+    m.clicks = clicks;
+
+    // Asleep is the user being away rather than rtmouse not working
+    if !enabled || asleep {
+        m.quiet_since = now;
+    } else if let Some(after) = no_clicks_after {
+        if !m.no_clicks_sent && now.duration_since(m.quiet_since) >= after {
+            out.push(Alert::NoClicks(after));
+            m.no_clicks_sent = true;
+        }
+    }
+    out
+}
+
+// Runs command for alert without waiting for it. Empty means monitoring is
+// off.
+pub fn send(command: &str, alert: Alert) {
+    if command.is_empty() {
+        return;
+    }
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RTMOUSE_EVENT", alert.as_str())
+        .env("RTMOUSE_DETAIL", alert.detail())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn();
+    match child {
+        // Reaped on a thread of its own, a slow mail server mustn't hold up
+        // the tick loop
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => eprintln!("Error: could not run monitor_command: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_on_changes_and_long_quiet() {
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        let after = Some(Duration::from_secs(600));
+        let mut m = Monitor::new(start);
+
+        assert_eq!(
+            update(&mut m, true, false, 5, at(0), after),
+            vec![Alert::Enabled]
+        );
+        assert_eq!(update(&mut m, true, false, 6, at(100), after), vec![]);
+        assert_eq!(
+            update(&mut m, true, false, 6, at(700), after),
+            vec![Alert::NoClicks(Duration::from_secs(600))]
+        );
+        // Only once
+        assert_eq!(update(&mut m, true, false, 6, at(1400), after), vec![]);
+        assert_eq!(
+            update(&mut m, true, false, 7, at(1500), after),
+            vec![Alert::ClickingAgain]
+        );
+
+        // Sleeping and being off don't count as quiet
+        assert_eq!(update(&mut m, true, true, 7, at(3000), after), vec![]);
+        assert_eq!(update(&mut m, true, false, 7, at(3100), after), vec![]);
+        assert_eq!(
+            update(&mut m, false, false, 7, at(3200), after),
+            vec![Alert::Disabled]
+        );
+        assert_eq!(update(&mut m, false, false, 7, at(5000), after), vec![]);
+        // Midnight isn't a click
+        assert_eq!(
+            update(&mut m, true, false, 0, at(5100), after),
+            vec![Alert::Enabled]
+        );
+        assert_eq!(update(&mut m, true, false, 0, at(5200), None), vec![]);
+    }
+}
@@ -0,0 +1,447 @@
+// Overlay windows for visual feedback. They're override-redirect so the
+// window manager leaves them alone, and get an empty input region so the
+// pointer, and the clicks we synthesize, go straight through them to the
+// window underneath. An overlay that ate our own click would be worse than
+// no overlay at all.
+//
+// With a compositor running they use a 32 bit ARGB visual, so anything not
+// drawn is see-through. Without one there's no alpha, so the window's
+// bounding shape is cut down to exactly what's drawn and the rest of the
+// rectangle doesn't exist.
+
+use crate::config::{Countdown, DwellConfig, Theme};
+use crate::geometry;
+use crate::StateX11;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_ulong};
+use std::time::Duration;
+use x11::xlib;
+
+// XFixes isn't covered by the x11 crate
+type XserverRegion = c_ulong;
+
+#[link(name = "Xfixes")]
+extern "C" {
+    fn XFixesCreateRegion(
+        display: *mut xlib::Display,
+        rectangles: *mut xlib::XRectangle,
+        nrectangles: c_int,
+    ) -> XserverRegion;
+    fn XFixesDestroyRegion(display: *mut xlib::Display, region: XserverRegion);
+    fn XFixesSetWindowShapeRegion(
+        display: *mut xlib::Display,
+        window: xlib::Window,
+        shape_kind: c_int,
+        x_off: c_int,
+        y_off: c_int,
+        region: XserverRegion,
+    );
+}
+
+// via X11/extensions/shape.h
+const SHAPE_BOUNDING: c_int = 0;
+const SHAPE_INPUT: c_int = 2;
+
+pub struct Overlay {
+    pub window: xlib::Window,
+    pub width: u32,
+    pub height: u32,
+    mapped: bool,
+    argb: bool,
+    gc: xlib::GC,
+    // The core "fixed" font, null if the server doesn't have it
+    font: *mut xlib::XFontStruct,
+}
+
+// Colors are 0xAARRGGBB. Alpha only counts with a compositor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Circle {
+        x: i32,
+        y: i32,
+        r: i32,
+        color: u32,
+    },
+    Rect {
+        x: i32,
+        y: i32,
+        w: u32,
+        h: u32,
+        color: u32,
+    },
+    // (x, y) is the top left corner of the text. Without a compositor it
+    // gets a black box behind it.
+    Text {
+        x: i32,
+        y: i32,
+        text: String,
+        color: u32,
+    },
+}
+
+// How every overlay looks, from the config or the high contrast theme.
+// outline is a black border around everything drawn, in pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Style {
+    pub dot_radius: u32,
+    pub dot_color: u32,
+    pub bar_width: u32,
+    pub bar_height: u32,
+    pub bar_color: u32,
+    pub countdown_color: u32,
+    pub countdown_font: &'static str,
+    pub outline: u32,
+}
+
+pub const OUTLINE_COLOR: u32 = 0xff000000;
+
+pub fn style(config: &DwellConfig, theme: Theme) -> Style {
+    match theme {
+        Theme::Default => Style {
+            dot_radius: config.anchor_dot_radius,
+            dot_color: config.anchor_dot_color,
+            bar_width: config.drag_bar_width,
+            bar_height: config.drag_bar_height,
+            bar_color: config.drag_bar_color,
+            countdown_color: config.countdown_color,
+            countdown_font: "fixed",
+            outline: 0,
+        },
+        Theme::HighContrast => Style {
+            dot_radius: config.anchor_dot_radius.max(8),
+            dot_color: 0xffffff00,
+            bar_width: config.drag_bar_width.max(100),
+            bar_height: config.drag_bar_height.max(14),
+            bar_color: 0xffffff00,
+            countdown_color: 0xffffff00,
+            countdown_font: "10x20",
+            outline: 3,
+        },
+    }
+}
+
+// A compositor owns the _NET_WM_CM_Sn selection for its screen
+pub fn compositor_running(st_x11: &StateX11) -> bool {
+    unsafe {
+        let screen = xlib::XDefaultScreen(st_x11.display);
+        let name = CString::new(format!("_NET_WM_CM_S{}", screen)).unwrap();
+        let atom = xlib::XInternAtom(st_x11.display, name.as_ptr(), xlib::False);
+        xlib::XGetSelectionOwner(st_x11.display, atom) != 0
+    }
+}
+
+fn argb_visual(st_x11: &StateX11) -> Option<xlib::XVisualInfo> {
+    unsafe {
+        let mut info = std::mem::zeroed();
+        let screen = xlib::XDefaultScreen(st_x11.display);
+        if xlib::XMatchVisualInfo(st_x11.display, screen, 32, xlib::TrueColor, &mut info) != 0 {
+            Some(info)
+        } else {
+            None
+        }
+    }
+}
+
+// One rectangle per row of the circle, for bounding shapes
+pub fn circle_rects(x: i32, y: i32, r: i32) -> Vec<xlib::XRectangle> {
+    (-r..=r)
+        .map(|dy| {
+            let half = (((r * r - dy * dy) as f64).sqrt()).round() as i32;
+            xlib::XRectangle {
+                x: (x - half) as i16,
+                y: (y + dy) as i16,
+                width: (2 * half + 1) as u16,
+                height: 1,
+            }
+        })
+        .collect()
+}
+
+// The pixel value for a color. ARGB visuals want premultiplied alpha; for
+// everything else assume a TrueColor 24 bit visual, which is all anyone
+// runs these days.
+fn pixel(argb: bool, color: u32) -> c_ulong {
+    if !argb {
+        return (color & 0xffffff) as c_ulong;
+    }
+    let a = color >> 24;
+    let premultiply = |shift: u32| (((color >> shift) & 0xff) * a / 255) << shift;
+    ((a << 24) | premultiply(16) | premultiply(8) | premultiply(0)) as c_ulong
+}
+
+// Empties the window's input region. Everything it covers keeps getting
+// the pointer, and XQueryPointer never reports an overlay as the window
+// under it, so dwell clicks can't land on one of ours.
+fn make_click_through(st_x11: &StateX11, window: xlib::Window) {
+    unsafe {
+        let region = XFixesCreateRegion(st_x11.display, std::ptr::null_mut(), 0);
+        XFixesSetWindowShapeRegion(st_x11.display, window, SHAPE_INPUT, 0, 0, region);
+        XFixesDestroyRegion(st_x11.display, region);
+    }
+}
+
+pub fn create(st_x11: &StateX11, width: u32, height: u32) -> Overlay {
+    let display = st_x11.display;
+    let visual = if compositor_running(st_x11) {
+        argb_visual(st_x11)
+    } else {
+        None
+    };
+
+    let (window, gc) = unsafe {
+        let mut attrs: xlib::XSetWindowAttributes = std::mem::zeroed();
+        attrs.override_redirect = xlib::True;
+        attrs.background_pixel = 0;
+        attrs.border_pixel = 0;
+        let mut mask = xlib::CWOverrideRedirect | xlib::CWBackPixel | xlib::CWBorderPixel;
+
+        // A 32 bit visual differs from the root's, so it needs its own
+        // colormap or XCreateWindow fails with BadMatch
+        let (depth, visual_ptr) = match &visual {
+            Some(info) => {
+                attrs.colormap =
+                    xlib::XCreateColormap(display, st_x11.root, info.visual, xlib::AllocNone);
+                mask |= xlib::CWColormap;
+                (info.depth, info.visual)
+            }
+            None => (xlib::CopyFromParent, std::ptr::null_mut()),
+        };
+
+        let window = xlib::XCreateWindow(
+            display,
+            st_x11.root,
+            0,
+            0,
+            width,
+            height,
+            0,
+            depth,
+            xlib::InputOutput as u32,
+            visual_ptr,
+            mask,
+            &mut attrs,
+        );
+        (
+            window,
+            xlib::XCreateGC(display, window, 0, std::ptr::null_mut()),
+        )
+    };
+    make_click_through(st_x11, window);
+
+    let font = unsafe {
+        let name = CString::new("fixed").unwrap();
+        let font = xlib::XLoadQueryFont(display, name.as_ptr());
+        if !font.is_null() {
+            xlib::XSetFont(display, gc, (*font).fid);
+        }
+        font
+    };
+
+    Overlay {
+        window,
+        width,
+        height,
+        mapped: false,
+        argb: visual.is_some(),
+        gc,
+        font,
+    }
+}
+
+// Switches the overlay to another core font, keeping the current one if
+// the server doesn't have it.
+pub fn set_font(st_x11: &StateX11, overlay: &mut Overlay, name: &str) -> bool {
+    let name = CString::new(name).unwrap();
+    unsafe {
+        let font = xlib::XLoadQueryFont(st_x11.display, name.as_ptr());
+        if font.is_null() {
+            return false;
+        }
+        if !overlay.font.is_null() {
+            xlib::XFreeFont(st_x11.display, overlay.font);
+        }
+        xlib::XSetFont(st_x11.display, overlay.gc, (*font).fid);
+        overlay.font = font;
+    }
+    true
+}
+
+pub fn resize(st_x11: &StateX11, overlay: &mut Overlay, width: u32, height: u32) {
+    unsafe {
+        xlib::XResizeWindow(st_x11.display, overlay.window, width, height);
+    }
+    overlay.width = width;
+    overlay.height = height;
+}
+
+// Width and height of text in the overlay's font
+pub fn text_size(overlay: &Overlay, text: &str) -> (u32, u32) {
+    if overlay.font.is_null() {
+        return (0, 0);
+    }
+    unsafe {
+        let font = &*overlay.font;
+        let width = xlib::XTextWidth(overlay.font, text.as_ptr().cast(), text.len() as c_int);
+        (width as u32, (font.ascent + font.descent) as u32)
+    }
+}
+
+// Replaces the overlay's contents. Coordinates are inside the overlay.
+pub fn draw(st_x11: &StateX11, overlay: &Overlay, shapes: &[Shape]) {
+    let display = st_x11.display;
+
+    unsafe {
+        if overlay.argb {
+            xlib::XClearWindow(display, overlay.window);
+        } else {
+            // Only what's drawn is left of the window
+            let mut rects = Vec::new();
+            for shape in shapes {
+                let (x, y, w, h) = match shape {
+                    Shape::Circle { x, y, r, .. } => {
+                        rects.extend(circle_rects(*x, *y, *r));
+                        continue;
+                    }
+                    Shape::Rect { x, y, w, h, .. } => (*x, *y, *w, *h),
+                    Shape::Text { x, y, text, .. } => {
+                        let (w, h) = text_size(overlay, text);
+                        (*x, *y, w, h)
+                    }
+                };
+                rects.push(xlib::XRectangle {
+                    x: x as i16,
+                    y: y as i16,
+                    width: w as u16,
+                    height: h as u16,
+                });
+            }
+            let region = XFixesCreateRegion(display, rects.as_mut_ptr(), rects.len() as c_int);
+            XFixesSetWindowShapeRegion(display, overlay.window, SHAPE_BOUNDING, 0, 0, region);
+            XFixesDestroyRegion(display, region);
+        }
+
+        for shape in shapes {
+            match shape.clone() {
+                Shape::Circle { x, y, r, color } => {
+                    xlib::XSetForeground(display, overlay.gc, pixel(overlay.argb, color));
+                    let d = (2 * r + 1) as u32;
+                    xlib::XFillArc(
+                        display,
+                        overlay.window,
+                        overlay.gc,
+                        x - r,
+                        y - r,
+                        d,
+                        d,
+                        0,
+                        360 * 64,
+                    );
+                }
+                Shape::Rect { x, y, w, h, color } => {
+                    xlib::XSetForeground(display, overlay.gc, pixel(overlay.argb, color));
+                    xlib::XFillRectangle(display, overlay.window, overlay.gc, x, y, w, h);
+                }
+                Shape::Text { x, y, text, color } if !overlay.font.is_null() => {
+                    xlib::XSetForeground(display, overlay.gc, pixel(overlay.argb, color));
+                    xlib::XDrawString(
+                        display,
+                        overlay.window,
+                        overlay.gc,
+                        x,
+                        y + (*overlay.font).ascent,
+                        text.as_ptr().cast(),
+                        text.len() as c_int,
+                    );
+                }
+                Shape::Text { .. } => {}
+            }
+        }
+        xlib::XFlush(display);
+    }
+}
+
+// Shows the overlay centered on (x, y), above everything else, moved as
+// little as it takes to be all on the monitor within if there is one.
+pub fn show_at(
+    st_x11: &StateX11,
+    overlay: &mut Overlay,
+    x: i32,
+    y: i32,
+    within: Option<geometry::Rect>,
+) {
+    let (mut left, mut top) = (x - overlay.width as i32 / 2, y - overlay.height as i32 / 2);
+    if let Some(bounds) = within {
+        (left, top) = geometry::keep_inside(bounds, left, top, overlay.width, overlay.height);
+    }
+    unsafe {
+        xlib::XMoveWindow(st_x11.display, overlay.window, left, top);
+        if !overlay.mapped {
+            xlib::XMapRaised(st_x11.display, overlay.window);
+            overlay.mapped = true;
+        }
+        xlib::XFlush(st_x11.display);
+    }
+}
+
+pub fn hide(st_x11: &StateX11, overlay: &mut Overlay) {
+    if overlay.mapped {
+        unsafe {
+            xlib::XUnmapWindow(st_x11.display, overlay.window);
+            xlib::XFlush(st_x11.display);
+        }
+        overlay.mapped = false;
+    }
+}
+
+pub fn destroy(st_x11: &StateX11, overlay: Overlay) {
+    unsafe {
+        if !overlay.font.is_null() {
+            xlib::XFreeFont(st_x11.display, overlay.font);
+        }
+        xlib::XFreeGC(st_x11.display, overlay.gc);
+        xlib::XDestroyWindow(st_x11.display, overlay.window);
+    }
+}
+
+// What the countdown overlay says with `left` to go
+pub fn countdown_text(style: Countdown, left: Duration, dwell_time: Duration) -> String {
+    match style {
+        Countdown::Off => String::new(),
+        Countdown::Millis => format!("{}", left.as_millis().div_ceil(100) * 100),
+        Countdown::Count => {
+            let third = (dwell_time.as_millis() / 3).max(1);
+            format!("{}", left.as_millis().div_ceil(third).clamp(1, 3))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circle_rows_are_symmetric() {
+        let rects = circle_rects(10, 10, 3);
+        assert_eq!(rects.len(), 7);
+        assert_eq!((rects[3].x, rects[3].y, rects[3].width), (7, 10, 7));
+        for (top, bottom) in rects.iter().zip(rects.iter().rev()) {
+            assert_eq!((top.x, top.width), (bottom.x, bottom.width));
+        }
+    }
+
+    #[test]
+    fn countdown_rounds_up() {
+        let dwell = Duration::from_millis(600);
+        let text = |style, ms| countdown_text(style, Duration::from_millis(ms), dwell);
+        assert_eq!(text(Countdown::Millis, 450), "500");
+        assert_eq!(text(Countdown::Count, 600), "3");
+        assert_eq!(text(Countdown::Count, 250), "2");
+        assert_eq!(text(Countdown::Count, 0), "1");
+    }
+
+    #[test]
+    fn argb_pixels_are_premultiplied() {
+        assert_eq!(pixel(true, 0x80ff0000), 0x80800000);
+        assert_eq!(pixel(false, 0x80ff0000), 0xff0000);
+    }
+}
@@ -0,0 +1,443 @@
+// rtmouse's own windows of buttons to dwell on, for people who can't use
+// hotkeys: the pause button, one big pause/resume target, and the control
+// panel, the usual dwell clicker strip of pause, left, double, right and
+// drag with the dwell's progress under them. A dwell on a button does what
+// it says instead of clicking it, and while dwell clicking is off the pause
+// buttons are the one thing that still works, see
+// DwellStateMachine::update_paused.
+//
+// Unlike the overlays these are normal windows. The pause button is one the
+// window manager decorates, so it can be dwell-dragged around by its title
+// bar; the panel is a dock, put where panel_position says. Neither takes
+// the focus, and what they show is the window's background pixmap, so the
+// server redraws it by itself and there are no Expose events to handle.
+
+use crate::config::{DwellConfig, PanelPosition};
+use crate::dwell::{ClickType, PanelButton};
+use crate::geometry::Rect;
+use crate::StateX11;
+use std::ffi::CString;
+use std::os::raw::c_int;
+use x11::xlib;
+
+// What the panel shows, redrawn when it changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Look {
+    pub paused: bool,
+    pub next_click: ClickType,
+    // The dwell's progress, in PROGRESS_STEPS
+    pub progress: u32,
+}
+
+pub const PROGRESS_STEPS: u32 = 20;
+
+pub struct Panel {
+    window: xlib::Window,
+    width: u32,
+    height: u32,
+    // Each button and where it is inside the window
+    buttons: Vec<(PanelButton, Rect)>,
+    // The progress bar inside the window, for panels that have one
+    bar: Option<Rect>,
+    // The dock's corner or edge, None for the pause button
+    position: Option<PanelPosition>,
+    gc: xlib::GC,
+    font: *mut xlib::XFontStruct,
+    shown: Option<Look>,
+}
+
+const PAUSE_COLOR: u32 = 0xc03030;
+const RESUME_COLOR: u32 = 0x30a030;
+const BUTTON_COLOR: u32 = 0x404040;
+// The click type the next dwell does
+const SELECTED_COLOR: u32 = 0x3060c0;
+const BAR_COLOR: u32 = 0x202020;
+const PROGRESS_COLOR: u32 = 0xffcc00;
+const TEXT_COLOR: u32 = 0xffffff;
+
+// Between the buttons, in the window's background color
+const GAP: u32 = 2;
+
+const STRIP: [PanelButton; 5] = [
+    PanelButton::Pause,
+    PanelButton::Click(ClickType::Left),
+    PanelButton::Click(ClickType::Double),
+    PanelButton::Click(ClickType::Right),
+    PanelButton::Click(ClickType::Drag),
+];
+
+// The strip's buttons side by side or one above the other, each size
+// pixels square, with the progress bar along the long side. Returns the
+// window's size too.
+pub fn layout(size: u32, vertical: bool) -> (u32, u32, Vec<(PanelButton, Rect)>, Rect) {
+    let step = (size + GAP) as i32;
+    let bar = (size / 6).max(4);
+    let long = STRIP.len() as u32 * (size + GAP) - GAP;
+    let buttons = STRIP
+        .iter()
+        .enumerate()
+        .map(|(i, &button)| {
+            let offset = i as i32 * step;
+            let (x, y) = if vertical { (0, offset) } else { (offset, 0) };
+            (
+                button,
+                Rect {
+                    x,
+                    y,
+                    width: size,
+                    height: size,
+                },
+            )
+        })
+        .collect();
+    if vertical {
+        let bar_rect = Rect {
+            x: (size + GAP) as i32,
+            y: 0,
+            width: bar,
+            height: long,
+        };
+        (size + GAP + bar, long, buttons, bar_rect)
+    } else {
+        let bar_rect = Rect {
+            x: 0,
+            y: (size + GAP) as i32,
+            width: long,
+            height: bar,
+        };
+        (long, size + GAP + bar, buttons, bar_rect)
+    }
+}
+
+// Where a width by height panel's top left goes on the monitor
+pub fn place(monitor: Rect, position: PanelPosition, width: u32, height: u32) -> (i32, i32) {
+    let free_x = monitor.width.saturating_sub(width) as i32;
+    let free_y = monitor.height.saturating_sub(height) as i32;
+    let (fx, fy) = match position {
+        PanelPosition::TopLeft => (0, 0),
+        PanelPosition::Top => (1, 0),
+        PanelPosition::TopRight => (2, 0),
+        PanelPosition::Left => (0, 1),
+        PanelPosition::Right => (2, 1),
+        PanelPosition::BottomLeft => (0, 2),
+        PanelPosition::Bottom => (1, 2),
+        PanelPosition::BottomRight => (2, 2),
+    };
+    (monitor.x + free_x * fx / 2, monitor.y + free_y * fy / 2)
+}
+
+fn atom(display: *mut xlib::Display, name: &str) -> xlib::Atom {
+    let name = CString::new(name).unwrap();
+    unsafe { xlib::XInternAtom(display, name.as_ptr(), xlib::False) }
+}
+
+fn set_atoms(display: *mut xlib::Display, window: xlib::Window, property: &str, values: &[&str]) {
+    let atoms: Vec<xlib::Atom> = values.iter().map(|v| atom(display, v)).collect();
+    unsafe {
+        xlib::XChangeProperty(
+            display,
+            window,
+            atom(display, property),
+            xlib::XA_ATOM,
+            32,
+            xlib::PropModeReplace,
+            atoms.as_ptr().cast(),
+            atoms.len() as c_int,
+        );
+    }
+}
+
+// The primary monitor comes first, panels go there
+fn panel_monitor(st_x11: &StateX11) -> Rect {
+    st_x11.monitors.first().copied().unwrap_or(Rect {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    })
+}
+
+fn create_window(
+    st_x11: &StateX11,
+    (x, y): (i32, i32),
+    width: u32,
+    height: u32,
+    window_type: &str,
+) -> (xlib::Window, xlib::GC, *mut xlib::XFontStruct) {
+    let display = st_x11.display;
+    let window =
+        unsafe { xlib::XCreateSimpleWindow(display, st_x11.root, x, y, width, height, 0, 0, 0) };
+
+    let title = CString::new("rtmouse").unwrap();
+    set_atoms(display, window, "_NET_WM_WINDOW_TYPE", &[window_type]);
+    set_atoms(
+        display,
+        window,
+        "_NET_WM_STATE",
+        &["_NET_WM_STATE_ABOVE", "_NET_WM_STATE_STICKY"],
+    );
+    unsafe {
+        xlib::XStoreName(display, window, title.as_ptr());
+        // So the tick loop hears when it's moved, mapped or unmapped, rather
+        // than asking where it is every time the pointer moves
+        xlib::XSelectInput(display, window, xlib::StructureNotifyMask);
+
+        // Taking the focus would pull it away from what the user is doing
+        let mut hints: xlib::XWMHints = std::mem::zeroed();
+        hints.flags = xlib::InputHint;
+        hints.input = xlib::False;
+        xlib::XSetWMHints(display, window, &mut hints);
+
+        // Asked for a place, and no resizing, which would undo the layout
+        let mut size_hints: xlib::XSizeHints = std::mem::zeroed();
+        size_hints.flags = xlib::PPosition | xlib::PMinSize | xlib::PMaxSize;
+        size_hints.x = x;
+        size_hints.y = y;
+        size_hints.min_width = width as c_int;
+        size_hints.min_height = height as c_int;
+        size_hints.max_width = width as c_int;
+        size_hints.max_height = height as c_int;
+        xlib::XSetWMNormalHints(display, window, &mut size_hints);
+    }
+
+    let gc = unsafe { xlib::XCreateGC(display, window, 0, std::ptr::null_mut()) };
+    // Big enough to read from across the room, when the server has it
+    let font = ["10x20", "fixed"]
+        .iter()
+        .map(|name| {
+            let name = CString::new(*name).unwrap();
+            unsafe { xlib::XLoadQueryFont(display, name.as_ptr()) }
+        })
+        .find(|font| !font.is_null())
+        .unwrap_or(std::ptr::null_mut());
+    if !font.is_null() {
+        unsafe {
+            xlib::XSetFont(display, gc, (*font).fid);
+        }
+    }
+
+    unsafe {
+        xlib::XMapWindow(display, window);
+        xlib::XFlush(display);
+    }
+    (window, gc, font)
+}
+
+pub fn create_pause_button(st_x11: &StateX11, size: u32) -> Panel {
+    let (window, gc, font) =
+        create_window(st_x11, (0, 0), size, size, "_NET_WM_WINDOW_TYPE_UTILITY");
+    let rect = Rect {
+        x: 0,
+        y: 0,
+        width: size,
+        height: size,
+    };
+    Panel {
+        window,
+        width: size,
+        height: size,
+        buttons: vec![(PanelButton::Pause, rect)],
+        bar: None,
+        position: None,
+        gc,
+        font,
+        shown: None,
+    }
+}
+
+pub fn create_control_panel(st_x11: &StateX11, config: &DwellConfig) -> Panel {
+    let (width, height, buttons, bar) = layout(config.panel_button_size, config.panel_vertical);
+    let at = place(panel_monitor(st_x11), config.panel_position, width, height);
+    let (window, gc, font) = create_window(st_x11, at, width, height, "_NET_WM_WINDOW_TYPE_DOCK");
+    Panel {
+        window,
+        width,
+        height,
+        buttons,
+        bar: Some(bar),
+        position: Some(config.panel_position),
+        gc,
+        font,
+        shown: None,
+    }
+}
+
+// Puts the control panel back in its place on the monitors there are now
+pub fn reposition(st_x11: &StateX11, panel: &Panel) {
+    if let Some(position) = panel.position {
+        let (x, y) = place(panel_monitor(st_x11), position, panel.width, panel.height);
+        unsafe {
+            xlib::XMoveWindow(st_x11.display, panel.window, x, y);
+        }
+    }
+}
+
+fn label(button: PanelButton, paused: bool) -> &'static str {
+    match button {
+        PanelButton::Pause if paused => "resume",
+        PanelButton::Pause => "pause",
+        PanelButton::Click(click) => click.as_str(),
+    }
+}
+
+fn color(button: PanelButton, look: &Look) -> u32 {
+    match button {
+        PanelButton::Pause if look.paused => RESUME_COLOR,
+        PanelButton::Pause => PAUSE_COLOR,
+        PanelButton::Click(click) if click == look.next_click => SELECTED_COLOR,
+        PanelButton::Click(_) => BUTTON_COLOR,
+    }
+}
+
+// Redraws the panel if look differs from what it shows
+pub fn show(st_x11: &StateX11, panel: &mut Panel, mut look: Look) {
+    if panel.bar.is_none() {
+        look.progress = 0;
+    }
+    if panel.shown == Some(look) {
+        return;
+    }
+    panel.shown = Some(look);
+
+    let display = st_x11.display;
+    let gc = panel.gc;
+    unsafe {
+        let depth = xlib::XDefaultDepth(display, xlib::XDefaultScreen(display));
+        let pixmap = xlib::XCreatePixmap(
+            display,
+            panel.window,
+            panel.width,
+            panel.height,
+            depth as u32,
+        );
+        let fill = |color: u32, r: Rect| {
+            xlib::XSetForeground(display, gc, color as _);
+            xlib::XFillRectangle(display, pixmap, gc, r.x, r.y, r.width, r.height);
+        };
+        fill(
+            0,
+            Rect {
+                x: 0,
+                y: 0,
+                width: panel.width,
+                height: panel.height,
+            },
+        );
+
+        for &(button, r) in &panel.buttons {
+            fill(color(button, &look), r);
+            if panel.font.is_null() {
+                continue;
+            }
+            let text = label(button, look.paused);
+            let font = &*panel.font;
+            let width = xlib::XTextWidth(panel.font, text.as_ptr().cast(), text.len() as c_int);
+            let x = r.x + (r.width as c_int - width) / 2;
+            let y = r.y + (r.height as c_int + font.ascent - font.descent) / 2;
+            xlib::XSetForeground(display, gc, TEXT_COLOR as _);
+            xlib::XDrawString(
+                display,
+                pixmap,
+                gc,
+                x,
+                y,
+                text.as_ptr().cast(),
+                text.len() as c_int,
+            );
+        }
+
+        if let Some(bar) = panel.bar {
+            fill(BAR_COLOR, bar);
+            let mut done = bar;
+            if bar.width > bar.height {
+                done.width = bar.width * look.progress / PROGRESS_STEPS;
+            } else {
+                done.height = bar.height * look.progress / PROGRESS_STEPS;
+            }
+            if look.progress > 0 {
+                fill(PROGRESS_COLOR, done);
+            }
+        }
+
+        xlib::XSetWindowBackgroundPixmap(display, panel.window, pixmap);
+        // The window keeps its own reference
+        xlib::XFreePixmap(display, pixmap);
+        xlib::XClearWindow(display, panel.window);
+        xlib::XFlush(display);
+    }
+}
+
+// Where the panel's buttons are on the root window now, none while it
+// isn't showing (minimized, or on another desktop despite asking to be
+// sticky)
+pub fn buttons(st_x11: &StateX11, panel: &Panel) -> Vec<(Rect, PanelButton)> {
+    let display = st_x11.display;
+    let (mut x, mut y) = (0, 0);
+    unsafe {
+        let mut attrs: xlib::XWindowAttributes = std::mem::zeroed();
+        if xlib::XGetWindowAttributes(display, panel.window, &mut attrs) == 0
+            || attrs.map_state != xlib::IsViewable
+        {
+            return Vec::new();
+        }
+        let mut child = 0;
+        xlib::XTranslateCoordinates(
+            display,
+            panel.window,
+            st_x11.root,
+            0,
+            0,
+            &mut x,
+            &mut y,
+            &mut child,
+        );
+    }
+    panel
+        .buttons
+        .iter()
+        .map(|&(button, r)| {
+            (
+                Rect {
+                    x: x + r.x,
+                    y: y + r.y,
+                    ..r
+                },
+                button,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_lays_out_and_docks() {
+        let (w, h, buttons, bar) = layout(60, false);
+        assert_eq!((w, h), (308, 72));
+        assert_eq!(buttons[1].0, PanelButton::Click(ClickType::Left));
+        assert_eq!((buttons[4].1.x, buttons[4].1.y), (248, 0));
+        assert_eq!((bar.y, bar.width), (62, 308));
+
+        let (w, h, buttons, _) = layout(60, true);
+        assert_eq!((w, h), (72, 308));
+        assert_eq!(buttons[2].1.y, 124);
+
+        let monitor = Rect {
+            x: 1920,
+            y: 0,
+            width: 2560,
+            height: 1440,
+        };
+        assert_eq!(place(monitor, PanelPosition::TopLeft, 308, 72), (1920, 0));
+        assert_eq!(
+            place(monitor, PanelPosition::Bottom, 308, 72),
+            (1920 + 1126, 1368)
+        );
+        assert_eq!(
+            place(monitor, PanelPosition::Right, 72, 308),
+            (1920 + 2488, 566)
+        );
+    }
+}
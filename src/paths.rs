@@ -0,0 +1,104 @@
+// Where rtmouse keeps the files it makes while running.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static RUNTIME_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// --runtime-dir, for a second rtmouse or one whose files live elsewhere. Has
+// to be called before anything asks for runtime_dir().
+pub fn set_runtime_dir(dir: PathBuf) {
+    let _ = RUNTIME_DIR.set(dir);
+}
+
+// $XDG_RUNTIME_DIR/rtmouse, or a per-user directory in /tmp without one
+pub fn runtime_dir() -> PathBuf {
+    if let Some(dir) = RUNTIME_DIR.get() {
+        return dir.clone();
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("rtmouse"),
+        None => {
+            let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+            PathBuf::from(format!("/tmp/rtmouse-{}", user))
+        }
+    }
+}
+
+// Creates runtime_dir() readable by us only, and returns it. One that's
+// already there gets tightened up, which fails if it isn't ours, since
+// /tmp/rtmouse-$USER could have been made by anyone.
+pub fn ensure_runtime_dir() -> io::Result<PathBuf> {
+    let dir = runtime_dir();
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    if fs::metadata(&dir)?.permissions().mode() & 0o077 != 0 {
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(dir)
+}
+
+// A path from the config, where empty means name in runtime_dir()
+pub fn or_runtime_file(setting: &str, name: &str) -> PathBuf {
+    if setting.is_empty() {
+        runtime_dir().join(name)
+    } else {
+        PathBuf::from(setting)
+    }
+}
+
+// Creates runtime_dir() if path is in it. Anywhere else was picked by the
+// user and has to exist already.
+pub fn ensure_dir_for(path: &Path) -> io::Result<()> {
+    if path.parent() == Some(runtime_dir().as_path()) {
+        ensure_runtime_dir()?;
+    }
+    Ok(())
+}
+
+// $XDG_STATE_HOME/rtmouse, for what should outlive a reboot but isn't config
+pub fn state_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))?;
+    Some(base.join("rtmouse"))
+}
+
+// $XDG_DATA_HOME/rtmouse, then /usr/share/rtmouse, for what users and
+// distributions install for rtmouse to find, like translations
+pub fn data_dirs() -> Vec<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")));
+    data_home
+        .into_iter()
+        .chain(Some(PathBuf::from("/usr/share")))
+        .map(|dir| dir.join("rtmouse"))
+        .collect()
+}
+
+// Daily usage counters, see stats.rs
+pub fn stats_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("stats"))
+}
+
+// history.rs's event log, and the previous one once it got too big
+pub fn history_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("history"))
+}
+
+pub fn old_history_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("history.old"))
+}
+
+// Recorded click macros, one file each, see macros.rs
+pub fn macros_dir() -> Option<PathBuf> {
+    Some(state_dir()?.join("macros"))
+}
@@ -0,0 +1,65 @@
+// Whether we're running on battery, read from sysfs. Good enough for picking
+// a tick rate; UPower would tell us the same thing over D-Bus.
+
+use std::fs;
+use std::path::Path;
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path)
+        .map(|s| s.trim().to_string())
+        .unwrap_or_default()
+}
+
+// On battery means there's a battery and no mains supply is online.
+// Machines without a battery, or where we can't tell, count as plugged in.
+pub fn on_battery_in(dir: &Path) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    let mut battery = false;
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        match read(&supply.join("type")).as_str() {
+            "Mains" | "USB" if read(&supply.join("online")) == "1" => return false,
+            "Battery" if read(&supply.join("present")) != "0" => battery = true,
+            _ => {}
+        }
+    }
+    battery
+}
+
+pub fn on_battery() -> bool {
+    on_battery_in(Path::new("/sys/class/power_supply"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supply(dir: &Path, name: &str, files: &[(&str, &str)]) {
+        let path = dir.join(name);
+        fs::create_dir_all(&path).unwrap();
+        for (file, contents) in files {
+            fs::write(path.join(file), format!("{}\n", contents)).unwrap();
+        }
+    }
+
+    #[test]
+    fn battery_only_counts_when_unplugged() {
+        let dir = std::env::temp_dir().join(format!("rtmouse-power-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(!on_battery_in(&dir));
+
+        supply(&dir, "BAT0", &[("type", "Battery"), ("present", "1")]);
+        supply(&dir, "AC", &[("type", "Mains"), ("online", "1")]);
+        assert!(!on_battery_in(&dir));
+
+        supply(&dir, "AC", &[("type", "Mains"), ("online", "0")]);
+        assert!(on_battery_in(&dir));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
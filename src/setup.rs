@@ -0,0 +1,299 @@
+// First-run questions for `--setup`, or when rtmouse starts from a terminal
+// without a config. A handful of choices that matter most on day one; the
+// config it writes has every other option in it, commented, for later.
+
+use crate::config::{self, DwellConfig};
+use std::io::{self, BufRead, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+// (what's offered, dwell_time in milliseconds)
+const SPEEDS: &[(&str, u64)] = &[
+    ("quick, 0.35 seconds", 350),
+    ("medium, 0.5 seconds", 500),
+    ("slow, 0.9 seconds", 900),
+    ("very slow, 1.4 seconds", 1400),
+];
+
+// Asks until the answer is one of the choices' numbers, or empty for the
+// default. Running out of input takes the default too, so a closed stdin
+// can't loop forever.
+fn choose(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    question: &str,
+    choices: &[&str],
+    default: usize,
+) -> io::Result<usize> {
+    writeln!(output, "{}", question)?;
+    for (i, choice) in choices.iter().enumerate() {
+        let mark = if i == default { " (default)" } else { "" };
+        writeln!(output, "  {}) {}{}", i + 1, choice, mark)?;
+    }
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(default);
+        }
+        match line.trim().parse::<usize>() {
+            _ if line.trim().is_empty() => return Ok(default),
+            Ok(n) if (1..=choices.len()).contains(&n) => return Ok(n - 1),
+            _ => writeln!(output, "type a number from 1 to {}", choices.len())?,
+        }
+    }
+}
+
+fn yes_no(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    question: &str,
+    default: bool,
+) -> io::Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        write!(output, "{} [{}] ", question, hint)?;
+        output.flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(default);
+        }
+        match line.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => writeln!(output, "type y or n")?,
+        }
+    }
+}
+
+// GNOME's own dwell clicking, from mousetweaks. Its gesture mappings pick a
+// click type by the direction moved after the dwell, which rtmouse has no
+// equivalent of, so only the timing and threshold come across.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mousetweaks {
+    pub enabled: bool,
+    pub dwell_time: Duration,
+    // How far the pointer can move and still be dwelling, in pixels
+    pub threshold: u32,
+}
+
+impl Mousetweaks {
+    // Someone who used it, rather than GNOME's defaults nobody chose
+    fn worth_offering(&self) -> bool {
+        self.enabled || self.dwell_time != Duration::from_millis(1200) || self.threshold != 10
+    }
+}
+
+// From `gsettings list-recursively org.gnome.desktop.a11y.mouse`, one
+// "schema key value" per line
+pub fn parse_mousetweaks(text: &str) -> Option<Mousetweaks> {
+    let (mut enabled, mut dwell_time, mut threshold) = (false, None, None);
+    for line in text.lines() {
+        let mut words = line.split_whitespace().skip(1);
+        match (words.next(), words.next()) {
+            (Some("dwell-click-enabled"), Some(v)) => enabled = v == "true",
+            (Some("dwell-time"), Some(v)) => {
+                dwell_time = v
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| (0.0..=60.0).contains(s))
+                    .map(Duration::from_secs_f64)
+            }
+            (Some("dwell-threshold"), Some(v)) => threshold = v.parse().ok(),
+            _ => {}
+        }
+    }
+    Some(Mousetweaks {
+        enabled,
+        dwell_time: dwell_time?,
+        threshold: threshold?,
+    })
+}
+
+// None without gsettings or outside GNOME
+pub fn read_mousetweaks() -> Option<Mousetweaks> {
+    let output = Command::new("gsettings")
+        .args(["list-recursively", "org.gnome.desktop.a11y.mouse"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    parse_mousetweaks(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Offers what mousetweaks was set to, returns true if it was taken
+fn import_mousetweaks(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    config: &mut DwellConfig,
+    mousetweaks: &Mousetweaks,
+) -> io::Result<bool> {
+    let question = format!(
+        "GNOME's dwell click is set to click after {:.1} seconds, moving less than {} pixels. Use that?",
+        mousetweaks.dwell_time.as_secs_f64(),
+        mousetweaks.threshold
+    );
+    if !yes_no(input, output, &question, true)? {
+        return Ok(false);
+    }
+    config.dwell_time = mousetweaks.dwell_time.max(Duration::from_millis(1));
+    config.min_movement_pixels = mousetweaks.threshold.min(1000);
+    if mousetweaks.enabled {
+        writeln!(
+            output,
+            "turn GNOME's dwell click off, or every dwell clicks twice"
+        )?;
+    }
+    Ok(true)
+}
+
+// calibrate measures how far the pointer wanders while held still, in
+// pixels, when there's a display to measure it on. Settings taken from
+// mousetweaks replace the speed question and the measuring.
+pub fn run(
+    input: &mut dyn BufRead,
+    output: &mut dyn Write,
+    calibrate: Option<&dyn Fn() -> Option<u32>>,
+    mousetweaks: Option<&Mousetweaks>,
+) -> io::Result<DwellConfig> {
+    let mut config = DwellConfig::default();
+
+    let imported = match mousetweaks.filter(|m| m.worth_offering()) {
+        Some(m) => import_mousetweaks(input, output, &mut config, m)?,
+        None => false,
+    };
+
+    if !imported {
+        let names: Vec<&str> = SPEEDS.iter().map(|&(name, _)| name).collect();
+        let speed = choose(
+            input,
+            output,
+            "How long should the pointer rest before it clicks?",
+            &names,
+            1,
+        )?;
+        config.dwell_time = Duration::from_millis(SPEEDS[speed].1);
+    }
+
+    config.drag_enabled = yes_no(
+        input,
+        output,
+        "Drag when the pointer moves off soon after a click?",
+        config.drag_enabled,
+    )?;
+    config.sound_enabled = yes_no(
+        input,
+        output,
+        "Play a sound on each click?",
+        config.sound_enabled,
+    )?;
+
+    if let Some(calibrate) = calibrate.filter(|_| !imported) {
+        if yes_no(
+            input,
+            output,
+            "Measure how much your pointer wanders when you hold it still?",
+            true,
+        )? {
+            writeln!(output, "Rest the pointer on one spot for 5 seconds...")?;
+            output.flush()?;
+            match calibrate() {
+                Some(wander) => {
+                    // Enough headroom that wandering never restarts a dwell
+                    config.min_movement_pixels = (wander + wander / 2 + 2).clamp(3, 1000);
+                    writeln!(
+                        output,
+                        "it wandered {} pixels, so min_movement_pixels = {}",
+                        wander, config.min_movement_pixels
+                    )?;
+                }
+                None => writeln!(output, "couldn't reach the display, skipping that")?,
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+pub fn config_text(config: &DwellConfig) -> String {
+    format!(
+        "# rtmouse config, written by rtmouse-rs --setup. Every option is listed\n\
+         # here. Delete or change whatever you like, missing options use the default.\n\
+         \n{}",
+        config::to_toml(config, true)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answer(text: &str, calibrate: Option<&dyn Fn() -> Option<u32>>) -> DwellConfig {
+        let mut output = Vec::new();
+        run(&mut text.as_bytes(), &mut output, calibrate, None).unwrap()
+    }
+
+    #[test]
+    fn answers_pick_the_options() {
+        let config = answer("3\nn\nno\n", None);
+        assert_eq!(config.dwell_time, Duration::from_millis(900));
+        assert!(!config.drag_enabled);
+        assert!(!config.sound_enabled);
+
+        // Asked again after nonsense, and the defaults on enter or at the end
+        let config = answer("9\nfast\n\nmaybe\n", None);
+        assert_eq!(config.dwell_time, Duration::from_millis(500));
+        assert!(config.drag_enabled);
+        assert!(config.sound_enabled);
+
+        let config = answer("\n\n\ny\n", Some(&|| Some(8)));
+        assert_eq!(config.min_movement_pixels, 14);
+        let config = answer("\n\n\nn\n", Some(&|| panic!("asked to skip it")));
+        assert_eq!(config.min_movement_pixels, 10);
+
+        let text = config_text(&config);
+        assert_eq!(
+            config::from_str(&text).unwrap().dwell_time,
+            config.dwell_time
+        );
+    }
+
+    #[test]
+    fn mousetweaks_settings_come_across() {
+        let gsettings = "org.gnome.desktop.a11y.mouse dwell-click-enabled true\n\
+                         org.gnome.desktop.a11y.mouse dwell-gesture-double 'up'\n\
+                         org.gnome.desktop.a11y.mouse dwell-threshold 6\n\
+                         org.gnome.desktop.a11y.mouse dwell-time 0.80000000000000004\n";
+        let mousetweaks = parse_mousetweaks(gsettings).unwrap();
+        assert_eq!(mousetweaks.threshold, 6);
+        assert!(parse_mousetweaks("org.gnome.desktop.a11y.mouse dwell-time 1.2").is_none());
+
+        // Taking them skips the speed question and the measuring
+        let mut output = Vec::new();
+        let calibrate: &dyn Fn() -> Option<u32> = &|| panic!("already have a threshold");
+        let config = run(
+            &mut "\nn\n\n".as_bytes(),
+            &mut output,
+            Some(calibrate),
+            Some(&mousetweaks),
+        )
+        .unwrap();
+        assert_eq!(config.dwell_time, Duration::from_millis(800));
+        assert_eq!(config.min_movement_pixels, 6);
+        assert!(!config.drag_enabled);
+
+        // GNOME's untouched defaults aren't worth asking about
+        let defaults = Mousetweaks {
+            enabled: false,
+            dwell_time: Duration::from_millis(1200),
+            threshold: 10,
+        };
+        let config = run(&mut "3\n".as_bytes(), &mut output, None, Some(&defaults)).unwrap();
+        assert_eq!(config.dwell_time, Duration::from_millis(900));
+    }
+}
@@ -0,0 +1,121 @@
+// Shaking the pointer, fast back and forth a few times, toggles dwell
+// clicking: a switch that needs nothing but the pointer, no keyboard, no
+// panel, and works just as well while clicking is off.
+//
+// It's measured between ticks, so every leg has to take most of a tick to
+// be seen. That's fine for a deliberate shake, and keeps reading and
+// scrolling from ever looking like one.
+
+use crate::config::DwellConfig;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+pub struct StateShake {
+    last: Option<(i32, i32, Instant)>,
+    // Which way the last fast movement went: along x or not, and forwards
+    // or not
+    heading: Option<(bool, bool)>,
+    // When it turned back, within shake_time
+    turns: VecDeque<Instant>,
+}
+
+impl StateShake {
+    pub fn new() -> StateShake {
+        StateShake {
+            last: None,
+            heading: None,
+            turns: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for StateShake {
+    fn default() -> StateShake {
+        StateShake::new()
+    }
+}
+
+// Takes every pointer position, returns true when they add up to a shake
+pub fn feed(st: &mut StateShake, config: &DwellConfig, x: i32, y: i32, now: Instant) -> bool {
+    let (last_x, last_y, last_at) = match st.last.replace((x, y, now)) {
+        Some(last) => last,
+        None => return false,
+    };
+    while st
+        .turns
+        .front()
+        .is_some_and(|&t| now.saturating_duration_since(t) > config.shake_time)
+    {
+        st.turns.pop_front();
+    }
+
+    let (dx, dy) = ((x - last_x) as f64, (y - last_y) as f64);
+    let secs = now.saturating_duration_since(last_at).as_secs_f64();
+    if secs <= 0.0 || dx.hypot(dy) / secs < config.shake_speed as f64 {
+        // Slowing down at the ends is part of a shake, this doesn't break it
+        return false;
+    }
+
+    let horizontal = dx.abs() >= dy.abs();
+    let forwards = if horizontal { dx > 0.0 } else { dy > 0.0 };
+    if let Some((was_horizontal, was_forwards)) = st.heading {
+        if was_horizontal == horizontal && was_forwards != forwards {
+            st.turns.push_back(now);
+        }
+    }
+    st.heading = Some((horizontal, forwards));
+
+    if st.turns.len() as u32 >= config.shake_reversals {
+        st.turns.clear();
+        st.heading = None;
+        return true;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn shake(st: &mut StateShake, config: &DwellConfig, points: &[(i32, u64)]) -> Vec<bool> {
+        let start = Instant::now();
+        points
+            .iter()
+            .map(|&(x, ms)| feed(st, config, x, 500, start + Duration::from_millis(ms)))
+            .collect()
+    }
+
+    #[test]
+    fn fast_back_and_forth_is_a_shake() {
+        let config = DwellConfig::default();
+
+        // 300 pixels a tick is 3000 per second, well over shake_speed
+        let mut st = StateShake::new();
+        let legs: Vec<(i32, u64)> = (0..7)
+            .map(|i| (if i % 2 == 0 { 100 } else { 400 }, i * 100))
+            .collect();
+        let found = shake(&mut st, &config, &legs);
+        assert_eq!(found.iter().filter(|&&f| f).count(), 1);
+        assert!(found[5]);
+
+        // The same distance, slowly, is only moving around
+        let mut st = StateShake::new();
+        let slow: Vec<(i32, u64)> = (0..7)
+            .map(|i| (if i % 2 == 0 { 100 } else { 400 }, i * 1000))
+            .collect();
+        assert!(!shake(&mut st, &config, &slow).contains(&true));
+
+        // And turns spread out over longer than shake_time don't add up
+        let mut st = StateShake::new();
+        let spread = [
+            (100, 0),
+            (400, 100),
+            (100, 200),
+            (400, 1500),
+            (100, 1600),
+            (400, 3000),
+        ];
+        assert!(!shake(&mut st, &config, &spread).contains(&true));
+    }
+}
@@ -0,0 +1,414 @@
+// Feedback sounds. Playing through a sound server's own client instead of
+// opening an ALSA device means our sounds mix with whatever else is playing
+// and show up as a stream with its own volume. pw-play talks to PipeWire
+// directly and paplay covers PulseAudio (and pipewire-pulse); aplay is the
+// last resort for systems with neither.
+//
+// A sound theme is a directory of sounds named after what they're for
+// (left.wav, drag-end.oga), with a theme.toml that can point any of them at
+// another file instead. Themes live in sounds/ under paths::data_dirs().
+// Unless there's a theme or the config names a sound file, the sounds are
+// synthesized: short sine bursts whose pitch says what kind of click
+// happened, written out as WAV files in the runtime directory the first time
+// they're needed.
+
+use crate::config::{self, Value};
+use crate::dwell::ClickType;
+use crate::paths;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+const PLAYERS: &[(&str, &[&str])] = &[
+    ("pw-play", &["--media-role", "Notification"]),
+    ("paplay", &["--property=media.role=event"]),
+    ("aplay", &["-q"]),
+];
+
+pub struct StateSound {
+    // Index into PLAYERS, found on first use
+    player: Option<Option<usize>>,
+    // Sounds still playing, reaped on the next play
+    children: Vec<Child>,
+    // Synthesized tones already written out, by tone, pitch and duration, so
+    // changing sound_pitch or sound_duration on reload writes new ones
+    tones: Vec<((Tone, u32, Duration), PathBuf)>,
+    // From `rtmouse-rs sound-theme`, over the config's sound_theme. Empty
+    // for none.
+    chosen_theme: Option<String>,
+    // The theme last looked up by name, None if it wouldn't load
+    theme: Option<(String, Option<SoundTheme>)>,
+}
+
+impl StateSound {
+    pub fn new() -> StateSound {
+        StateSound {
+            player: None,
+            children: Vec::new(),
+            tones: Vec::new(),
+            chosen_theme: None,
+            theme: None,
+        }
+    }
+}
+
+impl Default for StateSound {
+    fn default() -> StateSound {
+        StateSound::new()
+    }
+}
+
+fn in_path(program: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
+
+// The player that would be used, for `rtmouse doctor`
+pub fn player_name() -> Option<&'static str> {
+    PLAYERS
+        .iter()
+        .map(|(program, _)| *program)
+        .find(|program| in_path(program))
+}
+
+fn find_player() -> Option<usize> {
+    let found = PLAYERS.iter().position(|(program, _)| in_path(program));
+    if found.is_none() {
+        eprintln!("Error: no sound player found, install pw-play, paplay or aplay");
+    }
+    found
+}
+
+pub fn play(st: &mut StateSound, file: &Path) {
+    st.children
+        .retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_)) | Err(_)));
+
+    let player = *st.player.get_or_insert_with(find_player);
+    let (program, args) = match player {
+        Some(i) => PLAYERS[i],
+        None => return,
+    };
+
+    match Command::new(program)
+        .args(args)
+        .arg(file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => st.children.push(child),
+        Err(e) => eprintln!("Error: could not run {}: {}", program, e),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tone {
+    Click(ClickType),
+    DragEnd,
+    // A low blip, then the Click tone of the type that was picked
+    Selected(ClickType),
+    // Something went wrong, like the stuck drag watchdog firing
+    Warning,
+}
+
+const SAMPLE_RATE: u32 = 44100;
+
+fn name(tone: Tone) -> &'static str {
+    match tone {
+        Tone::Click(click) => click.as_str(),
+        Tone::DragEnd => "drag-end",
+        Tone::Selected(ClickType::Left) => "selected-left",
+        Tone::Selected(ClickType::Right) => "selected-right",
+        Tone::Selected(ClickType::Double) => "selected-double",
+        Tone::Selected(ClickType::Drag) => "selected-drag",
+        Tone::Selected(ClickType::Gesture) => "selected-gesture",
+        Tone::Warning => "warning",
+    }
+}
+
+// A sine burst with a quick attack and a decay to silence, so it doesn't
+// click at either end.
+fn burst(out: &mut Vec<i16>, freq: f32, duration: Duration) {
+    let n = (duration.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    let attack = (SAMPLE_RATE / 500) as usize;
+    for i in 0..n {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        let envelope = if i < attack {
+            i as f32 / attack as f32
+        } else {
+            let left = 1.0 - (i - attack) as f32 / (n - attack).max(1) as f32;
+            left * left
+        };
+        let sample = (2.0 * PI * freq * t).sin() * envelope * 0.5;
+        out.push((sample * i16::MAX as f32) as i16);
+    }
+}
+
+fn silence(out: &mut Vec<i16>, duration: Duration) {
+    let n = (duration.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    out.extend(std::iter::repeat_n(0, n));
+}
+
+pub fn samples(tone: Tone, pitch: u32, duration: Duration) -> Vec<i16> {
+    let pitch = pitch as f32;
+    let mut out = Vec::new();
+    match tone {
+        Tone::Click(ClickType::Left) => burst(&mut out, pitch, duration),
+        Tone::Click(ClickType::Right) => burst(&mut out, pitch * 0.75, duration),
+        Tone::Click(ClickType::Double) => {
+            burst(&mut out, pitch, duration);
+            silence(&mut out, duration);
+            burst(&mut out, pitch, duration);
+        }
+        Tone::Click(ClickType::Drag) => burst(&mut out, pitch * 1.5, duration),
+        Tone::Click(ClickType::Gesture) => burst(&mut out, pitch * 2.0, duration),
+        Tone::DragEnd => burst(&mut out, pitch * 1.25, duration),
+        Tone::Selected(click) => {
+            burst(&mut out, pitch * 0.5, duration / 2);
+            silence(&mut out, duration);
+            out.extend(samples(Tone::Click(click), pitch as u32, duration));
+        }
+        Tone::Warning => {
+            for _ in 0..3 {
+                burst(&mut out, pitch * 0.5, duration * 2);
+                silence(&mut out, duration);
+            }
+        }
+    }
+    out
+}
+
+// 16 bit mono PCM
+pub fn wav(samples: &[i16]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    out
+}
+
+// Like left-880hz-40ms.wav
+fn tone_file_name(tone: Tone, pitch: u32, duration: Duration) -> String {
+    format!("{}-{}hz-{}ms.wav", name(tone), pitch, duration.as_millis())
+}
+
+fn tone_file(st: &mut StateSound, tone: Tone, pitch: u32, duration: Duration) -> Option<PathBuf> {
+    let key = (tone, pitch, duration);
+    if let Some((_, path)) = st.tones.iter().find(|(k, _)| *k == key) {
+        return Some(path.clone());
+    }
+
+    let written = paths::ensure_runtime_dir().and_then(|dir| {
+        let path = dir.join(tone_file_name(tone, pitch, duration));
+        fs::write(&path, wav(&samples(tone, pitch, duration)))?;
+        Ok(path)
+    });
+    match written {
+        Ok(path) => {
+            st.tones.push((key, path.clone()));
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!("Error: could not write the {} sound: {}", name(tone), e);
+            None
+        }
+    }
+}
+
+pub struct SoundTheme {
+    dir: PathBuf,
+    // Event names from theme.toml and the files they play
+    files: HashMap<String, String>,
+}
+
+const THEME_MANIFEST: &str = "theme.toml";
+const THEME_EXTENSIONS: &[&str] = &["wav", "oga", "ogg"];
+
+impl SoundTheme {
+    // Only string values count, a line that isn't one falls back to the
+    // file named after the event
+    pub fn from_manifest(dir: PathBuf, text: &str) -> SoundTheme {
+        let (entries, _) = config::parse(text);
+        let files = entries
+            .into_iter()
+            .filter_map(|e| match e.value {
+                Value::Str(s) => Some((e.key, s)),
+                _ => None,
+            })
+            .collect();
+        SoundTheme { dir, files }
+    }
+
+    // None for a tone the theme leaves out, which stays synthesized
+    pub fn file(&self, tone: Tone) -> Option<PathBuf> {
+        let event = name(tone);
+        if let Some(file) = self.files.get(event) {
+            return Some(self.dir.join(file));
+        }
+        THEME_EXTENSIONS
+            .iter()
+            .map(|ext| self.dir.join(format!("{}.{}", event, ext)))
+            .find(|path| path.is_file())
+    }
+}
+
+// A name is looked for in the data directories, anything with a slash in it
+// is the theme's directory
+pub fn load_theme(name: &str) -> Result<SoundTheme, String> {
+    let dirs: Vec<PathBuf> = if name.contains('/') {
+        vec![PathBuf::from(name)]
+    } else {
+        paths::data_dirs()
+            .into_iter()
+            .map(|dir| dir.join("sounds").join(name))
+            .collect()
+    };
+    for dir in &dirs {
+        if let Ok(text) = fs::read_to_string(dir.join(THEME_MANIFEST)) {
+            return Ok(SoundTheme::from_manifest(dir.clone(), &text));
+        }
+    }
+    let places: Vec<String> = dirs.iter().map(|d| d.display().to_string()).collect();
+    Err(format!(
+        "no sound theme `{}`, looked for {} in {}",
+        name,
+        THEME_MANIFEST,
+        places.join(" and ")
+    ))
+}
+
+// For the control socket, "none" goes back to no theme whatever the config
+// says
+pub fn choose_theme(st: &mut StateSound, name: &str) -> Result<(), String> {
+    if name == "none" {
+        st.chosen_theme = Some(String::new());
+        return Ok(());
+    }
+    let theme = load_theme(name)?;
+    st.chosen_theme = Some(name.to_string());
+    st.theme = Some((name.to_string(), Some(theme)));
+    Ok(())
+}
+
+// For the status, "none" without one
+pub fn theme_name<'a>(st: &'a StateSound, config: &'a config::DwellConfig) -> &'a str {
+    match st.chosen_theme.as_deref().unwrap_or(&config.sound_theme) {
+        "" => "none",
+        name => name,
+    }
+}
+
+fn current_theme<'a>(
+    st: &'a mut StateSound,
+    config: &config::DwellConfig,
+) -> Option<&'a SoundTheme> {
+    let name = st.chosen_theme.as_deref().unwrap_or(&config.sound_theme);
+    if name.is_empty() {
+        return None;
+    }
+    if st.theme.as_ref().is_none_or(|(loaded, _)| loaded != name) {
+        let theme = load_theme(name).map_err(|e| eprintln!("Error: {}", e)).ok();
+        st.theme = Some((name.to_string(), theme));
+    }
+    st.theme.as_ref().and_then(|(_, theme)| theme.as_ref())
+}
+
+// The theme's sound if it has one, then sound_file from the config if there
+// is one, otherwise the synthesized tone
+pub fn play_tone(st: &mut StateSound, tone: Tone, config: &config::DwellConfig) {
+    if let Some(path) = current_theme(st, config).and_then(|theme| theme.file(tone)) {
+        play(st, &path);
+    } else if !config.sound_file.is_empty() {
+        play(st, Path::new(&config.sound_file));
+    } else if let Some(path) = tone_file(st, tone, config.sound_pitch, config.sound_duration) {
+        play(st, &path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn tone_files_differ_by_pitch_and_duration() {
+        let tone = Tone::Click(ClickType::Left);
+        let ms = Duration::from_millis;
+        let base = tone_file_name(tone, 880, ms(40));
+        assert_ne!(base, tone_file_name(tone, 440, ms(40)));
+        assert_ne!(base, tone_file_name(tone, 880, ms(80)));
+        assert_eq!(base, tone_file_name(tone, 880, ms(40)));
+    }
+
+    #[test]
+    fn wav_header_matches_samples() {
+        let samples = samples(Tone::Click(ClickType::Left), 880, Duration::from_millis(40));
+        assert_eq!(samples.len(), 1764);
+
+        let wav = wav(&samples);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize,
+            wav.len() - 8
+        );
+        assert_eq!(
+            u32::from_le_bytes(wav[40..44].try_into().unwrap()) as usize,
+            samples.len() * 2
+        );
+        // starts and ends quietly
+        assert_eq!(samples[0], 0);
+        assert!(samples[samples.len() - 1].abs() < 100);
+    }
+
+    #[test]
+    fn selected_tone_ends_with_the_click() {
+        let duration = Duration::from_millis(40);
+        for click in [ClickType::Left, ClickType::Right, ClickType::Double] {
+            let selected = samples(Tone::Selected(click), 880, duration);
+            let tone = samples(Tone::Click(click), 880, duration);
+            assert!(selected.ends_with(&tone));
+            assert!(selected.len() > tone.len() + 1764);
+        }
+    }
+
+    #[test]
+    fn themes_find_event_files() {
+        let dir = std::env::temp_dir().join(format!("rtmouse-theme-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("left.oga"), "").unwrap();
+        fs::write(
+            dir.join(THEME_MANIFEST),
+            "drag-end = \"sounds/drop.wav\"\nright = 3\n",
+        )
+        .unwrap();
+
+        let theme = load_theme(dir.to_str().unwrap()).unwrap();
+        assert_eq!(
+            theme.file(Tone::Click(ClickType::Left)),
+            Some(dir.join("left.oga"))
+        );
+        assert_eq!(theme.file(Tone::DragEnd), Some(dir.join("sounds/drop.wav")));
+        assert_eq!(theme.file(Tone::Click(ClickType::Right)), None);
+
+        fs::remove_file(dir.join(THEME_MANIFEST)).unwrap();
+        assert!(load_theme(dir.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,317 @@
+// Usage counters: clicks of each type, drags, and dwells the user gave up on
+// by moving away when they were most of the way there. Kept per day in
+// paths::stats_file() so trends survive restarts, to help with tuning
+// dwell_time. Days are local, so "today" starts over at the user's midnight,
+// not UTC's.
+
+use crate::dwell::{ClickType, Decision};
+use crate::paths;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub left: u64,
+    pub right: u64,
+    pub double: u64,
+    pub drags: u64,
+    pub aborted: u64,
+}
+
+impl Counts {
+    // The ones that went through, drags included
+    pub fn clicks(&self) -> u64 {
+        self.left + self.right + self.double + self.drags
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    // Oldest first, "YYYY-MM-DD"
+    pub days: Vec<(String, Counts)>,
+    // Changed since the last save
+    pub dirty: bool,
+}
+
+// A year is plenty for spotting trends
+const MAX_DAYS: usize = 366;
+
+const HEADER: &str = "# rtmouse usage per day (local time): date left right double drags aborted\n";
+
+// Moving on after at least this fraction of dwell_time counts as giving up
+// on the click, not just passing through
+const ABORT_FRACTION: u32 = 2;
+
+pub fn date(t: SystemTime) -> String {
+    let days = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or(0) as i64;
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+// Seconds east of UTC at t, by the TZ rules libc knows
+fn utc_offset(t: SystemTime) -> i64 {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&secs, &mut tm).is_null() {
+            return 0;
+        }
+        tm.tm_gmtoff as i64
+    }
+}
+
+// Like date, but the day it is where the user is
+pub fn local_date(t: SystemTime) -> String {
+    let offset = utc_offset(t);
+    let shift = Duration::from_secs(offset.unsigned_abs());
+    date(if offset >= 0 {
+        t + shift
+    } else {
+        t.checked_sub(shift).unwrap_or(UNIX_EPOCH)
+    })
+}
+
+// Something worth counting, or writing to the history log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    // Click(ClickType::Drag) is a drag starting
+    Click(ClickType),
+    DragEnd,
+    Aborted,
+}
+
+impl Event {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Click(click) => click.as_str(),
+            Event::DragEnd => "drag-end",
+            Event::Aborted => "aborted",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Event> {
+        match s {
+            "drag-end" => Some(Event::DragEnd),
+            "aborted" => Some(Event::Aborted),
+            click => ClickType::parse(click).map(Event::Click),
+        }
+    }
+}
+
+// What the step from one decision to the next amounts to
+pub fn event(previous: &Decision, current: &Decision) -> Option<Event> {
+    if previous == current {
+        return None;
+    }
+    match (previous, current) {
+        (_, Decision::Clicked(click)) => Some(Event::Click(*click)),
+        (_, Decision::DragStarted) => Some(Event::Click(ClickType::Drag)),
+        (_, Decision::DragReleased) => Some(Event::DragEnd),
+        (Decision::Idle { idle, dwell_time }, Decision::Moving { .. })
+            if *idle >= *dwell_time / ABORT_FRACTION =>
+        {
+            Some(Event::Aborted)
+        }
+        _ => None,
+    }
+}
+
+pub fn today(stats: &Stats, now: SystemTime) -> Counts {
+    match stats.days.last() {
+        Some((day, counts)) if *day == local_date(now) => *counts,
+        _ => Counts::default(),
+    }
+}
+
+pub fn count(stats: &mut Stats, event: Event, now: SystemTime) {
+    let bump: fn(&mut Counts) = match event {
+        Event::Click(ClickType::Left) => |c| c.left += 1,
+        Event::Click(ClickType::Right) => |c| c.right += 1,
+        Event::Click(ClickType::Double) => |c| c.double += 1,
+        Event::Click(ClickType::Drag) => |c| c.drags += 1,
+        Event::Aborted => |c| c.aborted += 1,
+        // What a gesture did isn't known here, and the file has no column
+        // for them
+        Event::Click(ClickType::Gesture) | Event::DragEnd => return,
+    };
+
+    let today = local_date(now);
+    match stats.days.last_mut() {
+        Some((day, counts)) if *day == today => bump(counts),
+        _ => {
+            let mut counts = Counts::default();
+            bump(&mut counts);
+            stats.days.push((today, counts));
+            if stats.days.len() > MAX_DAYS {
+                stats.days.remove(0);
+            }
+        }
+    }
+    stats.dirty = true;
+}
+
+pub fn to_text(stats: &Stats) -> String {
+    let mut out = HEADER.to_string();
+    for (day, c) in &stats.days {
+        let _ = writeln!(
+            out,
+            "{} {} {} {} {} {}",
+            day, c.left, c.right, c.double, c.drags, c.aborted
+        );
+    }
+    out
+}
+
+// Lines that don't make sense are dropped, losing some history is better
+// than refusing to start
+pub fn parse(text: &str) -> Stats {
+    let days = text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let day = words.next()?.to_string();
+            let mut n = || words.next()?.parse().ok();
+            let counts = Counts {
+                left: n()?,
+                right: n()?,
+                double: n()?,
+                drags: n()?,
+                aborted: n()?,
+            };
+            Some((day, counts))
+        })
+        .collect();
+    Stats { days, dirty: false }
+}
+
+pub fn load_from(path: &Path) -> io::Result<Stats> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(parse(&text)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Stats::default()),
+        Err(e) => Err(e),
+    }
+}
+
+// Written next to the old file and renamed over it, so a crash mid write
+// can't lose the history
+pub fn save_to(stats: &mut Stats, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, to_text(stats))?;
+    fs::rename(&tmp, path)?;
+    stats.dirty = false;
+    Ok(())
+}
+
+pub fn load() -> Stats {
+    let path = match paths::stats_file() {
+        Some(path) => path,
+        None => return Stats::default(),
+    };
+    load_from(&path).unwrap_or_else(|e| {
+        eprintln!("Error: could not read {}: {}", path.display(), e);
+        Stats::default()
+    })
+}
+
+pub fn save(stats: &mut Stats) {
+    if let Some(path) = paths::stats_file() {
+        if let Err(e) = save_to(stats, &path) {
+            eprintln!("Error: could not write {}: {}", path.display(), e);
+            // Don't retry on every tick
+            stats.dirty = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(UNIX_EPOCH), "1970-01-01");
+        assert_eq!(
+            date(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "2023-11-14"
+        );
+        assert_eq!(
+            date(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29"
+        );
+    }
+
+    #[test]
+    fn counts_and_round_trips() {
+        let idle = |ms| Decision::Idle {
+            idle: Duration::from_millis(ms),
+            dwell_time: Duration::from_millis(500),
+        };
+        let moving = Decision::Moving { dx: 20, dy: 0 };
+        // Local noon, whatever TZ the tests run in
+        let day = |n: u64| {
+            let noon = 86400 * n as i64 + 43200;
+            let t = UNIX_EPOCH + Duration::from_secs(noon as u64);
+            UNIX_EPOCH + Duration::from_secs((noon - utc_offset(t)) as u64)
+        };
+
+        assert_eq!(
+            event(&idle(500), &Decision::DragStarted),
+            Some(Event::Click(ClickType::Drag))
+        );
+        assert_eq!(event(&idle(300), &moving), Some(Event::Aborted));
+        // Just passing through
+        assert_eq!(event(&idle(100), &moving), None);
+
+        let mut stats = Stats::default();
+        count(&mut stats, Event::Click(ClickType::Left), day(20739));
+        count(&mut stats, Event::Click(ClickType::Drag), day(20740));
+        count(&mut stats, Event::DragEnd, day(20740));
+        count(&mut stats, Event::Aborted, day(20740));
+        assert!(stats.dirty);
+
+        assert_eq!(
+            stats.days[1],
+            (
+                "2026-10-14".to_string(),
+                Counts {
+                    drags: 1,
+                    aborted: 1,
+                    ..Counts::default()
+                }
+            )
+        );
+        assert_eq!(today(&stats, day(20740)).clicks(), 1);
+        assert_eq!(today(&stats, day(20741)), Counts::default());
+
+        let text = to_text(&stats);
+        assert_eq!(
+            text,
+            format!("{}2026-10-13 1 0 0 0 0\n2026-10-14 0 0 0 1 1\n", HEADER)
+        );
+        stats.dirty = false;
+        assert_eq!(parse(&format!("{}garbage\n", text)), stats);
+    }
+}
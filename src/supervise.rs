@@ -0,0 +1,155 @@
+// `--supervise`: a small parent that starts rtmouse again when it crashes.
+// Someone who can only dwell can't get to a terminal to restart a clicker
+// that died under them.
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use std::ffi::OsString;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{exit, Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// How long rtmouse gets to let go of the button and put things back after
+// a SIGTERM, before it's killed
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+// Running this long means it wasn't crashing right away, so the backoff
+// starts over
+const STABLE_RUN: Duration = Duration::from_secs(300);
+
+// Exit codes 1 and 2 are rtmouse refusing to start, with a message saying
+// why (a bad config, another one running), and asking again won't help.
+// Being stopped on purpose isn't a crash either. Panics, other exit codes
+// and any other signal are.
+pub fn should_restart(status: ExitStatus) -> bool {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => !(0..=2).contains(&code),
+        (None, Some(signal)) => !matches!(signal, SIGHUP | SIGINT | SIGTERM),
+        (None, None) => false,
+    }
+}
+
+// 1s after the first crash in a row, doubling up to a minute
+pub fn backoff(crashes_in_a_row: u32) -> Duration {
+    let secs = 1u64 << crashes_in_a_row.saturating_sub(1).min(6);
+    Duration::from_secs(secs).min(MAX_BACKOFF)
+}
+
+// SIGTERM first so rtmouse shuts down on its own, and only SIGKILL if it
+// doesn't in time
+fn stop_child(child: &mut Child) {
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, SIGTERM);
+    }
+    let until = Instant::now() + STOP_GRACE;
+    while Instant::now() < until {
+        match child.try_wait() {
+            Ok(None) => sleep(Duration::from_millis(50)),
+            _ => return,
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// Runs this same executable with args until it exits in a way that
+// shouldn't be restarted, then exits the same way.
+pub fn run(args: Vec<OsString>) -> ! {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("Error: --supervise can't find its own executable: {}", e);
+            exit(1);
+        }
+    };
+
+    // Stopping the supervisor stops rtmouse too. A ctrl-c reaches both
+    // anyway, kill and systemd may only tell this one.
+    let stop = Arc::new(AtomicBool::new(false));
+    for &signal in &[SIGHUP, SIGINT, SIGTERM] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(&stop)) {
+            eprintln!("Error: --supervise can't catch signal {}: {}", signal, e);
+        }
+    }
+
+    let mut crashes = 0;
+    let mut crashes_in_a_row = 0;
+    loop {
+        let started = Instant::now();
+        let mut child = match Command::new(&exe).args(&args).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Error: --supervise can't start {}: {}", exe.display(), e);
+                exit(1);
+            }
+        };
+
+        let status = loop {
+            if stop.load(Ordering::Relaxed) {
+                stop_child(&mut child);
+                exit(0);
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) => sleep(Duration::from_millis(100)),
+                Err(e) => {
+                    eprintln!("Error: --supervise lost track of rtmouse: {}", e);
+                    exit(1);
+                }
+            }
+        };
+
+        if !should_restart(status) {
+            exit(status.code().unwrap_or(0));
+        }
+        crashes += 1;
+        crashes_in_a_row = if started.elapsed() >= STABLE_RUN {
+            1
+        } else {
+            crashes_in_a_row + 1
+        };
+        let wait = backoff(crashes_in_a_row);
+        eprintln!(
+            "rtmouse crashed ({}), {} crash{} so far. starting it again in {}s",
+            status,
+            crashes,
+            if crashes == 1 { "" } else { "es" },
+            wait.as_secs()
+        );
+
+        let until = Instant::now() + wait;
+        while Instant::now() < until {
+            if stop.load(Ordering::Relaxed) {
+                exit(0);
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restarts_only_crashes() {
+        // Raw wait statuses: the exit code in the second byte, or the signal
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        assert!(!should_restart(exited(0)));
+        assert!(!should_restart(exited(1)));
+        assert!(should_restart(exited(101)));
+        assert!(!should_restart(ExitStatus::from_raw(SIGTERM)));
+        assert!(should_restart(ExitStatus::from_raw(11)));
+    }
+
+    #[test]
+    fn backoff_doubles_to_a_minute() {
+        let secs: Vec<u64> = (1..=9).map(|n| backoff(n).as_secs()).collect();
+        assert_eq!(secs, [1, 2, 4, 8, 16, 32, 60, 60, 60]);
+        assert_eq!(backoff(0).as_secs(), 1);
+    }
+}
@@ -0,0 +1,45 @@
+// The config file and control socket parsers take whatever they're given,
+// so random input has to come back as an error, never a panic.
+
+use proptest::prelude::*;
+use rtmouse_rs::config::{self, DwellConfig};
+use rtmouse_rs::control::Request;
+
+// Lines that look enough like the config file to get past the first checks
+fn config_line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[a-z_]{1,24} = [0-9a-z\"\\[\\], .-]{0,16}",
+        "\\[[a-z_.\"]{0,16}\\]",
+        "#.{0,16}",
+        ".{0,32}",
+    ]
+}
+
+proptest! {
+    #[test]
+    fn config_never_panics(text in ".{0,200}") {
+        let _ = config::from_str(&text);
+    }
+
+    #[test]
+    fn config_lines_never_panic(lines in prop::collection::vec(config_line(), 0..20)) {
+        let _ = config::from_str(&lines.join("\n"));
+    }
+
+    #[test]
+    fn control_lines_never_panic(line in ".{0,64}") {
+        let _ = Request::parse(&line);
+    }
+
+    #[test]
+    fn client_messages_never_panic(longs in prop::collection::vec(any::<i64>(), 0..5)) {
+        let longs: Vec<std::os::raw::c_long> = longs.iter().map(|&l| l as _).collect();
+        let _ = Request::from_client_message(&longs);
+    }
+}
+
+#[test]
+fn written_config_reads_back() {
+    let text = config::to_toml(&DwellConfig::default(), true);
+    assert!(config::from_str(&text).is_ok());
+}
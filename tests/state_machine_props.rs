@@ -0,0 +1,142 @@
+// Invariants of the dwell state machine over randomized input sequences.
+
+use proptest::prelude::*;
+use rtmouse_rs::config::DwellConfig;
+use rtmouse_rs::dwell::{Command, DwellStateMachine, Sample};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug)]
+struct Step {
+    dt_ms: u64,
+    dx: i32,
+    dy: i32,
+    buttons: u64,
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    (
+        0u64..300,
+        // mostly still or jittering, sometimes a real move
+        prop_oneof![3 => Just(0), 2 => -3i32..=3, 1 => -60i32..=60],
+        prop_oneof![3 => Just(0), 2 => -3i32..=3, 1 => -60i32..=60],
+        prop_oneof![4 => Just(0u64), 1 => 1u64..16],
+    )
+        .prop_map(|(dt_ms, dx, dy, buttons)| Step {
+            dt_ms,
+            dx,
+            dy,
+            buttons,
+        })
+}
+
+fn config() -> impl Strategy<Value = DwellConfig> {
+    (any::<bool>(), 100u64..1000, 100u64..1000, 1u32..20).prop_map(
+        |(drag_enabled, dwell_ms, drag_ms, min_movement_pixels)| DwellConfig {
+            drag_enabled,
+            dwell_time: Duration::from_millis(dwell_ms),
+            drag_time: Duration::from_millis(drag_ms),
+            min_movement_pixels,
+            ..DwellConfig::default()
+        },
+    )
+}
+
+// Runs the steps and hands every (sample, output, moving-after) triple to f.
+fn run<F>(config: DwellConfig, steps: &[Step], mut f: F) -> DwellStateMachine
+where
+    F: FnMut(&Sample, &[Command], bool),
+{
+    let mut sm = DwellStateMachine::new(config);
+    sm.set_active(true);
+
+    let mut now = Instant::now();
+    let (mut x, mut y) = (500, 500);
+    for s in steps {
+        now += Duration::from_millis(s.dt_ms);
+        x += s.dx;
+        y += s.dy;
+        let sample = Sample {
+            x,
+            y,
+            buttons: s.buttons,
+            now,
+            unfocused: false,
+            grabbed: false,
+        };
+        let out = sm.update(sample);
+        f(&sample, &out, sm.is_moving());
+    }
+    sm
+}
+
+proptest! {
+    #[test]
+    fn press_and_release_stay_balanced(config in config(), steps in prop::collection::vec(step(), 0..200)) {
+        let mut held = false;
+        let mut sm = run(config, &steps, |_, out, _| {
+            for cmd in out {
+                match cmd {
+                    Command::Press => {
+                        assert!(!held, "press while already pressed");
+                        held = true;
+                    }
+                    Command::Release => {
+                        assert!(held, "release without press");
+                        held = false;
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        // Resting long enough always lets go of a drag.
+        if held {
+            let out = sm.update(Sample {
+                x: 0,
+                y: 0,
+                buttons: 0,
+                now: Instant::now() + Duration::from_secs(3600),
+                unfocused: false,
+                grabbed: false,
+            });
+            let out2 = sm.update(Sample {
+                x: 0,
+                y: 0,
+                buttons: 0,
+                now: Instant::now() + Duration::from_secs(7200),
+                unfocused: false,
+                grabbed: false,
+            });
+            prop_assert!(out.contains(&Command::Release) || out2.contains(&Command::Release));
+        }
+
+        // And so does shutting down, for any state.
+        let mut sm = run(DwellConfig::default(), &steps, |_, _, _| {});
+        let dragging = sm.is_dragging();
+        prop_assert_eq!(sm.shutdown().contains(&Command::Release), dragging);
+    }
+
+    #[test]
+    fn no_click_while_inhibited(config in config(), steps in prop::collection::vec(step(), 0..200)) {
+        run(config, &steps, |sample, out, _| {
+            if sample.buttons != 0 {
+                assert!(!out.contains(&Command::Press), "clicked while a button was held");
+            }
+        });
+    }
+
+    #[test]
+    fn clicks_never_fire_early(config in config(), steps in prop::collection::vec(step(), 0..200)) {
+        let dwell_time = config.dwell_time;
+        let mut last_motion: Option<Instant> = None;
+        run(config, &steps, |sample, out, moving| {
+            if moving {
+                last_motion = Some(sample.now);
+            }
+            if out.contains(&Command::Press) {
+                let since = last_motion.expect("clicked before the pointer ever moved");
+                assert!(sample.now - since >= dwell_time, "dwell fired early");
+            }
+        });
+    }
+}